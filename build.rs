@@ -0,0 +1,29 @@
+//! Precompile the syntaxes and themes, so loading them at startup is just deserializing.
+
+use std::{env, path::Path};
+use syntect::{dumps, highlighting::ThemeSet, parsing::SyntaxSet};
+
+fn main() {
+    let out_dir = env::var("OUT_DIR").expect("OUT_DIR is set by cargo");
+    let out_dir = Path::new(&out_dir);
+    let syntaxes = Path::new("assets/syntaxes");
+    let themes = Path::new("assets/themes");
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed={}", syntaxes.display());
+    println!("cargo:rerun-if-changed={}", themes.display());
+
+    let mut builder = SyntaxSet::load_defaults_newlines().into_builder();
+    if syntaxes.is_dir() {
+        builder
+            .add_from_folder(syntaxes, true)
+            .expect("bundled syntaxes are valid");
+    }
+    dumps::dump_to_uncompressed_file(&builder.build(), out_dir.join("syntaxes.packdump"))
+        .expect("can write the syntax dump");
+
+    let mut ts = ThemeSet::load_defaults();
+    if themes.is_dir() {
+        ts.add_from_folder(themes).expect("bundled themes are valid");
+    }
+    dumps::dump_to_file(&ts, out_dir.join("themes.themedump")).expect("can write the theme dump");
+}
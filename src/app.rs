@@ -1,136 +1,908 @@
-use anyhow::Result;
-use egui::{Color32, TextEdit, Vec2, ScrollArea, TextStyle};
-use log::info;
+use crate::{
+    delimited,
+    editor::{Editor, EditorView},
+    encoding::{Encoding, LineEnding},
+    file_tree::{FileTree, TreeEvent},
+    format::{Format, ParseError},
+    gutter::Gutter,
+    line_buffer::Position,
+    line_index::LineIndex,
+    markdown_preview::{self, MarkdownPreview},
+    multi_cursor::Selection,
+    recent,
+    settings::{
+        LegacySettings, Settings, SettingsFile, ThemeMode, DEFAULT_FONT_SIZE, EDITOR_FONT,
+        EDITOR_FONT_BOLD, EDITOR_FONT_BOLD_ITALIC, EDITOR_FONT_ITALIC, MAX_FONT_SIZE,
+        MIN_FONT_SIZE,
+    },
+    syntax_highlighting::{self, CodeTheme, ColumnMode, Columns, HighlightCache},
+    system_theme,
+    table_view::{self, TableView},
+    theme_editor::ThemeEditor,
+    tree_view::{Tree, TreeAction, TreeView},
+    whole_file::{LineOffsets, WholeFile},
+    wrap::WrapMode,
+};
+use anyhow::{anyhow, bail, Result};
+use log::{info, warn};
 use positioned_io::{RandomAccessFile, ReadAt};
 use std::io::Read;
 use std::{
-    collections::{BTreeSet, HashMap},
+    collections::HashMap,
     fs::{metadata, File},
     path::{Path, PathBuf},
+    time::Instant,
 };
 
-#[derive(serde::Deserialize, serde::Serialize, Default)]
-struct Settings {
-    line_numbers: bool,
-    tree_view: bool,
-    recent_files: BTreeSet<PathBuf>,
-    editor_font_size: f32,
-}
-
 /// We derive Deserialize/Serialize so we can persist app state on shutdown.
-#[derive(serde::Deserialize, serde::Serialize)]
+#[derive(serde::Deserialize, serde::Serialize, Default)]
 #[serde(default)] // if we add new fields, give them default values when deserializing old state
 pub struct MicronApp {
     #[serde(skip)]
     open_files: HashMap<PathBuf, OpenedFile>,
     active_file: Option<PathBuf>,
+    /// Settings used to live here, they are in the settings file now
+    #[serde(rename = "settings", skip_serializing, deserialize_with = "some")]
+    legacy_settings: Option<LegacySettings>,
+    #[serde(skip)]
     settings: Settings,
+    #[serde(skip)]
+    settings_file: SettingsFile,
+    #[serde(skip)]
+    show_preferences: bool,
+    /// The editor font currently installed in egui
+    #[serde(skip)]
+    installed_font: Option<Option<PathBuf>>,
+    #[serde(skip)]
+    file_tree: FileTree,
+    /// Search text of the language picker, while it is open
+    #[serde(skip)]
+    language_picker: Option<String>,
+    /// When `new` was called, until the first frame is logged
+    #[serde(skip)]
+    started: Option<Instant>,
+    #[serde(skip)]
+    theme_editor: Option<ThemeEditor>,
+    /// The go to line or offset window, while it is open
+    #[serde(skip)]
+    go_to: Option<GoToPicker>,
+    /// Why formatting a file failed, while the window about it is open
+    #[serde(skip)]
+    format_error: Option<FormatError>,
+    /// Formatted text of a file that can't be changed, until the user decides to open it
+    #[serde(skip)]
+    formatted: Option<Formatted>,
+    /// A large file being formatted
+    #[serde(skip)]
+    formatting: Option<Formatting>,
 }
 
-impl Default for MicronApp {
-    fn default() -> Self {
+/// Shift+Alt+F, as in other editors
+const FORMAT_DOCUMENT: egui::KeyboardShortcut = egui::KeyboardShortcut::new(
+    egui::Modifiers {
+        alt: true,
+        shift: true,
+        ..egui::Modifiers::NONE
+    },
+    egui::Key::F,
+);
+
+struct FormatError {
+    path: PathBuf,
+    format: Format,
+    error: ParseError,
+    /// Byte offset of the line with the error, in large files
+    line_offset: Option<u64>,
+}
+
+struct Formatted {
+    /// Where the text came from
+    source: PathBuf,
+    /// Name of the new tab
+    path: PathBuf,
+    text: String,
+    encoding: Encoding,
+}
+
+/// A large file being read and formatted in the background.
+struct Formatting {
+    path: PathBuf,
+    format: Format,
+    minify: bool,
+    encoding: Encoding,
+    /// The formatted text, or the parse error and the byte offset of its line
+    task: WholeFile<Result<String, (ParseError, u64)>>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum GoTo {
+    Line,
+    Offset,
+}
+
+struct GoToPicker {
+    target: GoTo,
+    input: String,
+    error: Option<String>,
+}
+
+impl GoToPicker {
+    fn new(target: GoTo) -> Self {
         Self {
-            open_files: Default::default(),
-            active_file: Default::default(),
-            settings: Default::default(),
+            target,
+            input: String::new(),
+            error: None,
         }
     }
 }
 
+fn some<'de, D: serde::Deserializer<'de>, T: serde::Deserialize<'de>>(
+    deserializer: D,
+) -> Result<Option<T>, D::Error> {
+    T::deserialize(deserializer).map(Some)
+}
+
 impl MicronApp {
     /// Called once before the first frame.
     pub fn new(cc: &eframe::CreationContext<'_>) -> Self {
-        let mut fonts = egui::FontDefinitions::default();
+        let start = Instant::now();
+        syntax_highlighting::preload();
+        system_theme::watch(&cc.egui_ctx);
 
-        // Install my own font (maybe supporting non-latin characters):
-        fonts.font_data.insert(
-            "mono".to_owned(),
-            egui::FontData::from_static(include_bytes!("../assets/FiraCode-Regular.ttf")),
-        ); // .ttf and .otf supported
+        let mut app: Self = cc
+            .storage
+            .and_then(|storage| eframe::get_value(storage, eframe::APP_KEY))
+            .unwrap_or_default();
 
-        fonts.font_data.insert(
-            "sans".to_owned(),
-            egui::FontData::from_static(include_bytes!("../assets/FiraCode-Regular.ttf")),
-        ); // .ttf and .otf supported
+        app.settings_file = SettingsFile::new();
+        if let Some(settings) = app.settings_file.load() {
+            app.settings = settings;
+        } else if let Some(legacy) = app.legacy_settings.take() {
+            match legacy.migrate() {
+                Ok(settings) => app.settings = settings,
+                Err(e) => warn!("Can't migrate old settings: {e}"),
+            }
+        }
+        app.settings.recent_files.prune();
+        info!("App state loaded in {:?}", start.elapsed());
+        app.started = Some(start);
+        app
+    }
 
-        // Put my font first (highest priority):
-        fonts
-            .families
-            .get_mut(&egui::FontFamily::Proportional)
-            .unwrap()
-            .insert(0, "sans".to_owned());
+    /// Pick up external edits of the settings file and persist our own changes.
+    fn sync_settings(&mut self, ctx: &egui::Context) {
+        if let Some(settings) = self.settings_file.poll(ctx) {
+            self.settings = settings;
+        }
+        self.settings_file.store(&self.settings);
 
-        // Put my font as last fallback for monospace:
-        fonts
-            .families
-            .get_mut(&egui::FontFamily::Monospace)
-            .unwrap()
-            .insert(0, "mono".to_owned());
+        if self.installed_font.as_ref() != Some(&self.settings.editor_font) {
+            setup_fonts(ctx, self.settings.editor_font.as_deref());
+            self.installed_font = Some(self.settings.editor_font.clone());
+        }
+    }
+
+    fn status_bar(&mut self, ui: &mut egui::Ui) {
+        let Some(opened_file) = self
+            .active_file
+            .as_ref()
+            .and_then(|p| self.open_files.get_mut(p))
+        else {
+            return;
+        };
+        ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+            let language = match opened_file.highlight.syntax_name() {
+                None => "…",
+                Some(None) => "Plain Text",
+                Some(Some(name)) => name,
+            };
+            if ui
+                .button(language)
+                .on_hover_text("Select language mode")
+                .clicked()
+            {
+                self.language_picker = Some(String::new());
+            }
 
-        cc.egui_ctx.set_fonts(fonts);
+            let ending = opened_file.status.line_ending.unwrap_or(LineEnding::Lf);
+            ui.add_enabled_ui(!opened_file.read_only, |ui| {
+                ui.menu_button(ending.name(), |ui| {
+                    for target in LineEnding::ALL {
+                        if ui
+                            .selectable_label(ending == target, target.name())
+                            .clicked()
+                        {
+                            opened_file.editor().set_line_ending(target);
+                            ui.close_menu();
+                        }
+                    }
+                })
+                .response
+                .on_hover_text("Convert line endings")
+                .on_disabled_hover_text("The file is read-only");
+            });
 
-        if let Some(storage) = cc.storage {
-            return eframe::get_value(storage, eframe::APP_KEY).unwrap_or_default();
+            ui.menu_button(opened_file.encoding.name(), |ui| {
+                ui.weak("Reopen with encoding");
+                for encoding in Encoding::ALL {
+                    if ui
+                        .selectable_label(opened_file.encoding == encoding, encoding.name())
+                        .clicked()
+                    {
+                        // Decode the bytes of the current text
+                        opened_file.sync_buffer();
+                        opened_file.encoding = encoding;
+                        opened_file.editor = None;
+                        opened_file.buffer_version = None;
+                        if opened_file.partial {
+                            // Newlines are encoded differently, so count them again
+                            opened_file.line_index = Some(LineIndex::build(
+                                &opened_file.path,
+                                opened_file.len,
+                                encoding,
+                            ));
+                            opened_file.first_line = None;
+                        }
+                        ui.close_menu();
+                    }
+                }
+            })
+            .response
+            .on_hover_text("Select encoding");
+
+            let read_only = opened_file.read_only;
+            if ui
+                .add_enabled(
+                    !opened_file.partial,
+                    egui::SelectableLabel::new(
+                        read_only,
+                        if read_only { "Read-only" } else { "Editable" },
+                    ),
+                )
+                .on_hover_text("Toggle read-only")
+                .on_disabled_hover_text("Large files are always read-only")
+                .clicked()
+            {
+                opened_file.read_only = !read_only;
+            }
+
+            let size = if opened_file.partial {
+                opened_file.len
+            } else {
+                opened_file.status.size
+            };
+            ui.label(format_size(size));
+
+            if opened_file.partial {
+                match opened_file
+                    .line_index
+                    .as_ref()
+                    .and_then(LineIndex::total_lines)
+                {
+                    Some(lines) => ui.label(plural(lines, "line")),
+                    None => ui.weak("indexing…"),
+                };
+            } else {
+                ui.label(plural(opened_file.status.lines as u64, "line"));
+            }
+
+            let long_lines = opened_file.status.long_lines;
+            if long_lines > 0 {
+                ui.weak(format!("{} too long", plural(long_lines as u64, "line")))
+                    .on_hover_text(format!(
+                        "Lines longer than {} chars are shown in chunks, and lines longer than {} \
+                         bytes are not highlighted. Both limits can be changed in the settings.",
+                        self.settings.max_line_columns,
+                        format_size(self.settings.max_highlight_len as u64),
+                    ));
+            }
+
+            if let Some((chars, lines, bytes)) = opened_file.status.selection {
+                ui.label(format!(
+                    "{} selected ({}, {})",
+                    plural(chars as u64, "char"),
+                    plural(lines as u64, "line"),
+                    plural(bytes as u64, "byte"),
+                ));
+            }
+
+            if opened_file.partial
+                && ui
+                    .button(format!("Offset {}", opened_file.cursor))
+                    .on_hover_text("Go to offset")
+                    .clicked()
+            {
+                self.go_to = Some(GoToPicker::new(GoTo::Offset));
+            }
+
+            if let Some(column) = &opened_file.status.column {
+                ui.label(column).on_hover_text("Column at the caret");
+            }
+
+            let (line, column) = opened_file.status.cursor;
+            let first_line = opened_file.first_line.unwrap_or_default();
+            let position = format!("Ln {}, Col {}", first_line + line as u64 + 1, column + 1);
+            if ui.button(position).on_hover_text("Go to line").clicked() {
+                self.go_to = Some(GoToPicker::new(GoTo::Line));
+            }
+        });
+    }
+
+    fn go_to_picker(&mut self, ctx: &egui::Context) {
+        let (Some(picker), Some(opened_file)) = (
+            &mut self.go_to,
+            self.active_file
+                .as_ref()
+                .and_then(|p| self.open_files.get_mut(p)),
+        ) else {
+            return;
+        };
+        let (title, hint) = match picker.target {
+            GoTo::Line => ("Go to line", "line[:column]"),
+            GoTo::Offset => ("Go to offset", "byte offset"),
+        };
+        let mut open = true;
+        let mut done = false;
+        egui::Window::new(title)
+            .open(&mut open)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                let response =
+                    ui.add(egui::TextEdit::singleline(&mut picker.input).hint_text(hint));
+                response.request_focus();
+                if ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                    let result = match picker.target {
+                        GoTo::Line => opened_file.go_to_line(&picker.input),
+                        GoTo::Offset => opened_file.go_to_offset(&picker.input),
+                    };
+                    match result {
+                        Ok(()) => done = true,
+                        Err(e) => picker.error = Some(e.to_string()),
+                    }
+                }
+                if let Some(error) = &picker.error {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                }
+            });
+        if done || !open {
+            self.go_to = None;
+        }
+    }
+
+    /// Format or minify the active file. Read-only and large files stay as they are, their
+    /// formatted text can be opened in a new tab instead.
+    fn format_active(&mut self, minify: bool) {
+        let Some(opened_file) = self
+            .active_file
+            .as_ref()
+            .and_then(|p| self.open_files.get_mut(p))
+        else {
+            return;
+        };
+        let Some(format) = opened_file.format() else {
+            return;
+        };
+        if opened_file.partial {
+            let (path, encoding) = (opened_file.path.clone(), opened_file.encoding);
+            let task = WholeFile::spawn(
+                &path,
+                opened_file.len,
+                encoding,
+                "Formatting",
+                move |text| {
+                    format.apply(&text, minify).map_err(|error| {
+                        let line = error.pos.line;
+                        let offset = LineOffsets::new(&text, encoding, [line]).get(line);
+                        (error, offset.unwrap_or_default())
+                    })
+                },
+            );
+            self.formatting = Some(Formatting {
+                path,
+                format,
+                minify,
+                encoding,
+                task,
+            });
+            return;
+        }
+        let text = opened_file.editor().text();
+        match format.apply(&text, minify) {
+            Ok(formatted) if opened_file.read_only => {
+                self.formatted = Some(Formatted {
+                    source: opened_file.path.clone(),
+                    path: formatted_path(&opened_file.path, minify),
+                    text: formatted,
+                    encoding: opened_file.encoding,
+                });
+            }
+            Ok(formatted) => opened_file.replace_text(&formatted),
+            Err(error) => {
+                opened_file.editor().go_to(error.pos);
+                self.format_error = Some(FormatError {
+                    path: opened_file.path.clone(),
+                    format,
+                    error,
+                    line_offset: None,
+                });
+            }
+        }
+    }
+
+    /// The parse error of the last format command, and the offer to open formatted text that
+    /// couldn't replace the original.
+    fn format_windows(&mut self, ctx: &egui::Context) {
+        let done = self.formatting.as_mut().and_then(|f| f.task.poll());
+        if let Some(formatting) = &self.formatting {
+            let mut open = true;
+            egui::Window::new("Formatting")
+                .open(&mut open)
+                .collapsible(false)
+                .show(ctx, |ui| {
+                    ui.label(formatting.path.display().to_string());
+                    formatting.task.ui(ui);
+                });
+            if !open {
+                self.formatting = None;
+            }
+        }
+        if let (Some(done), Some(formatting)) = (done, &self.formatting) {
+            let path = formatting.path.clone();
+            let (format, minify, encoding) =
+                (formatting.format, formatting.minify, formatting.encoding);
+            self.formatting = None;
+            match done {
+                Ok(text) => {
+                    self.formatted = Some(Formatted {
+                        source: path.clone(),
+                        path: formatted_path(&path, minify),
+                        text,
+                        encoding,
+                    });
+                }
+                Err((error, offset)) => {
+                    if let Some(opened_file) = self.open_files.get_mut(&path) {
+                        opened_file
+                            .go_to_position(error.pos, Some(offset))
+                            .unwrap_or_default();
+                    }
+                    self.format_error = Some(FormatError {
+                        path,
+                        format,
+                        error,
+                        line_offset: Some(offset),
+                    });
+                }
+            }
+        }
+
+        if let Some(failure) = &self.format_error {
+            let mut open = true;
+            egui::Window::new(format!("Invalid {}", failure.format.name()))
+                .open(&mut open)
+                .collapsible(false)
+                .show(ctx, |ui| {
+                    ui.colored_label(ui.visuals().error_fg_color, failure.error.to_string());
+                    if ui.button("Go to error").clicked() {
+                        if let Some(opened_file) = self.open_files.get_mut(&failure.path) {
+                            self.active_file = Some(failure.path.clone());
+                            opened_file
+                                .go_to_position(failure.error.pos, failure.line_offset)
+                                .unwrap_or_default();
+                        }
+                    }
+                });
+            if !open {
+                self.format_error = None;
+            }
+        }
+
+        let Some(formatted) = &self.formatted else {
+            return;
+        };
+        let mut open = true;
+        let mut accepted = false;
+        let mut cancelled = false;
+        egui::Window::new("Open formatted document")
+            .open(&mut open)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                let reason = match self.open_files.get(&formatted.source) {
+                    Some(f) if f.partial => "is too large to edit",
+                    _ => "is read-only",
+                };
+                ui.label(format!(
+                    "{} {reason}. Open the formatted document in a new, unsaved tab instead?",
+                    formatted.source.display()
+                ));
+                ui.horizontal(|ui| {
+                    accepted = ui.button("Open in new tab").clicked();
+                    cancelled = ui.button("Cancel").clicked();
+                });
+            });
+        if accepted {
+            if let Some(formatted) = self.formatted.take() {
+                let of = OpenedFile::unsaved(
+                    formatted.path.clone(),
+                    &formatted.text,
+                    formatted.encoding,
+                );
+                self.open_files.insert(formatted.path.clone(), of);
+                self.active_file = Some(formatted.path);
+            }
+        }
+        if cancelled || !open {
+            self.formatted = None;
+        }
+    }
+
+    fn language_picker(&mut self, ctx: &egui::Context) {
+        let (Some(search), Some(path)) = (&mut self.language_picker, &self.active_file) else {
+            return;
+        };
+        let mut open = true;
+        let mut picked = None;
+        egui::Window::new("Select language mode")
+            .open(&mut open)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                picked = syntax_highlighting::syntax_picker(ui, search)
+            });
+        if picked.is_some() || !open {
+            self.language_picker = None;
+        }
+        match picked {
+            Some(Some(name)) => {
+                self.settings.language_overrides.insert(path.clone(), name);
+            }
+            Some(None) => {
+                self.settings.language_overrides.remove(path);
+            }
+            None => {}
+        }
+    }
+
+    /// Ctrl+plus/minus/0 and ctrl+scroll change the editor font size.
+    fn handle_zoom(&mut self, ctx: &egui::Context) {
+        use egui::gui_zoom::kb_shortcuts;
+
+        let mut size = self.settings.editor_font_id().size;
+        ctx.input_mut(|i| {
+            if i.consume_shortcut(&kb_shortcuts::ZOOM_RESET) {
+                size = DEFAULT_FONT_SIZE;
+            }
+            if i.consume_shortcut(&kb_shortcuts::ZOOM_IN) {
+                size += 1.0;
+            }
+            if i.consume_shortcut(&kb_shortcuts::ZOOM_OUT) {
+                size -= 1.0;
+            }
+            size *= i.zoom_delta();
+        });
+        self.settings.editor_font_size = size.clamp(MIN_FONT_SIZE, MAX_FONT_SIZE);
+    }
+
+    /// Open `path` and make it the active file.
+    fn open(&mut self, path: PathBuf) {
+        match read_file(&path) {
+            Ok(of) => {
+                self.active_file = Some(path.clone());
+                self.settings
+                    .recent_files
+                    .touch(&path, self.settings.recent_limit);
+                self.open_files.insert(path, of);
+            }
+            Err(e) => {
+                warn!("Can't open {}: {e}", path.display());
+                if !path.exists() {
+                    self.settings.recent_files.remove(&path);
+                }
+            }
+        }
+    }
+
+    fn handle_tree_event(&mut self, event: TreeEvent) {
+        match event {
+            TreeEvent::Open(path) => self.open(path),
+            TreeEvent::Renamed { from, to } => {
+                let renamed = self
+                    .open_files
+                    .keys()
+                    .filter(|p| p.starts_with(&from))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                for old in renamed {
+                    if let Some(mut of) = self.open_files.remove(&old) {
+                        let new = to.join(old.strip_prefix(&from).unwrap_or(&old));
+                        if self.active_file.as_ref() == Some(&old) {
+                            self.active_file = Some(new.clone());
+                        }
+                        of.path = new.clone();
+                        self.open_files.insert(new, of);
+                    }
+                }
+            }
+            TreeEvent::Deleted(path) => {
+                self.open_files.retain(|p, _| !p.starts_with(&path));
+                if self
+                    .active_file
+                    .as_ref()
+                    .is_some_and(|p| p.starts_with(&path))
+                {
+                    self.active_file = None;
+                }
+            }
         }
-        Default::default()
     }
 }
 
 impl eframe::App for MicronApp {
     fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        for opened_file in self.open_files.values_mut() {
+            opened_file.sync_buffer();
+        }
         eframe::set_value(storage, eframe::APP_KEY, self);
     }
 
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        if let Some(start) = self.started.take() {
+            info!("First frame {:?} after creating the app", start.elapsed());
+        }
+        self.handle_zoom(ctx);
+        self.sync_settings(ctx);
+        if ctx.input_mut(|i| i.consume_shortcut(&FORMAT_DOCUMENT)) {
+            self.format_active(false);
+        }
+        system_theme::set_active(self.settings.theme_mode == ThemeMode::System);
+        let dark = self.settings.is_dark(
+            system_theme::prefers_dark()
+                .or_else(|| _frame.info().system_theme.map(|t| t == eframe::Theme::Dark)),
+        );
+
         egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
             egui::menu::bar(ui, |ui| {
                 ui.menu_button("File", |ui| {
                     if ui.button("Quit").clicked() {
                         _frame.close();
                     }
+                    if ui.button("Preferences").clicked() {
+                        self.show_preferences = true;
+                        ui.close_menu();
+                    }
+                    if ui
+                        .button("Reload syntaxes and themes")
+                        .on_hover_text("From the syntaxes and themes folders in the config dir")
+                        .clicked()
+                    {
+                        syntax_highlighting::reload(ctx);
+                        ui.close_menu();
+                    }
                     if ui.button("Open").clicked() {
                         if let Some(p) = rfd::FileDialog::new().pick_file() {
-                            if let Ok(of) = read_file(&p) {
-                                self.active_file = Some(p.clone());
-                                self.settings.recent_files.insert(p.clone());
-                                self.open_files.insert(p, of);
-                            }
+                            self.open(p);
+                        }
+                        ui.close_menu();
+                    }
+                    if ui.button("Open Folder").clicked() {
+                        if let Some(p) = rfd::FileDialog::new().pick_folder() {
+                            self.settings.tree_root = Some(p);
+                            self.settings.tree_view = true;
+                            self.file_tree = Default::default();
                         }
                         ui.close_menu();
                     }
 
                     ui.menu_button("Recent", |ui| {
-                        for p in &self.settings.recent_files {
-                            if let Some(fname) = p.file_name() {
-                                if ui.button(fname.to_string_lossy().to_string()).clicked() {
-                                    if let Ok(of) = read_file(&p) {
-                                        self.active_file = Some(p.clone());
-                                        self.open_files.insert(p.clone(), of);
-                                    }
-
+                        let mut clicked = None;
+                        for (name, recent) in self.settings.recent_files.entries() {
+                            ui.horizontal(|ui| {
+                                if ui
+                                    .selectable_label(recent.pinned, "📌")
+                                    .on_hover_text(if recent.pinned { "Unpin" } else { "Pin" })
+                                    .clicked()
+                                {
+                                    self.settings.recent_files.toggle_pin(&recent.path);
+                                }
+                                if ui
+                                    .button(name)
+                                    .on_hover_text(format!(
+                                        "{}\nOpened {}",
+                                        recent.path.display(),
+                                        recent::age(recent.opened)
+                                    ))
+                                    .clicked()
+                                {
+                                    clicked = Some(recent.path);
                                     ui.close_menu();
                                 }
+                            });
+                        }
+                        if self.settings.recent_files.is_empty() {
+                            ui.weak("No recent files");
+                        } else {
+                            ui.separator();
+                            if ui.button("Clear recent").clicked() {
+                                self.settings.recent_files.clear();
+                                ui.close_menu();
                             }
                         }
+                        if let Some(p) = clicked {
+                            self.open(p);
+                        }
+                    });
+                });
+
+                ui.menu_button("Edit", |ui| {
+                    let format = self
+                        .active_file
+                        .as_ref()
+                        .and_then(|p| self.open_files.get(p))
+                        .and_then(OpenedFile::format);
+                    ui.add_enabled_ui(format.is_some(), |ui| {
+                        let hint = "Only JSON, XML, YAML and TOML can be formatted";
+                        if ui
+                            .add(
+                                egui::Button::new("Format Document")
+                                    .shortcut_text(ctx.format_shortcut(&FORMAT_DOCUMENT)),
+                            )
+                            .on_disabled_hover_text(hint)
+                            .clicked()
+                        {
+                            self.format_active(false);
+                            ui.close_menu();
+                        }
+                        if ui.button("Minify").on_disabled_hover_text(hint).clicked() {
+                            self.format_active(true);
+                            ui.close_menu();
+                        }
+                    });
+                });
+
+                ui.menu_button("View", |ui| {
+                    ui.checkbox(&mut self.settings.line_numbers, "Line numbers");
+                    ui.add_enabled(
+                        self.settings.line_numbers,
+                        egui::Checkbox::new(
+                            &mut self.settings.relative_line_numbers,
+                            "Relative line numbers",
+                        ),
+                    );
+                    ui.add_enabled(
+                        self.settings.tree_root.is_some(),
+                        egui::Checkbox::new(&mut self.settings.tree_view, "File tree"),
+                    );
+                    let active = self
+                        .active_file
+                        .as_ref()
+                        .and_then(|p| self.open_files.get_mut(p));
+                    ui.add_enabled_ui(active.is_some(), |ui| {
+                        let mut active = active;
+                        ui.menu_button("Word wrap", |ui| {
+                            if let Some(opened_file) = active.as_deref_mut() {
+                                opened_file.wrap.ui(ui);
+                            }
+                        });
+                        ui.menu_button("Columns", |ui| {
+                            if let Some(opened_file) = active.as_deref_mut() {
+                                opened_file.columns.ui(ui);
+                            }
+                        });
+                        let markdown = active.as_deref().is_some_and(OpenedFile::is_markdown);
+                        let mut preview = active.as_deref().is_some_and(|f| f.markdown_preview);
+                        if ui
+                            .add_enabled(
+                                markdown,
+                                egui::Checkbox::new(&mut preview, "Markdown preview"),
+                            )
+                            .on_disabled_hover_text("Only Markdown files have a preview")
+                            .changed()
+                        {
+                            if let Some(opened_file) = active.as_deref_mut() {
+                                opened_file.markdown_preview = preview;
+                            }
+                        }
+                        ui.menu_button("View as", |ui| {
+                            if let Some(opened_file) = active {
+                                opened_file.view_mode_ui(ui);
+                            }
+                        });
+                    });
+                    ui.menu_button("Ruler", |ui| self.settings.ruler_ui(ui));
+                    ui.separator();
+                    ui.horizontal(|ui| {
+                        ui.label("Font size");
+                        let mut size = self.settings.editor_font_id().size;
+                        if ui
+                            .add(
+                                egui::DragValue::new(&mut size)
+                                    .clamp_range(MIN_FONT_SIZE..=MAX_FONT_SIZE),
+                            )
+                            .changed()
+                        {
+                            self.settings.editor_font_size = size;
+                        }
+                    });
+                    if ui.button("Editor font…").clicked() {
+                        if let Some(p) = rfd::FileDialog::new()
+                            .add_filter("Fonts", &["ttf", "otf"])
+                            .pick_file()
+                        {
+                            self.settings.editor_font = Some(p);
+                        }
+                        ui.close_menu();
+                    }
+                    if self.settings.editor_font.is_some()
+                        && ui.button("Default editor font").clicked()
+                    {
+                        self.settings.editor_font = None;
+                        ui.close_menu();
+                    }
+                    ui.separator();
+                    ui.menu_button("Theme", |ui| {
+                        self.settings.theme_mode_ui(ui);
+                        ui.separator();
+                        syntax_highlighting::theme_radios(ui, self.settings.theme_mut(dark), dark);
+                        ui.separator();
+                        if ui.button("Edit theme…").clicked() {
+                            let current = self.settings.theme_mut(dark);
+                            self.theme_editor = Some(ThemeEditor::new(current));
+                            ui.close_menu();
+                        }
+                        if ui.button("Appearance settings…").clicked() {
+                            self.show_preferences = true;
+                            ui.close_menu();
+                        }
                     });
                 });
             });
         });
 
+        egui::Window::new("Preferences")
+            .open(&mut self.show_preferences)
+            .show(ctx, |ui| {
+                self.settings.ui(ui);
+                ui.separator();
+                self.settings_file.ui(ui);
+            });
+
+        if let Some(editor) = &mut self.theme_editor {
+            if !editor.ui(ctx, self.settings.theme_mut(dark)) {
+                self.theme_editor = None;
+            }
+        }
+        let syntax = self
+            .active_file
+            .as_ref()
+            .and_then(|f| self.open_files.get(f))
+            .and_then(|f| f.highlight.syntax_name().flatten());
+        self.settings.code_theme(dark, syntax).sync_dark_mode(ctx);
+
+        self.language_picker(ctx);
+        self.go_to_picker(ctx);
+        self.format_windows(ctx);
+
+        egui::TopBottomPanel::bottom("status_bar").show(ctx, |ui| self.status_bar(ui));
+
         egui::SidePanel::left("side_panel").show(ctx, |ui| {
-            let mut open_files = self.open_files.keys().collect::<Vec<_>>();
-            open_files.sort();
+            let mut open_files = self.open_files.iter().collect::<Vec<_>>();
+            open_files.sort_by_key(|(p, _)| *p);
 
             ui.vertical_centered_justified(|ui| {
-                for f in open_files {
+                for (f, of) in open_files {
+                    let mut name = f
+                        .file_name()
+                        .map(|f| f.to_string_lossy().to_string())
+                        .unwrap_or_default();
+                    if of.unsaved {
+                        name.push_str(" ●");
+                    }
                     if ui
-                        .button(format!(
-                            "{}",
-                            f.file_name()
-                                .map(|f| f.to_string_lossy().to_string())
-                                .unwrap_or_default()
-                        ))
-                        .on_hover_text(f.display().to_string())
+                        .button(name)
+                        .on_hover_text(if of.unsaved {
+                            format!("{} (unsaved)", f.display())
+                        } else {
+                            f.display().to_string()
+                        })
                         .clicked()
                     {
                         self.active_file = Some(f.clone());
@@ -140,97 +912,266 @@ impl eframe::App for MicronApp {
             if ui.button("Close all").clicked() {
                 self.open_files.clear();
             }
+
+            if let (true, Some(root)) = (self.settings.tree_view, self.settings.tree_root.clone()) {
+                ui.separator();
+                ui.horizontal(|ui| {
+                    if ui.small_button("⟳").on_hover_text("Refresh").clicked() {
+                        self.file_tree.refresh();
+                    }
+                    if let Some(active) = &self.active_file {
+                        if ui
+                            .small_button("◎")
+                            .on_hover_text("Reveal active file")
+                            .clicked()
+                        {
+                            self.file_tree.reveal(&root, active);
+                        }
+                    }
+                });
+                let events = egui::ScrollArea::vertical()
+                    .auto_shrink([false, false])
+                    .show(ui, |ui| {
+                        self.file_tree.ui(ui, &root, self.active_file.as_deref())
+                    })
+                    .inner;
+                for event in events {
+                    self.handle_tree_event(event);
+                }
+            }
         });
 
         egui::CentralPanel::default().show(ctx, |ui| {
+            let mut clicked_file = None;
             if let Some(opened_file) = self
                 .open_files
                 .get_mut(&(self.active_file.clone()).unwrap_or_default())
             {
-                let mut text = String::from_utf8_lossy(opened_file.buffer.as_ref()).to_string();
+                let font_id = self.settings.editor_font_id();
+                let query = self.settings.syntax_query(&opened_file.path);
 
-                egui::ScrollArea::vertical()
-                    .auto_shrink([false, true])
-                    .show(ui, |ui| {
-                        let mut theme =
-                            crate::syntax_highlighting::CodeTheme::from_memory(ui.ctx());
-                        ui.collapsing("Theme", |ui| {
-                            ui.group(|ui| {
-                                theme.ui(ui);
-                                theme.clone().store_in_memory(ui.ctx());
-                            });
-                        });
+                let theme = self
+                    .settings
+                    .code_theme(dark, opened_file.highlight.syntax_name().flatten());
 
-                        let ext = opened_file
-                            .path
-                            .extension()
-                            .map(|e| e.to_string_lossy().to_string().to_lowercase())
-                            .unwrap_or_default();
+                if opened_file.partial {
+                    ui.label("Large file mode");
 
-                        let mut layouter = |ui: &egui::Ui, string: &str, wrap_width: f32| {
-                            let mut layout_job = crate::syntax_highlighting::highlight(
-                                ui.ctx(),
-                                &theme,
-                                string,
-                                ext.as_str(),
-                            );
-                            layout_job.wrap.max_width = wrap_width;
-                            ui.fonts(|f| f.layout_job(layout_job))
-                        };
+                    let response = ui.add(
+                        egui::Slider::new(&mut opened_file.cursor, 0..=opened_file.len)
+                            .logarithmic(true),
+                    );
+                    if response.changed() {
+                        opened_file.seek().unwrap_or_default();
+                    }
+                }
 
-                        if opened_file.partial {
-                            ui.label("Large file mode");
-
-                            let response = ui.add(
-                                egui::Slider::new(&mut opened_file.cursor, 0..=opened_file.len)
-                                    .logarithmic(true),
-                            );
-                            if response.changed() {
-                                opened_file.seek().unwrap_or_default();
-                            }
+                let colors = theme.colors(ui.visuals());
+                let gutter = self.settings.line_numbers.then(|| {
+                    let mut gutter = Gutter {
+                        first_line: 1,
+                        font_id: font_id.clone(),
+                        current_line: None,
+                        relative: self.settings.relative_line_numbers,
+                        colors,
+                    };
+                    if opened_file.partial {
+                        match opened_file.first_line() {
+                            Some(line) => gutter.first_line = line + 1,
+                            None => ui
+                                .ctx()
+                                .request_repaint_after(std::time::Duration::from_millis(100)),
                         }
+                    }
+                    gutter
+                });
 
-                        // let text_style = TextStyle::Monospace;
-                        // let row_height = ui.text_style_height(&text_style);
-                        // let num_rows = text.lines().count();
-                        // ScrollArea::vertical().auto_shrink([false; 2]).show_rows(
-                        //     ui,
-                        //     row_height,
-                        //     num_rows,
-                        //     |ui, row_range| {
-                        //         for row in row_range {
-                        //             let text = format!("This is row {}/{}", row + 1, num_rows);
-                        //             ui.label(text);
-                        //         }
-                        //     },
-                        // );
-
-
-
-                        egui::ScrollArea::vertical().show(ui, |ui| {
-                            if ui
-                                .add(
-                                    egui::TextEdit::multiline(&mut text)
-                                        .font(egui::TextStyle::Monospace) // for cursor height
-                                        .code_editor()
-                                        .desired_rows(10)
-                                        .lock_focus(true)
-                                        .desired_width(f32::INFINITY)
-                                        .frame(false)
-                                        .margin(Vec2::new(2., 2.))
-                                        .layouter(&mut layouter),
-                                )
-                                .changed()
-                            {
-                                opened_file.buffer = text.into_bytes();
-                            }
-                        });
-                    });
+                let columns = opened_file.delimited_columns();
+                let code_font = font_id.clone();
+                let view = EditorView {
+                    id: egui::Id::new(("editor", &opened_file.path)),
+                    font_id,
+                    theme: &theme,
+                    query: &query,
+                    colors,
+                    gutter,
+                    wrap: opened_file.wrap,
+                    ruler: self.settings.ruler,
+                    max_line_columns: self.settings.max_line_columns,
+                    max_highlight_len: self.settings.max_highlight_len,
+                    columns,
+                    read_only: opened_file.read_only,
+                };
+                let delimiter = delimited::for_path(&opened_file.path);
+                match (opened_file.view, opened_file.format(), delimiter) {
+                    (ViewMode::Tree, Some(format), _) if Tree::supports(format) => {
+                        opened_file.show_tree(ui, format);
+                    }
+                    (ViewMode::Table, _, Some(delimiter)) => {
+                        opened_file.show_table(ui, delimiter);
+                    }
+                    _ if opened_file.markdown_preview && opened_file.is_markdown() => {
+                        let id = egui::Id::new(("markdown preview", &opened_file.path));
+                        egui::SidePanel::right(id)
+                            .resizable(true)
+                            .default_width(ui.available_width() / 2.0)
+                            .show_inside(ui, |ui| {
+                                clicked_file = opened_file.show_preview(ui, &theme, &code_font);
+                            });
+                        opened_file.show_editor(ui, view);
+                        if opened_file.preview_behind() {
+                            ui.ctx().request_repaint();
+                        }
+                    }
+                    _ => opened_file.show_editor(ui, view),
+                }
+                if opened_file.update_status() {
+                    ui.ctx().request_repaint();
+                }
+            }
+            if let Some(path) = clicked_file {
+                self.open(path);
             }
         });
     }
 }
 
+/// `data.min.json` for minified and `data.formatted.json` for formatted `data.json`.
+fn formatted_path(path: &Path, minify: bool) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let mut name = format!("{stem}.{}", if minify { "min" } else { "formatted" });
+    if let Some(extension) = path.extension() {
+        name = format!("{name}.{}", extension.to_string_lossy());
+    }
+    path.with_file_name(name)
+}
+
+/// `1.5 MiB` and the like
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return plural(bytes, "byte");
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+fn plural(n: u64, unit: &str) -> String {
+    format!("{n} {unit}{}", if n == 1 { "" } else { "s" })
+}
+
+/// Install the bundled fonts and, if set, the user's editor font in front of them.
+fn setup_fonts(ctx: &egui::Context, editor_font: Option<&Path>) {
+    let mut fonts = egui::FontDefinitions::default();
+
+    // Install my own font (maybe supporting non-latin characters):
+    fonts.font_data.insert(
+        "mono".to_owned(),
+        egui::FontData::from_static(include_bytes!("../assets/FiraCode-Regular.ttf")),
+    ); // .ttf and .otf supported
+
+    fonts.font_data.insert(
+        "sans".to_owned(),
+        egui::FontData::from_static(include_bytes!("../assets/FiraCode-Regular.ttf")),
+    ); // .ttf and .otf supported
+
+    // Put my font first (highest priority):
+    fonts
+        .families
+        .get_mut(&egui::FontFamily::Proportional)
+        .unwrap()
+        .insert(0, "sans".to_owned());
+
+    // Put my font as last fallback for monospace:
+    fonts
+        .families
+        .get_mut(&egui::FontFamily::Monospace)
+        .unwrap()
+        .insert(0, "mono".to_owned());
+
+    // The editor uses its own family, falling back to the monospace fonts
+    let mut editor_family = fonts.families[&egui::FontFamily::Monospace].clone();
+    if let Some(path) = editor_font {
+        match std::fs::read(path) {
+            Ok(data) if is_font(&data) => {
+                fonts
+                    .font_data
+                    .insert(EDITOR_FONT.to_owned(), egui::FontData::from_owned(data));
+                editor_family.insert(0, EDITOR_FONT.to_owned());
+            }
+            Ok(_) => warn!("{} is not a TrueType/OpenType font", path.display()),
+            Err(e) => warn!("Can't load font {}: {e}", path.display()),
+        }
+    }
+
+    // Bold and italic faces of the user's font, if it has them. The bundled font has none.
+    let faces = [
+        (EDITOR_FONT_BOLD, "Bold"),
+        (EDITOR_FONT_ITALIC, "Italic"),
+        (EDITOR_FONT_BOLD_ITALIC, "BoldItalic"),
+    ];
+    let mut installed = vec![];
+    for (family, face) in faces {
+        let mut face_family = vec![];
+        if let Some(path) = editor_font.and_then(|p| font_face(p, face)) {
+            match std::fs::read(&path) {
+                Ok(data) if is_font(&data) => {
+                    fonts
+                        .font_data
+                        .insert(family.to_owned(), egui::FontData::from_owned(data));
+                    face_family.push(family.to_owned());
+                    installed.push(family);
+                }
+                Ok(_) => warn!("{} is not a TrueType/OpenType font", path.display()),
+                Err(e) => warn!("Can't load font {}: {e}", path.display()),
+            }
+        }
+        // Glyphs missing in the face come from the regular font. Without a face the family is
+        // still bound, as text laid out for a previous font may use it.
+        face_family.extend(editor_family.iter().cloned());
+        fonts
+            .families
+            .insert(egui::FontFamily::Name(family.into()), face_family);
+    }
+    syntax_highlighting::set_font_faces(&installed);
+
+    fonts
+        .families
+        .insert(egui::FontFamily::Name(EDITOR_FONT.into()), editor_family);
+
+    ctx.set_fonts(fonts);
+}
+
+/// A sibling of a font file with another face, e.g. `Foo-Bold.ttf` for `Foo-Regular.ttf`.
+fn font_face(path: &Path, face: &str) -> Option<PathBuf> {
+    let stem = path.file_stem()?.to_str()?;
+    let extension = path.extension()?.to_str()?;
+    let base = stem
+        .strip_suffix("-Regular")
+        .or_else(|| stem.strip_suffix("Regular"))
+        .unwrap_or(stem);
+    [
+        format!("{base}-{face}.{extension}"),
+        format!("{base}{face}.{extension}"),
+    ]
+    .into_iter()
+    .map(|name| path.with_file_name(name))
+    .find(|p| p.exists())
+}
+
+/// egui panics on invalid font data, so check the magic bytes first.
+fn is_font(data: &[u8]) -> bool {
+    matches!(
+        data.get(..4),
+        Some([0, 1, 0, 0] | b"OTTO" | b"true" | b"ttcf")
+    )
+}
+
 fn read_file(path: &Path) -> Result<OpenedFile> {
     // open a file (note: binding does not need to be mut)
     let raf = RandomAccessFile::open(path)?;
@@ -238,16 +1179,36 @@ fn read_file(path: &Path) -> Result<OpenedFile> {
 
     const MAX_BYTES: u64 = 5 * 1000000;
 
-    if meta.len() < MAX_BYTES.try_into()? {
+    if meta.len() < MAX_BYTES {
         // read up to 512 bytes
         let mut buf = vec![];
         File::read_to_end(&mut File::open(path)?, &mut buf)?;
         Ok(OpenedFile {
             cursor: 0,
+            encoding: Encoding::detect(&buf),
+            read_only: meta.permissions().readonly(),
             buffer: buf,
             partial: false,
             path: path.into(),
             len: meta.len(),
+            unsaved: false,
+            view: ViewMode::Text,
+            columns: default_columns(path),
+            delimiter: None,
+            markdown_preview: false,
+            line_index: None,
+            first_line: Some(0),
+            highlight: Default::default(),
+            wrap: WrapMode::Off,
+            editor: None,
+            buffer_version: None,
+            status: Default::default(),
+            tree: None,
+            tree_lines: Default::default(),
+            tree_task: None,
+            tree_caret: None,
+            table: None,
+            preview: None,
         })
     } else {
         info!("Large file");
@@ -256,9 +1217,29 @@ fn read_file(path: &Path) -> Result<OpenedFile> {
         Ok(OpenedFile {
             cursor: 0,
             buffer: buf.to_vec(),
+            encoding: Encoding::detect(&buf),
+            read_only: true,
             partial: true,
             path: path.into(),
             len: meta.len(),
+            unsaved: false,
+            view: ViewMode::Text,
+            columns: default_columns(path),
+            delimiter: None,
+            markdown_preview: false,
+            line_index: Some(LineIndex::build(path, meta.len(), Encoding::detect(&buf))),
+            first_line: None,
+            highlight: Default::default(),
+            wrap: WrapMode::Off,
+            editor: None,
+            buffer_version: None,
+            status: Default::default(),
+            tree: None,
+            tree_lines: Default::default(),
+            tree_task: None,
+            tree_caret: None,
+            table: None,
+            preview: None,
         })
     }
 }
@@ -267,12 +1248,160 @@ fn read_file(path: &Path) -> Result<OpenedFile> {
 struct OpenedFile {
     cursor: u64,
     buffer: Vec<u8>,
+    #[serde(default)]
+    encoding: Encoding,
+    #[serde(default)]
+    read_only: bool,
+    #[serde(default)]
+    wrap: WrapMode,
     partial: bool,
     path: PathBuf,
     len: u64,
+    /// Not backed by a file, like formatted text opened in a new tab
+    #[serde(default)]
+    unsaved: bool,
+    #[serde(default)]
+    view: ViewMode,
+    /// How delimited text is highlighted in the editor
+    #[serde(default)]
+    columns: ColumnMode,
+    /// What the columns are split by, once known
+    #[serde(skip)]
+    delimiter: Option<char>,
+    /// Show the rendered Markdown beside the editor
+    #[serde(default)]
+    markdown_preview: bool,
+    /// Only built for partially loaded files
+    #[serde(skip)]
+    line_index: Option<LineIndex>,
+    /// Zero-based line number the buffer starts at, once known
+    #[serde(skip)]
+    first_line: Option<u64>,
+    #[serde(skip)]
+    highlight: HighlightCache,
+    /// The decoded buffer, made when it is first shown
+    #[serde(skip)]
+    editor: Option<Editor>,
+    /// Version of the editor text `buffer` was encoded from. Edits are only encoded when the
+    /// bytes are needed, see [`OpenedFile::sync_buffer`].
+    #[serde(skip)]
+    buffer_version: Option<u64>,
+    /// What the status bar shows about the editor, as of the last frame
+    #[serde(skip)]
+    status: EditorStatus,
+    /// The document as a tree, once it was shown as one
+    #[serde(skip)]
+    tree: Option<TreeView>,
+    /// Byte offsets of the lines of a large file its tree nodes start in
+    #[serde(skip)]
+    tree_lines: LineOffsets,
+    /// Reading and parsing a large file for its tree
+    #[serde(skip)]
+    tree_task: Option<WholeFile<(TreeView, LineOffsets)>>,
+    /// Caret the tree selection was last synced with, counted from the start of the file
+    #[serde(skip)]
+    tree_caret: Option<Position>,
+    /// The records as a table, once they were shown as one
+    #[serde(skip)]
+    table: Option<TableView>,
+    /// The rendered Markdown, once it was shown
+    #[serde(skip)]
+    preview: Option<MarkdownPreview>,
+}
+
+/// Bytes of a file looked at to detect what its columns are split by
+const DELIMITER_SAMPLE: usize = 64 * 1024;
+
+/// CSV and TSV files are colored by column when they are opened.
+fn default_columns(path: &Path) -> ColumnMode {
+    if delimited::for_path(path).is_some() {
+        ColumnMode::Rainbow
+    } else {
+        ColumnMode::Off
+    }
+}
+
+/// Name of the column at `pos` from the header in the first line, or its number if the first
+/// line isn't loaded.
+fn column_name(editor: &Editor, pos: Position, delimiter: char, header: bool) -> String {
+    let line = editor.line(pos.line);
+    let offset = line
+        .char_indices()
+        .nth(pos.column)
+        .map_or(line.len(), |(i, _)| i);
+    let column = delimited::fields(line, delimiter)
+        .iter()
+        .position(|f| offset <= f.end)
+        .unwrap_or_default();
+    header
+        .then(|| delimited::record(editor.line(0), delimiter))
+        .and_then(|names| names.into_iter().nth(column))
+        .filter(|name| !name.trim().is_empty())
+        .unwrap_or_else(|| format!("Column {}", column + 1))
+}
+
+/// How the document of an [`OpenedFile`] is shown.
+#[derive(Clone, Copy, PartialEq, Eq, Default, serde::Deserialize, serde::Serialize)]
+enum ViewMode {
+    #[default]
+    Text,
+    /// Collapsible nodes of a JSON, YAML or TOML document
+    Tree,
+    /// Records of a CSV or TSV file in columns
+    Table,
+}
+
+#[derive(Default, PartialEq)]
+struct EditorStatus {
+    /// Zero-based line in the buffer and column of the caret
+    cursor: (usize, usize),
+    /// Chars, lines and bytes selected
+    selection: Option<(usize, usize, usize)>,
+    lines: usize,
+    /// Bytes the text takes in its encoding
+    size: u64,
+    /// Lines split into chunks because they are too long
+    long_lines: usize,
+    line_ending: Option<LineEnding>,
+    /// Name of the column at the caret, if colored by column
+    column: Option<String>,
+    /// Text version and selections `selection` was counted for
+    selection_key: (u64, Vec<Selection>),
 }
 
 impl OpenedFile {
+    /// A tab with `text` that isn't saved anywhere yet.
+    fn unsaved(path: PathBuf, text: &str, encoding: Encoding) -> Self {
+        let buffer = encoding.encode(text);
+        Self {
+            cursor: 0,
+            encoding,
+            read_only: false,
+            partial: false,
+            len: buffer.len() as u64,
+            buffer,
+            path,
+            unsaved: true,
+            view: ViewMode::Text,
+            columns: ColumnMode::Off,
+            delimiter: None,
+            markdown_preview: false,
+            line_index: None,
+            first_line: Some(0),
+            highlight: Default::default(),
+            wrap: WrapMode::Off,
+            editor: None,
+            buffer_version: None,
+            status: Default::default(),
+            tree: None,
+            tree_lines: Default::default(),
+            tree_task: None,
+            tree_caret: None,
+            table: None,
+            preview: None,
+        }
+    }
+
     pub fn seek(&mut self) -> Result<()> {
         let mut buf = [0; 10000];
 
@@ -281,7 +1410,329 @@ impl OpenedFile {
         raf.read_at(self.cursor, &mut buf)?;
 
         self.buffer = buf.to_vec();
+        self.first_line = None;
+        self.editor = None;
+        self.buffer_version = None;
 
         Ok(())
     }
+
+    /// Move the caret to `line[:column]`, both one-based and counted from the start of the file.
+    fn go_to_line(&mut self, input: &str) -> Result<()> {
+        let (line, column) = input.trim().split_once(':').unwrap_or((input.trim(), "1"));
+        let line = line.trim().parse::<u64>()?;
+        let column = column.trim().parse::<usize>()?;
+        let first_line = if self.partial {
+            self.first_line()
+                .ok_or_else(|| anyhow!("The file is still being indexed"))?
+        } else {
+            0
+        };
+        let editor = self.editor();
+        let lines = editor.line_count();
+        let in_buffer = line
+            .checked_sub(first_line + 1)
+            .filter(|l| *l < lines as u64)
+            .ok_or_else(|| {
+                if self.partial {
+                    anyhow!("Line {line} is outside the loaded part of the file")
+                } else {
+                    anyhow!("The file has {lines} lines")
+                }
+            })?;
+        self.editor()
+            .go_to(Position::new(in_buffer as usize, column.saturating_sub(1)));
+        Ok(())
+    }
+
+    /// Load the part of a large file starting at byte `input`.
+    fn go_to_offset(&mut self, input: &str) -> Result<()> {
+        let offset = input.trim().parse::<u64>()?;
+        if offset >= self.len {
+            bail!("The file is only {} long", format_size(self.len));
+        }
+        self.cursor = offset;
+        self.seek()
+    }
+
+    /// Radio buttons for the ways the file can be shown.
+    fn view_mode_ui(&mut self, ui: &mut egui::Ui) {
+        ui.radio_value(&mut self.view, ViewMode::Text, "Text");
+        let tree = self.format().is_some_and(Tree::supports);
+        ui.add_enabled(
+            tree,
+            egui::RadioButton::new(self.view == ViewMode::Tree, "Tree"),
+        )
+        .on_disabled_hover_text("Only JSON, YAML and TOML can be shown as a tree")
+        .clicked()
+        .then(|| self.view = ViewMode::Tree);
+        let table = delimited::for_path(&self.path).is_some();
+        ui.add_enabled(
+            table,
+            egui::RadioButton::new(self.view == ViewMode::Table, "Table"),
+        )
+        .on_disabled_hover_text("Only CSV and TSV files can be shown as a table")
+        .clicked()
+        .then(|| self.view = ViewMode::Table);
+    }
+
+    /// The language to format the file as, if it is one we can.
+    fn format(&self) -> Option<Format> {
+        Format::detect(self.highlight.syntax_name().flatten(), &self.path)
+    }
+
+    /// Replace the text in one step that can be undone.
+    fn replace_text(&mut self, text: &str) {
+        self.editor().replace_all(text);
+    }
+
+    /// Move the caret to `pos`, counted from the start of the file. Large files are loaded from
+    /// `line_offset`, the byte offset of that line, first.
+    fn go_to_position(&mut self, pos: Position, line_offset: Option<u64>) -> Result<()> {
+        match line_offset {
+            Some(offset) => {
+                self.cursor = offset;
+                self.seek()?;
+                self.editor().go_to(Position::new(0, pos.column));
+            }
+            None => self.editor().go_to(pos),
+        }
+        Ok(())
+    }
+
+    /// The primary caret, counted from the start of the file. `None` while a large file is
+    /// still being indexed.
+    fn file_caret(&mut self) -> Option<Position> {
+        let head = self.editor().primary().head;
+        let first_line = if self.partial { self.first_line()? } else { 0 };
+        Some(Position::new(first_line as usize + head.line, head.column))
+    }
+
+    /// Show the document as a tree, selecting the node at the caret.
+    fn show_tree(&mut self, ui: &mut egui::Ui, format: Format) {
+        if self.partial {
+            // Large files are read-only, so their tree is built once, in the background
+            if self.tree.is_none() {
+                let encoding = self.encoding;
+                let task = self.tree_task.get_or_insert_with(|| {
+                    WholeFile::spawn(&self.path, self.len, encoding, "Parsing", move |text| {
+                        let tree = TreeView::new(format, &text, 0);
+                        let lines = LineOffsets::new(&text, encoding, tree.lines());
+                        (tree, lines)
+                    })
+                });
+                let Some((tree, lines)) = task.poll() else {
+                    task.ui(ui);
+                    return;
+                };
+                self.tree = Some(tree);
+                self.tree_lines = lines;
+                self.tree_task = None;
+                self.tree_caret = None;
+            }
+        } else {
+            let version = self.editor().version();
+            if self.tree.as_ref().map(TreeView::version) != Some(version) {
+                let text = self.editor().text();
+                match &mut self.tree {
+                    Some(tree) => tree.rebuild(format, &text, version),
+                    None => self.tree = Some(TreeView::new(format, &text, version)),
+                }
+                self.tree_caret = None;
+            }
+        }
+
+        let caret = self.file_caret();
+        let Some(tree) = &mut self.tree else {
+            return;
+        };
+        if caret != self.tree_caret {
+            if let Some(caret) = caret {
+                tree.select_at(caret);
+            }
+            self.tree_caret = caret;
+        }
+        let (pos, open) = match tree.ui(ui) {
+            Some(TreeAction::Select(pos)) => (pos, false),
+            Some(TreeAction::Open(pos)) => (pos, true),
+            None => return,
+        };
+        let line_offset = self
+            .partial
+            .then(|| self.tree_lines.get(pos.line))
+            .flatten();
+        self.go_to_position(pos, line_offset).unwrap_or_default();
+        // Already selected
+        self.tree_caret = Some(pos).filter(|_| !self.partial);
+        if open {
+            self.view = ViewMode::Text;
+        }
+    }
+
+    /// Show the records as a table, split by `delimiter` unless another one is detected.
+    fn show_table(&mut self, ui: &mut egui::Ui, delimiter: char) {
+        // Records are found by their bytes, so large files have to be in an ASCII compatible
+        // encoding
+        if self.partial && matches!(self.encoding, Encoding::Utf16Le | Encoding::Utf16Be) {
+            ui.weak("Large UTF-16 files can't be shown as a table");
+            return;
+        }
+        let version = if self.partial {
+            0
+        } else {
+            self.editor().version()
+        };
+        if self.table.as_ref().map(TableView::version) != Some(version) {
+            let source = if self.partial {
+                table_view::Source::File {
+                    path: self.path.clone(),
+                    encoding: self.encoding,
+                }
+            } else {
+                table_view::Source::Text(self.editor().text().into())
+            };
+            match &mut self.table {
+                Some(table) => table.set_source(source, version),
+                None => self.table = Some(TableView::new(source, version, delimiter)),
+            }
+        }
+        if let Some(table) = &mut self.table {
+            table.ui(ui);
+        }
+    }
+
+    fn is_markdown(&self) -> bool {
+        markdown_preview::is_markdown(&self.path)
+            || self.highlight.syntax_name() == Some(Some("Markdown"))
+    }
+
+    /// Show the rendered Markdown, scrolled to where the editor is. Returns the local file a link
+    /// to was clicked.
+    fn show_preview(
+        &mut self,
+        ui: &mut egui::Ui,
+        theme: &CodeTheme,
+        code: &egui::FontId,
+    ) -> Option<PathBuf> {
+        let editor = self.editor();
+        let (version, top_line) = (editor.version(), editor.top_line());
+        if self.preview.as_ref().map(MarkdownPreview::version) != Some(version) {
+            let text = self.editor().text();
+            match &mut self.preview {
+                Some(preview) => preview.set_text(&text, version),
+                None => {
+                    let base = self.path.parent().unwrap_or(Path::new("")).to_path_buf();
+                    self.preview = Some(MarkdownPreview::new(&text, version, base));
+                }
+            }
+        }
+        let preview = self.preview.as_mut()?;
+        preview.ui(ui, theme, code, top_line);
+        preview.take_clicked_file()
+    }
+
+    /// Whether the preview has yet to follow the editor scrolling.
+    fn preview_behind(&self) -> bool {
+        match (&self.preview, &self.editor) {
+            (Some(preview), Some(editor)) => !preview.is_synced(editor.top_line()),
+            _ => false,
+        }
+    }
+
+    fn editor(&mut self) -> &mut Editor {
+        let editor = self
+            .editor
+            .get_or_insert_with(|| Editor::new(&self.encoding.decode(&self.buffer)));
+        self.buffer_version.get_or_insert(editor.version());
+        editor
+    }
+
+    fn show_editor(&mut self, ui: &mut egui::Ui, view: EditorView<'_>) {
+        self.editor();
+        if let Some(editor) = &mut self.editor {
+            editor.show(ui, &mut self.highlight, view);
+        }
+    }
+
+    /// Encode the editor text into `buffer` if it was edited since it last was.
+    fn sync_buffer(&mut self) -> &[u8] {
+        if let Some(editor) = &self.editor {
+            if self.buffer_version != Some(editor.version()) {
+                self.buffer = self.encoding.encode(&editor.text());
+                self.buffer_version = Some(editor.version());
+            }
+        }
+        &self.buffer
+    }
+
+    /// Catch up with the editor. Returns whether the status bar needs to be redrawn.
+    fn update_status(&mut self) -> bool {
+        let columns = self.delimited_columns();
+        let Some(editor) = &self.editor else {
+            return false;
+        };
+        let head = editor.primary().head;
+        let selection_key = (editor.version(), editor.selections().to_vec());
+        let size = if editor.version() == self.status.selection_key.0
+            && editor.line_ending() == self.status.line_ending
+        {
+            self.status.size
+        } else {
+            editor.encoded_len(self.encoding) as u64
+        };
+        let selection = if selection_key == self.status.selection_key {
+            self.status.selection
+        } else {
+            editor
+                .selected()
+                .map(|(chars, lines, text)| (chars, lines, self.encoding.encoded_len(&text)))
+        };
+        let status = EditorStatus {
+            cursor: (head.line, head.column),
+            selection,
+            lines: editor.line_count(),
+            size,
+            long_lines: editor.long_lines(),
+            line_ending: editor.line_ending(),
+            column: columns
+                .map(|c| column_name(editor, head, c.delimiter, self.first_line == Some(0))),
+            selection_key,
+        };
+        if status.long_lines > 0 && self.status.long_lines == 0 {
+            info!(
+                "{} has {} long lines",
+                self.path.display(),
+                status.long_lines
+            );
+        }
+        let changed = status != self.status;
+        self.status = status;
+        changed
+    }
+
+    /// Columns to color the text by, if they are shown.
+    fn delimited_columns(&mut self) -> Option<Columns> {
+        let align = match self.columns {
+            ColumnMode::Off => return None,
+            ColumnMode::Rainbow => false,
+            ColumnMode::Aligned => true,
+        };
+        if self.delimiter.is_none() {
+            let encoding = self.encoding;
+            let buffer = self.sync_buffer();
+            let sample = &buffer[..buffer.len().min(DELIMITER_SAMPLE)];
+            let detected = delimited::detect(&encoding.decode(sample));
+            self.delimiter = Some(delimited::for_path(&self.path).or(detected).unwrap_or(','));
+        }
+        let delimiter = self.delimiter.unwrap_or(',');
+        Some(Columns { delimiter, align })
+    }
+
+    /// Zero-based line number of the start of the buffer. `None` while the file is still being indexed.
+    pub fn first_line(&mut self) -> Option<u64> {
+        if self.first_line.is_none() {
+            self.first_line = self.line_index.as_ref()?.line_at(self.cursor);
+        }
+        self.first_line
+    }
 }
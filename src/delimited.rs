@@ -0,0 +1,176 @@
+//! Delimiter separated values, like CSV and TSV.
+
+use std::{borrow::Cow, io::Read, ops::Range, path::Path};
+
+/// What fields may be separated by, in order of preference
+pub const DELIMITERS: [char; 4] = [',', '\t', ';', '|'];
+
+/// Lines looked at to detect the delimiter
+const SAMPLE_LINES: usize = 20;
+
+/// Bytes read at once when scanning for records
+const READ_SIZE: usize = 1 << 20;
+
+/// Name of a delimiter in menus.
+pub fn name(delimiter: char) -> &'static str {
+    match delimiter {
+        ',' => "Comma",
+        '\t' => "Tab",
+        ';' => "Semicolon",
+        '|' => "Pipe",
+        _ => "Other",
+    }
+}
+
+/// The delimiter files named like `path` usually have, for `.csv`, `.tsv` and `.psv`.
+pub fn for_path(path: &Path) -> Option<char> {
+    let extension = path.extension()?.to_string_lossy().to_ascii_lowercase();
+    match extension.as_str() {
+        "csv" => Some(','),
+        "tsv" | "tab" => Some('\t'),
+        "psv" => Some('|'),
+        _ => None,
+    }
+}
+
+/// The delimiter that splits the first lines of `text` into the most fields, the same number
+/// in every line. `None` if none splits them at all.
+pub fn detect(text: &str) -> Option<char> {
+    let lines = text
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .take(SAMPLE_LINES)
+        .collect::<Vec<_>>();
+    DELIMITERS
+        .into_iter()
+        .rev()
+        .filter_map(|delimiter| {
+            let mut counts = lines.iter().map(|l| fields(l, delimiter).len());
+            let first = counts.next()?;
+            (first > 1 && counts.all(|c| c == first)).then_some((delimiter, first))
+        })
+        .max_by_key(|(_, count)| *count)
+        .map(|(delimiter, _)| delimiter)
+}
+
+/// Byte ranges of the fields of `line`. Delimiters in double quotes don't count.
+pub fn fields(line: &str, delimiter: char) -> Vec<Range<usize>> {
+    let mut fields = vec![];
+    let mut start = 0;
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        if c == '"' {
+            quoted = !quoted;
+        } else if c == delimiter && !quoted {
+            fields.push(start..i);
+            start = i + c.len_utf8();
+        }
+    }
+    fields.push(start..line.len());
+    fields
+}
+
+/// `field` without its quotes and with doubled quotes made single.
+pub fn unquote(field: &str) -> Cow<'_, str> {
+    match field.strip_prefix('"').and_then(|f| f.strip_suffix('"')) {
+        Some(inner) if inner.contains("\"\"") => Cow::Owned(inner.replace("\"\"", "\"")),
+        Some(inner) => Cow::Borrowed(inner),
+        None => Cow::Borrowed(field),
+    }
+}
+
+/// The unquoted fields of a record, without its line break.
+pub fn record(text: &str, delimiter: char) -> Vec<String> {
+    let text = text.trim_end_matches(['\r', '\n']);
+    fields(text, delimiter)
+        .into_iter()
+        .map(|f| unquote(&text[f]).into_owned())
+        .collect()
+}
+
+/// Calls `record` with the index, byte offset and bytes of every record in `reader`, until it
+/// returns false. Records end at line breaks that aren't in quotes.
+pub fn scan(
+    mut reader: impl Read,
+    mut record: impl FnMut(usize, u64, &[u8]) -> bool,
+) -> std::io::Result<()> {
+    let mut buf = vec![0; READ_SIZE];
+    let mut current = vec![];
+    let (mut index, mut start, mut offset) = (0, 0, 0);
+    let mut quoted = false;
+    loop {
+        let read = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        let mut from = 0;
+        for (i, b) in buf[..read].iter().enumerate() {
+            match b {
+                b'"' => quoted = !quoted,
+                b'\n' if !quoted => {
+                    current.extend_from_slice(&buf[from..=i]);
+                    if !record(index, start, &current) {
+                        return Ok(());
+                    }
+                    current.clear();
+                    from = i + 1;
+                    index += 1;
+                    start = offset + from as u64;
+                }
+                _ => {}
+            }
+        }
+        current.extend_from_slice(&buf[from..read]);
+        offset += read as u64;
+    }
+    if !current.is_empty() {
+        record(index, start, &current);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_delimiters() {
+        assert_eq!(detect("a,b,c\n1,2,3\n"), Some(','));
+        assert_eq!(detect("a;b;c\n1,5;2;3\n"), Some(';'));
+        assert_eq!(detect("a\tb\n\"x\ty\"\tz\n"), Some('\t'));
+        assert_eq!(detect("just text\nmore text"), None);
+    }
+
+    #[test]
+    fn quoted_fields() {
+        let line = "a,\"b, c\",\"say \"\"hi\"\"\",";
+        let fields = fields(line, ',');
+        assert_eq!(fields.len(), 4);
+        assert_eq!(unquote(&line[fields[1].clone()]), "b, c");
+        assert_eq!(
+            record(&format!("{line}\r\n"), ','),
+            ["a", "b, c", "say \"hi\"", ""]
+        );
+    }
+
+    #[test]
+    fn scans_records() {
+        let text = "a,b\n\"1\n2\",3\nx,y";
+        let mut records = vec![];
+        scan(text.as_bytes(), |i, start, bytes| {
+            records.push((i, start, String::from_utf8_lossy(bytes).into_owned()));
+            true
+        })
+        .unwrap();
+        assert_eq!(
+            records,
+            [
+                (0, 0, "a,b\n".into()),
+                (1, 4, "\"1\n2\",3\n".into()),
+                (2, 12, "x,y".into()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,892 @@
+//! The text editor widget. Only the lines on screen are laid out and highlighted, so editing
+//! stays fast in large files.
+
+use crate::{
+    encoding::{Encoding, LineEnding},
+    gutter::Gutter,
+    line_buffer::{Edit, LineBuffer, Position},
+    multi_cursor::{Motion, MultiCursor, Selection},
+    syntax_highlighting::{
+        CodeTheme, Columns, EditedText, EditorColors, HighlightCache, SyntaxQuery,
+    },
+    wrap::{Rows, WrapMode},
+};
+use egui::{
+    epaint::text::cursor::CCursor, Align, Align2, Event, FontId, Galley, Key, Modifiers, Pos2,
+    Rect, Sense, Ui, Vec2,
+};
+use std::{
+    ops::Range,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+/// Consecutive typing within this many seconds is undone in one step
+const UNDO_MERGE_SECS: f64 = 1.0;
+
+/// Space between the gutter and the text
+const TEXT_MARGIN: f32 = 4.0;
+
+/// Shown after each row of a line that was split because it is too long
+const SPLIT_MARKER: &str = "↩";
+
+/// Source of [`Editor::version`]s, shared so no two texts ever get the same one
+static VERSION: AtomicU64 = AtomicU64::new(0);
+
+fn next_version() -> u64 {
+    VERSION.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum EditKind {
+    Typing,
+    Other,
+}
+
+/// Everything one command changed, undone and redone as a whole.
+struct Transaction {
+    edits: Vec<Edit>,
+    before: MultiCursor,
+    after: MultiCursor,
+    kind: EditKind,
+    /// `InputState::time` of the last edit
+    time: f64,
+}
+
+/// How to show an [`Editor`] for one frame.
+pub struct EditorView<'a> {
+    pub id: egui::Id,
+    pub font_id: FontId,
+    pub theme: &'a CodeTheme,
+    pub query: &'a SyntaxQuery,
+    pub colors: EditorColors,
+    /// Line numbers, if they are shown
+    pub gutter: Option<Gutter>,
+    pub wrap: WrapMode,
+    /// Column to draw a vertical line at
+    pub ruler: Option<usize>,
+    /// Lines longer than this many chars are split into rows of that length, even when not wrapped
+    pub max_line_columns: usize,
+    /// Lines longer than this many bytes are not highlighted
+    pub max_highlight_len: usize,
+    /// Color delimited text by column instead of by syntax
+    pub columns: Option<Columns>,
+    pub read_only: bool,
+}
+
+/// A document being edited: its lines, carets and undo history.
+pub struct Editor {
+    buffer: LineBuffer,
+    /// What lines are joined with when saving, `None` if the text had none
+    line_ending: Option<LineEnding>,
+    cursors: MultiCursor,
+    undo: Vec<Transaction>,
+    redo: Vec<Transaction>,
+    /// Changes with every edit
+    version: u64,
+    /// Edits since the highlighter last saw the text, and the version they started from
+    unhighlighted: (u64, Vec<Edit>),
+    /// Length of the longest line, `None` after an edit until it's needed
+    longest_line: Option<usize>,
+    /// Alignment to scroll the primary caret into view with on the next frame
+    scroll_to_caret: Option<Option<Align>>,
+    /// Take the keyboard focus on the next frame
+    request_focus: bool,
+    /// Rows that fit on screen, as of the last frame
+    page_rows: usize,
+    /// How lines are split into rows
+    rows: Rows,
+    /// Chars per row if lines are wrapped, as of the last frame
+    wrap_columns: Option<usize>,
+    /// Chars per row of too long lines, as of the last frame
+    split_columns: usize,
+    /// Line at the top of the screen, as of the last frame
+    top_line: usize,
+}
+
+impl Editor {
+    pub fn new(text: &str) -> Self {
+        let version = next_version();
+        Self {
+            buffer: LineBuffer::new(text),
+            line_ending: LineEnding::detect(text),
+            cursors: Default::default(),
+            undo: vec![],
+            redo: vec![],
+            version,
+            unhighlighted: (version, vec![]),
+            longest_line: None,
+            scroll_to_caret: None,
+            request_focus: false,
+            page_rows: 20,
+            rows: Rows::default(),
+            wrap_columns: None,
+            split_columns: usize::MAX,
+            top_line: 0,
+        }
+    }
+
+    /// The whole text, with the line endings it was opened with or converted to.
+    pub fn text(&self) -> String {
+        self.buffer
+            .text(self.line_ending.unwrap_or(LineEnding::Lf).as_str())
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.buffer.line_count()
+    }
+
+    /// Text of `line`, without its line break.
+    pub fn line(&self, line: usize) -> &str {
+        self.buffer.line(line)
+    }
+
+    /// Size of [`Editor::text`] in `encoding`, without encoding it.
+    pub fn encoded_len(&self, encoding: Encoding) -> usize {
+        let line_break = encoding.encoded_len(self.line_ending.unwrap_or(LineEnding::Lf).as_str());
+        let lines = self.buffer.lines().map(|l| encoding.encoded_len(l));
+        encoding.encode("").len() + lines.sum::<usize>() + line_break * (self.line_count() - 1)
+    }
+
+    pub fn line_ending(&self) -> Option<LineEnding> {
+        self.line_ending
+    }
+
+    /// Use `ending` for all lines from now on. Mixed line endings can't be kept once edited.
+    pub fn set_line_ending(&mut self, ending: LineEnding) {
+        self.line_ending = Some(ending);
+    }
+
+    /// Changes whenever the text does, and differs between editors.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Replace the whole text in one step that can be undone, keeping the caret where it can.
+    pub fn replace_all(&mut self, text: &str) {
+        let before = self.cursors.clone();
+        let edit = self
+            .buffer
+            .replace(Position::default()..self.buffer.end(), text);
+        self.cursors
+            .set(Selection::caret(self.buffer.clamp(before.primary().head)));
+        self.record(vec![edit], before, EditKind::Other, 0.0);
+    }
+
+    /// Zero-based line at the top of the screen, as of the last frame.
+    pub fn top_line(&self) -> usize {
+        self.top_line
+    }
+
+    /// How many lines were split into chunks because they are too long, as of the last frame.
+    pub fn long_lines(&self) -> usize {
+        self.rows.long_lines()
+    }
+
+    pub fn selections(&self) -> &[Selection] {
+        self.cursors.selections()
+    }
+
+    /// The caret the status bar reports.
+    pub fn primary(&self) -> Selection {
+        self.cursors.primary()
+    }
+
+    /// Chars and lines selected over all selections, and the selected text. `None` if nothing is.
+    pub fn selected(&self) -> Option<(usize, usize, String)> {
+        let selections = self.selections().iter().filter(|s| !s.is_empty());
+        let mut found = None;
+        for s in selections {
+            let (chars, lines, text) = found.get_or_insert((0, 0, String::new()));
+            let range = s.range();
+            let selected = self.buffer.slice(range.clone());
+            *chars += selected.chars().count();
+            *lines += range.end.line - range.start.line + 1;
+            text.push_str(&selected);
+        }
+        found
+    }
+
+    /// Put a single caret at `pos` and scroll it to the middle of the screen.
+    pub fn go_to(&mut self, pos: Position) {
+        self.cursors.set(Selection::caret(self.buffer.clamp(pos)));
+        self.scroll_to_caret = Some(Some(Align::Center));
+        self.request_focus = true;
+    }
+
+    /// Returns whether the text was edited.
+    pub fn show(
+        &mut self,
+        ui: &mut Ui,
+        highlight: &mut HighlightCache,
+        mut view: EditorView<'_>,
+    ) -> bool {
+        let row_height = ui.fonts(|f| f.row_height(&view.font_id));
+        let char_width = ui.fonts(|f| f.glyph_width(&view.font_id, ' '));
+        highlight.set_columns(view.columns, &self.buffer);
+        // Aligned columns are wider than the text
+        let longest_line = *self
+            .longest_line
+            .get_or_insert_with(|| self.buffer.longest_line());
+        let longest_line = longest_line.max(highlight.aligned_width());
+        let gutter = view.gutter.take();
+        let gutter_width = gutter
+            .as_ref()
+            .map_or(0.0, |g| g.width(ui, self.buffer.line_count()));
+        let text_left = gutter_width + TEXT_MARGIN;
+
+        let output = egui::ScrollArea::both()
+            .id_source(view.id)
+            .auto_shrink([false; 2])
+            .show_viewport(ui, |ui, viewport| {
+                // Leave room for the caret after the last char
+                let window_columns = ((viewport.width() - text_left) / char_width) as usize;
+                let split = view.max_line_columns.max(1);
+                let (columns, width) = match view.wrap {
+                    WrapMode::Off => (None, longest_line.min(split)),
+                    WrapMode::Window => (Some(window_columns.saturating_sub(1).max(1)), 0),
+                    WrapMode::Column(columns) => (Some(columns), columns.min(split)),
+                };
+                self.wrap_columns = columns;
+                self.split_columns = split;
+                self.update_rows();
+
+                let origin = ui.cursor().min;
+                let size = Vec2::new(
+                    text_left + (width + 1) as f32 * char_width,
+                    self.rows.count() as f32 * row_height,
+                )
+                .max(viewport.size());
+                let rect = Rect::from_min_size(origin, size);
+                ui.allocate_rect(rect, Sense::hover());
+                let response = ui.interact(rect, view.id, Sense::click_and_drag());
+                if std::mem::take(&mut self.request_focus) {
+                    response.request_focus();
+                }
+
+                let layout = Layout {
+                    origin: origin + Vec2::new(text_left, 0.0),
+                    row_height,
+                    char_width,
+                    gutter_width,
+                };
+                self.handle_mouse(ui, &response, &layout, &view, highlight);
+                let changed = self.handle_keys(ui, &response, &view);
+                self.page_rows = ((viewport.height() / row_height) as usize).max(1);
+                self.update_rows();
+
+                let visible = (viewport.top() / row_height).floor().max(0.0) as usize
+                    ..((viewport.bottom() / row_height).ceil() as usize).min(self.rows.count());
+                let first_line = self.rows.line_of(visible.start).0;
+                let last_line = self.rows.line_of(visible.end.saturating_sub(1)).0;
+                self.top_line = first_line;
+                highlight.set_visible_lines(first_line..last_line + 1);
+                let (edited_from, edits) = &self.unhighlighted;
+                highlight.update(
+                    ui.ctx(),
+                    view.theme,
+                    view.query,
+                    view.max_highlight_len,
+                    EditedText {
+                        lines: &self.buffer,
+                        version: self.version,
+                        edited_from: *edited_from,
+                        edits,
+                    },
+                );
+                self.unhighlighted = (self.version, vec![]);
+                let rows = visible
+                    .map(|row| {
+                        let (line, index) = self.rows.line_of(row);
+                        VisibleRow {
+                            row,
+                            line,
+                            index,
+                            columns: self.rows.columns(&self.buffer, line, index),
+                            last: self.rows.is_last(line, index),
+                            split: self.rows.is_split(line),
+                            galley: self.galley(ui, highlight, &view, line, index),
+                        }
+                    })
+                    .collect::<Vec<_>>();
+
+                self.paint(ui, &response, &layout, &view, gutter, &rows);
+
+                if let Some(align) = self.scroll_to_caret.take() {
+                    let head = self.cursors.primary().head;
+                    let row = self.rows.row_of(head);
+                    let x = match rows.iter().find(|r| r.row == row) {
+                        Some(r) => column_x(&r.galley, head.column - r.columns.start),
+                        None => {
+                            let (line, index) = self.rows.line_of(row);
+                            let start = self.rows.columns(&self.buffer, line, index).start;
+                            (head.column - start) as f32 * char_width
+                        }
+                    };
+                    let caret = layout.caret_rect(row, x);
+                    // Keep the gutter from covering the caret
+                    ui.scroll_to_rect(caret.expand2(Vec2::new(text_left, 0.0)), align);
+                }
+                changed
+            });
+        output.inner
+    }
+
+    fn update_rows(&mut self) {
+        self.rows.update(
+            &self.buffer,
+            self.version,
+            self.wrap_columns,
+            self.split_columns,
+        );
+    }
+
+    /// Layout of row `index` of `line`.
+    fn galley(
+        &self,
+        ui: &Ui,
+        highlight: &HighlightCache,
+        view: &EditorView<'_>,
+        line: usize,
+        index: usize,
+    ) -> Arc<Galley> {
+        let job = highlight.line_job(
+            line,
+            self.buffer.line(line),
+            self.rows.bytes(&self.buffer, line, index),
+            view.theme,
+            &view.font_id,
+            ui.fonts(|f| f.glyph_width(&view.font_id, ' ')),
+        );
+        ui.fonts(|f| f.layout_job(job))
+    }
+
+    fn handle_mouse(
+        &mut self,
+        ui: &Ui,
+        response: &egui::Response,
+        layout: &Layout,
+        view: &EditorView<'_>,
+        highlight: &HighlightCache,
+    ) {
+        let (pressed, down, modifiers) = ui.input(|i| {
+            (
+                i.pointer.primary_pressed(),
+                i.pointer.primary_down(),
+                i.modifiers,
+            )
+        });
+        let Some(pointer) = response.interact_pointer_pos() else {
+            self.cursors.end_block();
+            return;
+        };
+        let (line, index) = self
+            .rows
+            .line_of(layout.row_at(pointer.y, self.rows.count()));
+        let columns = self.rows.columns(&self.buffer, line, index);
+        let x = pointer.x - layout.origin.x;
+        let galley = self.galley(ui, highlight, view, line, index);
+        let column = columns.start
+            + galley
+                .cursor_from_pos(Vec2::new(x, layout.row_height / 2.0))
+                .ccursor
+                .index;
+        // The end of a wrapped row is the start of the next one
+        let end = if self.rows.is_last(line, index) {
+            columns.end
+        } else {
+            columns.end.saturating_sub(1).max(columns.start)
+        };
+        let pos = Position::new(line, column.min(end));
+        // Columns by x, so a block can extend past short lines
+        let block_column = columns.start + (x / layout.char_width).round().max(0.0) as usize;
+
+        if pressed && response.hovered() {
+            ui.memory_mut(|m| m.request_focus(view.id));
+            if modifiers.alt {
+                self.cursors.end_block();
+                self.cursors.drag_block(&self.buffer, (line, block_column));
+            } else if modifiers.shift {
+                self.cursors.set_primary_head(pos);
+            } else if modifiers.command {
+                self.cursors.add(Selection::caret(pos));
+            } else {
+                self.cursors.set(Selection::caret(pos));
+            }
+        } else if down && self.cursors.is_dragging_block() {
+            self.cursors.drag_block(&self.buffer, (line, block_column));
+        } else if down && response.dragged() {
+            self.cursors.set_primary_head(pos);
+            self.scroll_to_caret = Some(None);
+        } else if !down {
+            self.cursors.end_block();
+        }
+
+        if response.triple_clicked() {
+            self.cursors.set(Selection {
+                anchor: Position::new(line, 0),
+                head: self.buffer.clamp(Position::new(line + 1, 0)),
+            });
+        } else if response.double_clicked() {
+            let word = self.buffer.word_at(pos);
+            self.cursors.set(Selection {
+                anchor: word.start,
+                head: word.end,
+            });
+        }
+    }
+
+    /// Move the carets `rows` rows down, or up if negative, keeping their distance from the
+    /// start of the row.
+    fn move_vertically(&mut self, rows: isize, extend: bool) {
+        self.update_rows();
+        let (buffer, layout_rows) = (&self.buffer, &self.rows);
+        self.cursors.move_heads(extend, |s| {
+            let row = layout_rows.row_of(s.head);
+            let target = row as isize + rows;
+            if target < 0 {
+                return Position::default();
+            }
+            if target as usize >= layout_rows.count() {
+                return buffer.end();
+            }
+            let (line, index) = layout_rows.line_of(row);
+            let offset = s.head.column - layout_rows.columns(buffer, line, index).start;
+            let (line, index) = layout_rows.line_of(target as usize);
+            let columns = layout_rows.columns(buffer, line, index);
+            let end = if layout_rows.is_last(line, index) {
+                columns.end
+            } else {
+                columns.end.saturating_sub(1).max(columns.start)
+            };
+            Position::new(line, (columns.start + offset).min(end))
+        });
+    }
+
+    /// Returns whether the text changed.
+    fn handle_keys(&mut self, ui: &Ui, response: &egui::Response, view: &EditorView<'_>) -> bool {
+        if !response.has_focus() {
+            return false;
+        }
+        ui.memory_mut(|m| m.lock_focus(view.id, true));
+        let (events, time) = ui.input(|i| (i.events.clone(), i.time));
+        let mut changed = false;
+        for event in events {
+            let before = self.cursors.clone();
+            let (edits, kind) = match event {
+                Event::Copy | Event::Cut => {
+                    if self.cursors.primary().is_empty() && !self.cursors.is_multiple() {
+                        continue;
+                    }
+                    let copied = self.cursors.copy(&self.buffer);
+                    ui.ctx().output_mut(|o| o.copied_text = copied);
+                    if event == Event::Copy || view.read_only {
+                        continue;
+                    }
+                    (self.cursors.insert(&mut self.buffer, ""), EditKind::Other)
+                }
+                Event::Paste(text) if !view.read_only => {
+                    (self.cursors.paste(&mut self.buffer, &text), EditKind::Other)
+                }
+                Event::Text(text) if !view.read_only && !text.chars().any(char::is_control) => (
+                    self.cursors.insert(&mut self.buffer, &text),
+                    EditKind::Typing,
+                ),
+                // The text being composed is inserted and selected, so the next update replaces it
+                Event::CompositionUpdate(text) if !view.read_only => {
+                    let edits = self.cursors.insert(&mut self.buffer, &text);
+                    self.cursors.select_inserted(&edits);
+                    self.record(edits, before, EditKind::Typing, time);
+                    changed = true;
+                    continue;
+                }
+                Event::CompositionEnd(text) if !view.read_only => (
+                    self.cursors.insert(&mut self.buffer, &text),
+                    EditKind::Typing,
+                ),
+                Event::Key {
+                    key,
+                    pressed: true,
+                    modifiers,
+                    ..
+                } => match self.handle_key(key, modifiers, view.read_only) {
+                    Some(edits) => (edits, EditKind::Other),
+                    None => {
+                        if self.cursors.selections() != before.selections() {
+                            self.scroll_to_caret = Some(None);
+                        }
+                        continue;
+                    }
+                },
+                _ => continue,
+            };
+            self.record(edits, before, kind, time);
+            changed = true;
+        }
+        changed
+    }
+
+    /// Handle a key press. Returns the edits if it changed the text.
+    fn handle_key(&mut self, key: Key, modifiers: Modifiers, read_only: bool) -> Option<Vec<Edit>> {
+        let word = modifiers.command;
+        let extend = modifiers.shift;
+        let page = self.page_rows as isize;
+        let rows = match key {
+            Key::ArrowUp => Some(-1),
+            Key::ArrowDown => Some(1),
+            Key::PageUp => Some(-page),
+            Key::PageDown => Some(page),
+            _ => None,
+        };
+        if let Some(rows) = rows {
+            self.move_vertically(rows, extend);
+            return None;
+        }
+        let motion = match key {
+            Key::ArrowLeft if word => Some(Motion::WordLeft),
+            Key::ArrowLeft => Some(Motion::Left),
+            Key::ArrowRight if word => Some(Motion::WordRight),
+            Key::ArrowRight => Some(Motion::Right),
+            Key::Home if word => Some(Motion::DocumentStart),
+            Key::Home => Some(Motion::Home),
+            Key::End if word => Some(Motion::DocumentEnd),
+            Key::End => Some(Motion::End),
+            _ => None,
+        };
+        if let Some(motion) = motion {
+            self.cursors.move_carets(&self.buffer, motion, extend);
+            return None;
+        }
+        match key {
+            Key::A if modifiers.command => self.cursors.select_all(&self.buffer),
+            Key::D if modifiers.command => self.cursors.add_next_occurrence(&self.buffer),
+            Key::Escape => self.cursors.collapse(),
+            Key::Z if modifiers.command && !read_only => {
+                if modifiers.shift {
+                    self.redo_last();
+                } else {
+                    self.undo_last();
+                }
+            }
+            Key::Y if modifiers.command && !read_only => self.redo_last(),
+            _ if read_only => {}
+            Key::Enter => return Some(self.cursors.insert(&mut self.buffer, "\n")),
+            Key::Tab if modifiers.is_none() => {
+                return Some(self.cursors.insert(&mut self.buffer, "\t"))
+            }
+            Key::Backspace => {
+                let motion = if word { Motion::WordLeft } else { Motion::Left };
+                return Some(self.cursors.delete(&mut self.buffer, motion));
+            }
+            Key::Delete => {
+                let motion = if word {
+                    Motion::WordRight
+                } else {
+                    Motion::Right
+                };
+                return Some(self.cursors.delete(&mut self.buffer, motion));
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// Put `edits` on the undo stack, merging consecutive typing.
+    fn record(&mut self, edits: Vec<Edit>, before: MultiCursor, kind: EditKind, time: f64) {
+        self.unhighlighted.1.extend(edits.iter().cloned());
+        self.edited();
+        self.redo.clear();
+        let after = self.cursors.clone();
+        match self.undo.last_mut() {
+            Some(last)
+                if kind == EditKind::Typing
+                    && last.kind == kind
+                    && time - last.time < UNDO_MERGE_SECS =>
+            {
+                last.edits.extend(edits);
+                last.after = after;
+                last.time = time;
+            }
+            _ => self.undo.push(Transaction {
+                edits,
+                before,
+                after,
+                kind,
+                time,
+            }),
+        }
+    }
+
+    fn undo_last(&mut self) {
+        if let Some(transaction) = self.undo.pop() {
+            for edit in transaction.edits.iter().rev() {
+                edit.revert(&mut self.buffer);
+                self.unhighlighted.1.push(edit.inverted());
+            }
+            self.cursors = transaction.before.clone();
+            self.cursors.clamp(&self.buffer);
+            self.redo.push(transaction);
+            self.edited();
+        }
+    }
+
+    fn redo_last(&mut self) {
+        if let Some(transaction) = self.redo.pop() {
+            for edit in &transaction.edits {
+                edit.apply(&mut self.buffer);
+                self.unhighlighted.1.push(edit.clone());
+            }
+            self.cursors = transaction.after.clone();
+            self.cursors.clamp(&self.buffer);
+            self.undo.push(transaction);
+            self.edited();
+        }
+    }
+
+    fn edited(&mut self) {
+        self.version = next_version();
+        self.longest_line = None;
+        self.scroll_to_caret = Some(None);
+    }
+
+    fn paint(
+        &self,
+        ui: &Ui,
+        response: &egui::Response,
+        layout: &Layout,
+        view: &EditorView<'_>,
+        gutter: Option<Gutter>,
+        rows: &[VisibleRow],
+    ) {
+        let painter = ui.painter();
+        let clip = ui.clip_rect();
+        let colors = &view.colors;
+        painter.rect_filled(clip, 0.0, colors.background);
+        let rect = |row: &VisibleRow| layout.row_rect(row.row, clip);
+
+        let primary = self.cursors.primary();
+        if primary.is_empty() {
+            for row in rows.iter().filter(|r| r.line == primary.head.line) {
+                painter.rect_filled(rect(row), 0.0, colors.line_highlight);
+            }
+        }
+        if let Some(column) = view.ruler {
+            let x = layout.origin.x + column as f32 * layout.char_width;
+            painter.vline(
+                x,
+                clip.y_range(),
+                egui::Stroke::new(1.0, colors.gutter_foreground.gamma_multiply(0.5)),
+            );
+        }
+
+        let focused = response.has_focus();
+        for row in rows {
+            let top_left = Pos2::new(layout.origin.x, rect(row).top());
+            for s in self.cursors.selections() {
+                if let Some(columns) = row.selected_columns(s) {
+                    let x = |column| top_left.x + column_x(&row.galley, column);
+                    let end = if columns.end > row.columns.len() {
+                        // The line break is selected too
+                        x(columns.end - 1) + layout.char_width
+                    } else {
+                        x(columns.end)
+                    };
+                    painter.rect_filled(
+                        Rect::from_x_y_ranges(x(columns.start)..=end, rect(row).y_range()),
+                        0.0,
+                        colors.selection,
+                    );
+                }
+            }
+            painter.galley(top_left, row.galley.clone());
+            if row.split && !row.last {
+                // The line goes on in the next row
+                painter.text(
+                    top_left + Vec2::new(row.galley.size().x, 0.0),
+                    Align2::LEFT_TOP,
+                    SPLIT_MARKER,
+                    view.font_id.clone(),
+                    colors.gutter_foreground,
+                );
+            }
+            if !focused {
+                continue;
+            }
+            for s in self.cursors.selections() {
+                if s.head.line != row.line || self.rows.row_of(s.head) != row.row {
+                    continue;
+                }
+                let x = column_x(&row.galley, s.head.column - row.columns.start);
+                let caret = layout.caret_rect(row.row, x);
+                painter.rect_filled(
+                    Rect::from_min_size(
+                        caret.min,
+                        Vec2::new(ui.visuals().text_cursor_width, caret.height()),
+                    ),
+                    0.0,
+                    colors.caret,
+                );
+                if *s == primary {
+                    // Where the input method shows its candidates
+                    ui.ctx()
+                        .output_mut(|o| o.text_cursor_pos = Some(caret.left_bottom()));
+                }
+            }
+        }
+
+        if let Some(mut gutter) = gutter {
+            gutter.current_line = Some(primary.head.line);
+            // Stays put when scrolling sideways
+            let gutter_rect = Rect::from_x_y_ranges(
+                clip.left()..=clip.left() + layout.gutter_width,
+                clip.y_range(),
+            );
+            gutter.paint(
+                ui,
+                gutter_rect,
+                rows.iter().map(|r| (r.line, rect(r), r.index > 0)),
+            );
+        }
+    }
+}
+
+/// A row on screen, laid out.
+struct VisibleRow {
+    row: usize,
+    line: usize,
+    /// Which row of the line it is
+    index: usize,
+    /// Columns of the line in this row
+    columns: Range<usize>,
+    /// Whether it is the last row of the line
+    last: bool,
+    /// Whether the line is split because it is too long
+    split: bool,
+    galley: Arc<Galley>,
+}
+
+impl VisibleRow {
+    /// Columns of this row, counted from its start, that `selection` covers. One past the row's
+    /// length if the line break is selected.
+    fn selected_columns(&self, selection: &Selection) -> Option<Range<usize>> {
+        let range = selection.range();
+        if selection.is_empty() || self.line < range.start.line || self.line > range.end.line {
+            return None;
+        }
+        let start = if self.line == range.start.line {
+            range.start.column.max(self.columns.start)
+        } else {
+            self.columns.start
+        };
+        let end = if self.line == range.end.line {
+            range.end.column.min(self.columns.end)
+        } else if self.last {
+            self.columns.end + 1
+        } else {
+            self.columns.end
+        };
+        (start < end).then(|| start - self.columns.start..end - self.columns.start)
+    }
+}
+
+/// Where the rows of the editor are on screen.
+struct Layout {
+    /// Top left corner of the text of the first row
+    origin: Pos2,
+    row_height: f32,
+    char_width: f32,
+    gutter_width: f32,
+}
+
+impl Layout {
+    fn row_at(&self, y: f32, row_count: usize) -> usize {
+        (((y - self.origin.y) / self.row_height).max(0.0) as usize).min(row_count - 1)
+    }
+
+    /// Screen rect of `row`, as wide as `clip`.
+    fn row_rect(&self, row: usize, clip: Rect) -> Rect {
+        let top = self.origin.y + row as f32 * self.row_height;
+        Rect::from_x_y_ranges(clip.x_range(), top..=top + self.row_height)
+    }
+
+    /// A caret `x` points right of the start of `row`.
+    fn caret_rect(&self, row: usize, x: f32) -> Rect {
+        let top = self.origin.y + row as f32 * self.row_height;
+        Rect::from_min_size(
+            Pos2::new(self.origin.x + x, top),
+            Vec2::new(0.0, self.row_height),
+        )
+    }
+}
+
+/// Distance of char `column` from the start of the row in `galley`.
+fn column_x(galley: &Galley, column: usize) -> f32 {
+    galley
+        .pos_from_cursor(&galley.from_ccursor(CCursor::new(column)))
+        .min
+        .x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn type_text(editor: &mut Editor, text: &str, time: f64) {
+        let before = editor.cursors.clone();
+        let edits = editor.cursors.insert(&mut editor.buffer, text);
+        editor.record(edits, before, EditKind::Typing, time);
+    }
+
+    #[test]
+    fn undo_merges_typing() {
+        let mut editor = Editor::new("a\r\nb");
+        editor.go_to(Position::new(1, 1));
+        let version = editor.version();
+        type_text(&mut editor, "c", 0.0);
+        type_text(&mut editor, "d", 0.5);
+        type_text(&mut editor, "e", 2.0);
+        assert_ne!(editor.version(), version);
+        assert_eq!(editor.text(), "a\r\nbcde");
+
+        editor.undo_last();
+        assert_eq!(editor.text(), "a\r\nbcd");
+        editor.undo_last();
+        assert_eq!(editor.text(), "a\r\nb");
+        assert_eq!(editor.primary(), Selection::caret(Position::new(1, 1)));
+        editor.redo_last();
+        assert_eq!(editor.text(), "a\r\nbcd");
+        assert_eq!(editor.primary(), Selection::caret(Position::new(1, 3)));
+    }
+
+    #[test]
+    fn edits_for_the_highlighter() {
+        let mut editor = Editor::new("a\r\nb");
+        let mut highlighted = editor.buffer.clone();
+        editor.go_to(Position::new(1, 1));
+        type_text(&mut editor, "c\nd", 0.0);
+        type_text(&mut editor, "é", 5.0);
+        editor.undo_last();
+        editor.undo_last();
+        editor.redo_last();
+
+        let (from, edits) = &editor.unhighlighted;
+        assert_ne!(*from, editor.version());
+        for edit in edits {
+            edit.apply(&mut highlighted);
+        }
+        assert_eq!(highlighted.text("\r\n"), editor.text());
+        assert_eq!(editor.text(), "a\r\nbc\r\nd");
+
+        for encoding in Encoding::ALL {
+            let bytes = encoding.encode(&editor.text());
+            assert_eq!(
+                editor.encoded_len(encoding),
+                bytes.len(),
+                "{}",
+                encoding.name()
+            );
+        }
+    }
+}
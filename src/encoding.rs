@@ -0,0 +1,170 @@
+//! Text encodings and line endings of opened files.
+
+/// How the bytes of a file are turned into text.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, serde::Deserialize, serde::Serialize)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    Utf8Bom,
+    Utf16Le,
+    Utf16Be,
+    /// Every byte is one character. Used for anything that isn't valid UTF-8.
+    Latin1,
+}
+
+const UTF8_BOM: &[u8] = &[0xef, 0xbb, 0xbf];
+const UTF16LE_BOM: &[u8] = &[0xff, 0xfe];
+const UTF16BE_BOM: &[u8] = &[0xfe, 0xff];
+
+impl Encoding {
+    pub const ALL: [Self; 5] = [
+        Self::Utf8,
+        Self::Utf8Bom,
+        Self::Utf16Le,
+        Self::Utf16Be,
+        Self::Latin1,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Utf8 => "UTF-8",
+            Self::Utf8Bom => "UTF-8 with BOM",
+            Self::Utf16Le => "UTF-16 LE",
+            Self::Utf16Be => "UTF-16 BE",
+            Self::Latin1 => "Latin-1",
+        }
+    }
+
+    /// Guess from the byte order mark, falling back to Latin-1 if `bytes` aren't UTF-8.
+    /// `bytes` may be cut off anywhere, like the window of a large file.
+    pub fn detect(bytes: &[u8]) -> Self {
+        if bytes.starts_with(UTF8_BOM) {
+            Self::Utf8Bom
+        } else if bytes.starts_with(UTF16LE_BOM) {
+            Self::Utf16Le
+        } else if bytes.starts_with(UTF16BE_BOM) {
+            Self::Utf16Be
+        } else {
+            match std::str::from_utf8(bytes) {
+                // An incomplete character at the end is fine
+                Err(e) if e.error_len().is_some() => Self::Latin1,
+                _ => Self::Utf8,
+            }
+        }
+    }
+
+    fn bom(self) -> &'static [u8] {
+        match self {
+            Self::Utf8Bom => UTF8_BOM,
+            Self::Utf16Le => UTF16LE_BOM,
+            Self::Utf16Be => UTF16BE_BOM,
+            Self::Utf8 | Self::Latin1 => &[],
+        }
+    }
+
+    pub fn decode(self, bytes: &[u8]) -> String {
+        let bytes = bytes.strip_prefix(self.bom()).unwrap_or(bytes);
+        match self {
+            Self::Utf8 | Self::Utf8Bom => String::from_utf8_lossy(bytes).into_owned(),
+            Self::Utf16Le | Self::Utf16Be => {
+                let units = bytes
+                    .chunks_exact(2)
+                    .map(|c| {
+                        let pair = [c[0], c[1]];
+                        if self == Self::Utf16Le {
+                            u16::from_le_bytes(pair)
+                        } else {
+                            u16::from_be_bytes(pair)
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                String::from_utf16_lossy(&units)
+            }
+            Self::Latin1 => bytes.iter().map(|b| char::from(*b)).collect(),
+        }
+    }
+
+    /// The bytes of `text` including the byte order mark, if the encoding has one.
+    pub fn encode(self, text: &str) -> Vec<u8> {
+        let mut bytes = self.bom().to_vec();
+        bytes.extend(self.encode_text(text));
+        bytes
+    }
+
+    /// Size of `text` in this encoding, without byte order mark.
+    pub fn encoded_len(self, text: &str) -> usize {
+        match self {
+            Self::Utf8 | Self::Utf8Bom => text.len(),
+            Self::Utf16Le | Self::Utf16Be => text.encode_utf16().count() * 2,
+            Self::Latin1 => text.chars().count(),
+        }
+    }
+
+    fn encode_text(self, text: &str) -> Vec<u8> {
+        match self {
+            Self::Utf8 | Self::Utf8Bom => text.as_bytes().to_vec(),
+            Self::Utf16Le => text.encode_utf16().flat_map(u16::to_le_bytes).collect(),
+            Self::Utf16Be => text.encode_utf16().flat_map(u16::to_be_bytes).collect(),
+            Self::Latin1 => text
+                .chars()
+                .map(|c| u8::try_from(c).unwrap_or(b'?'))
+                .collect(),
+        }
+    }
+}
+
+/// The line breaks used in a text.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+    Cr,
+    /// More than one kind
+    Mixed,
+}
+
+impl LineEnding {
+    /// The ones a text can be converted to
+    pub const ALL: [Self; 3] = [Self::Lf, Self::CrLf, Self::Cr];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Lf => "LF",
+            Self::CrLf => "CRLF",
+            Self::Cr => "CR",
+            Self::Mixed => "Mixed",
+        }
+    }
+
+    /// The line break itself. Mixed ones are written as LF.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Lf | Self::Mixed => "\n",
+            Self::CrLf => "\r\n",
+            Self::Cr => "\r",
+        }
+    }
+
+    /// `None` if `text` has no line breaks at all.
+    pub fn detect(text: &str) -> Option<Self> {
+        let mut found = None;
+        let mut bytes = text.bytes().peekable();
+        while let Some(b) = bytes.next() {
+            let ending = match b {
+                b'\n' => Self::Lf,
+                b'\r' if bytes.peek() == Some(&b'\n') => {
+                    bytes.next();
+                    Self::CrLf
+                }
+                b'\r' => Self::Cr,
+                _ => continue,
+            };
+            match found {
+                None => found = Some(ending),
+                Some(f) if f != ending => return Some(Self::Mixed),
+                Some(_) => {}
+            }
+        }
+        found
+    }
+}
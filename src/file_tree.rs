@@ -0,0 +1,306 @@
+use egui::Ui;
+use log::warn;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+#[derive(Clone)]
+struct Entry {
+    path: PathBuf,
+    is_dir: bool,
+}
+
+/// A file system operation waiting for user input or confirmation.
+enum Action {
+    NewFile { parent: PathBuf, name: String },
+    NewFolder { parent: PathBuf, name: String },
+    Rename { path: PathBuf, name: String },
+    Delete { path: PathBuf },
+}
+
+/// Something the app needs to react to.
+pub enum TreeEvent {
+    Open(PathBuf),
+    Renamed { from: PathBuf, to: PathBuf },
+    Deleted(PathBuf),
+}
+
+/// Lazily loaded directory tree. Directories are only read once they are expanded.
+#[derive(Default)]
+pub struct FileTree {
+    expanded: HashSet<PathBuf>,
+    children: HashMap<PathBuf, Vec<Entry>>,
+    action: Option<Action>,
+    error: Option<String>,
+    scroll_to: Option<PathBuf>,
+}
+
+impl FileTree {
+    /// Forget all cached directory listings, so they are read again.
+    pub fn refresh(&mut self) {
+        self.children.clear();
+    }
+
+    /// Expand all parents of `path` and scroll it into view.
+    pub fn reveal(&mut self, root: &Path, path: &Path) {
+        if !path.starts_with(root) {
+            return;
+        }
+        for parent in path.ancestors().skip(1) {
+            self.expanded.insert(parent.to_path_buf());
+            if parent == root {
+                break;
+            }
+        }
+        self.scroll_to = Some(path.to_path_buf());
+    }
+
+    pub fn ui(&mut self, ui: &mut Ui, root: &Path, active: Option<&Path>) -> Vec<TreeEvent> {
+        let mut events = vec![];
+
+        let root_name = root
+            .file_name()
+            .map(|f| f.to_string_lossy().to_string())
+            .unwrap_or_else(|| root.display().to_string());
+        ui.label(egui::RichText::new(root_name).strong())
+            .on_hover_text(root.display().to_string())
+            .context_menu(|ui| self.dir_menu(ui, root));
+
+        self.dir_ui(ui, root, active, &mut events);
+        self.action_window(ui.ctx(), &mut events);
+        events
+    }
+
+    fn dir_ui(
+        &mut self,
+        ui: &mut Ui,
+        dir: &Path,
+        active: Option<&Path>,
+        events: &mut Vec<TreeEvent>,
+    ) {
+        let entries = self
+            .children
+            .entry(dir.to_path_buf())
+            .or_insert_with(|| read_dir(dir))
+            .clone();
+
+        for entry in entries {
+            let name = entry
+                .path
+                .file_name()
+                .map(|f| f.to_string_lossy().to_string())
+                .unwrap_or_default();
+
+            if entry.is_dir {
+                let expanded = self.expanded.contains(&entry.path);
+                let icon = if expanded { "📂" } else { "📁" };
+                let response = ui.selectable_label(false, format!("{icon} {name}"));
+                if response.clicked() {
+                    if expanded {
+                        self.expanded.remove(&entry.path);
+                    } else {
+                        self.expanded.insert(entry.path.clone());
+                    }
+                }
+                response.context_menu(|ui| {
+                    self.dir_menu(ui, &entry.path);
+                    self.entry_menu(ui, &entry.path, &name);
+                });
+                if expanded {
+                    ui.indent(&entry.path, |ui| {
+                        self.dir_ui(ui, &entry.path, active, events);
+                    });
+                }
+            } else {
+                let selected = active == Some(entry.path.as_path());
+                let response =
+                    ui.selectable_label(selected, format!("{} {name}", file_icon(&entry.path)));
+                if self.scroll_to.as_ref() == Some(&entry.path) {
+                    response.scroll_to_me(Some(egui::Align::Center));
+                    self.scroll_to = None;
+                }
+                if response.clicked() {
+                    events.push(TreeEvent::Open(entry.path.clone()));
+                }
+                response.context_menu(|ui| self.entry_menu(ui, &entry.path, &name));
+            }
+        }
+    }
+
+    fn dir_menu(&mut self, ui: &mut Ui, dir: &Path) {
+        if ui.button("New file").clicked() {
+            self.action = Some(Action::NewFile {
+                parent: dir.to_path_buf(),
+                name: String::new(),
+            });
+            ui.close_menu();
+        }
+        if ui.button("New folder").clicked() {
+            self.action = Some(Action::NewFolder {
+                parent: dir.to_path_buf(),
+                name: String::new(),
+            });
+            ui.close_menu();
+        }
+    }
+
+    fn entry_menu(&mut self, ui: &mut Ui, path: &Path, name: &str) {
+        if ui.button("Rename").clicked() {
+            self.action = Some(Action::Rename {
+                path: path.to_path_buf(),
+                name: name.to_string(),
+            });
+            ui.close_menu();
+        }
+        if ui.button("Delete").clicked() {
+            self.action = Some(Action::Delete {
+                path: path.to_path_buf(),
+            });
+            ui.close_menu();
+        }
+    }
+
+    fn action_window(&mut self, ctx: &egui::Context, events: &mut Vec<TreeEvent>) {
+        let Some(action) = &mut self.action else {
+            return;
+        };
+
+        let title = match action {
+            Action::NewFile { .. } => "New file",
+            Action::NewFolder { .. } => "New folder",
+            Action::Rename { .. } => "Rename",
+            Action::Delete { .. } => "Delete",
+        };
+
+        let mut confirmed = false;
+        let mut cancelled = false;
+        egui::Window::new(title)
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .show(ctx, |ui| {
+                match action {
+                    Action::NewFile { name, .. }
+                    | Action::NewFolder { name, .. }
+                    | Action::Rename { name, .. } => {
+                        let response = ui.text_edit_singleline(name);
+                        response.request_focus();
+                        if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                            confirmed = true;
+                        }
+                    }
+                    Action::Delete { path } => {
+                        ui.label(format!("Delete {}?", path.display()));
+                    }
+                }
+                if let Some(error) = &self.error {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                }
+                ui.horizontal(|ui| {
+                    confirmed |= ui.button("Ok").clicked();
+                    cancelled = ui.button("Cancel").clicked()
+                        || ui.input(|i| i.key_pressed(egui::Key::Escape));
+                });
+            });
+
+        if cancelled {
+            self.action = None;
+            self.error = None;
+        } else if confirmed {
+            match self.apply(events) {
+                Ok(()) => {
+                    self.action = None;
+                    self.error = None;
+                }
+                Err(e) => {
+                    warn!("{title} failed: {e}");
+                    self.error = Some(e.to_string());
+                }
+            }
+        }
+    }
+
+    fn apply(&mut self, events: &mut Vec<TreeEvent>) -> std::io::Result<()> {
+        let Some(action) = &self.action else {
+            return Ok(());
+        };
+        match action {
+            Action::NewFile { parent, name } => {
+                let path = parent.join(name);
+                fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(&path)?;
+                self.children.remove(parent);
+                events.push(TreeEvent::Open(path));
+            }
+            Action::NewFolder { parent, name } => {
+                fs::create_dir(parent.join(name))?;
+                self.children.remove(parent);
+                self.expanded.insert(parent.clone());
+            }
+            Action::Rename { path, name } => {
+                let to = path.with_file_name(name);
+                fs::rename(path, &to)?;
+                if let Some(parent) = path.parent() {
+                    self.children.remove(parent);
+                }
+                events.push(TreeEvent::Renamed {
+                    from: path.clone(),
+                    to,
+                });
+            }
+            Action::Delete { path } => {
+                if path.is_dir() {
+                    fs::remove_dir_all(path)?;
+                } else {
+                    fs::remove_file(path)?;
+                }
+                if let Some(parent) = path.parent() {
+                    self.children.remove(parent);
+                }
+                events.push(TreeEvent::Deleted(path.clone()));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// List a directory, directories first, skipping everything a .gitignore excludes.
+fn read_dir(dir: &Path) -> Vec<Entry> {
+    let mut entries = ignore::WalkBuilder::new(dir)
+        .max_depth(Some(1))
+        .hidden(false)
+        .filter_entry(|e| e.file_name() != ".git")
+        .build()
+        .filter_map(|e| e.map_err(|e| warn!("{e}")).ok())
+        .filter(|e| e.depth() == 1)
+        .map(|e| Entry {
+            is_dir: e.file_type().map(|t| t.is_dir()).unwrap_or_default(),
+            path: e.into_path(),
+        })
+        .collect::<Vec<_>>();
+    entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.path.cmp(&b.path)));
+    entries
+}
+
+fn file_icon(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match ext.as_str() {
+        "rs" => "🦀",
+        "png" | "jpg" | "jpeg" | "gif" | "bmp" | "svg" | "webp" | "ico" => "🖼",
+        "toml" | "yaml" | "yml" | "json" | "ini" | "cfg" | "conf" => "⚙",
+        "md" | "txt" | "rst" => "🗒",
+        "csv" | "tsv" => "🗐",
+        "zip" | "tar" | "gz" | "xz" | "7z" => "📦",
+        "mp3" | "wav" | "ogg" | "flac" => "🎵",
+        "mp4" | "mkv" | "webm" | "mov" => "🎞",
+        "log" => "📜",
+        _ => "🗋",
+    }
+}
@@ -0,0 +1,439 @@
+//! Pretty printing and minifying JSON, XML, YAML and TOML documents.
+
+use crate::line_buffer::Position;
+use std::{fmt, path::Path};
+use yaml_rust::{Yaml, YamlEmitter, YamlLoader};
+
+/// Spaces per level of nesting in formatted documents
+const INDENT: usize = 2;
+
+/// A document language we can format.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Format {
+    Json,
+    Xml,
+    Yaml,
+    Toml,
+}
+
+/// Why a document doesn't parse, and where.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ParseError {
+    pub message: String,
+    /// Zero-based
+    pub pos: Position,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Line {}, column {}: {}",
+            self.pos.line + 1,
+            self.pos.column + 1,
+            self.message
+        )
+    }
+}
+
+impl Format {
+    /// By the name of the syntax the document is highlighted with, then by its extension.
+    pub fn detect(syntax: Option<&str>, path: &Path) -> Option<Self> {
+        let by_syntax = syntax.and_then(|s| match s.to_ascii_lowercase().as_str() {
+            "json" => Some(Self::Json),
+            "xml" => Some(Self::Xml),
+            "yaml" => Some(Self::Yaml),
+            "toml" => Some(Self::Toml),
+            _ => None,
+        });
+        let extension = path.extension()?.to_string_lossy().to_ascii_lowercase();
+        by_syntax.or(match extension.as_str() {
+            "json" => Some(Self::Json),
+            "xml" | "svg" | "xsd" | "xsl" | "xslt" | "plist" => Some(Self::Xml),
+            "yaml" | "yml" => Some(Self::Yaml),
+            "toml" => Some(Self::Toml),
+            _ => None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Json => "JSON",
+            Self::Xml => "XML",
+            Self::Yaml => "YAML",
+            Self::Toml => "TOML",
+        }
+    }
+
+    /// `text` indented one element per line, or with as little whitespace as possible if
+    /// `minify`. Comments are kept in XML and dropped in YAML and TOML. Ends with a line break if `text`
+    /// does.
+    pub fn apply(self, text: &str, minify: bool) -> Result<String, ParseError> {
+        let formatted = match self {
+            Self::Json => json(text, minify),
+            Self::Xml => xml(text, minify),
+            Self::Yaml => yaml(text, minify),
+            Self::Toml => toml(text, minify),
+        }?;
+        let mut formatted = formatted.trim_end().to_string();
+        if text.ends_with('\n') {
+            formatted.push('\n');
+        }
+        Ok(formatted)
+    }
+}
+
+/// Line and column of `offset`, a byte index into `text`.
+pub fn position_of(text: &str, offset: usize) -> Position {
+    let mut offset = offset.min(text.len());
+    while !text.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    Position::new(
+        before.matches('\n').count(),
+        before[line_start..].chars().count(),
+    )
+}
+
+fn newline(out: &mut String, depth: usize) {
+    out.push('\n');
+    out.extend(std::iter::repeat(' ').take(depth * INDENT));
+}
+
+/// Whether `text` is valid JSON.
+pub fn check_json(text: &str) -> Result<(), ParseError> {
+    if let Err(e) = serde_json::from_str::<serde::de::IgnoredAny>(text) {
+        let line_start = text
+            .split_inclusive('\n')
+            .take(e.line().saturating_sub(1))
+            .map(str::len)
+            .sum::<usize>();
+        let message = e.to_string();
+        let message = message.rsplit_once(" at line ").map_or(&*message, |m| m.0);
+        return Err(ParseError {
+            message: message.into(),
+            pos: position_of(text, line_start + e.column().saturating_sub(1)),
+        });
+    }
+    Ok(())
+}
+
+/// Only whitespace between tokens changes, so numbers and key order stay exactly as they were.
+fn json(text: &str, minify: bool) -> Result<String, ParseError> {
+    check_json(text)?;
+
+    let bytes = text.as_bytes();
+    let next_token = |from: usize| {
+        bytes[from..]
+            .iter()
+            .position(|b| !b.is_ascii_whitespace())
+            .map_or(bytes.len(), |i| from + i)
+    };
+    let mut out = String::with_capacity(text.len());
+    let mut depth = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' => {
+                let mut end = i + 1;
+                while bytes[end] != b'"' {
+                    end += if bytes[end] == b'\\' { 2 } else { 1 };
+                }
+                out.push_str(&text[i..=end]);
+                i = end;
+            }
+            open @ (b'{' | b'[') => {
+                out.push(open as char);
+                let next = next_token(i + 1);
+                if matches!(bytes.get(next), Some(b'}' | b']')) {
+                    out.push(bytes[next] as char);
+                    i = next;
+                } else {
+                    depth += 1;
+                    if !minify {
+                        newline(&mut out, depth);
+                    }
+                }
+            }
+            close @ (b'}' | b']') => {
+                depth -= 1;
+                if !minify {
+                    newline(&mut out, depth);
+                }
+                out.push(close as char);
+            }
+            b',' => {
+                out.push(',');
+                if !minify {
+                    newline(&mut out, depth);
+                }
+            }
+            b':' => out.push_str(if minify { ":" } else { ": " }),
+            b if b.is_ascii_whitespace() => {}
+            // Outside of strings valid JSON is ASCII
+            b => out.push(b as char),
+        }
+        i += 1;
+    }
+    Ok(out)
+}
+
+fn xml(text: &str, minify: bool) -> Result<String, ParseError> {
+    use quick_xml::{events::Event, Reader, Writer};
+
+    let mut reader = Reader::from_str(text);
+    reader.trim_text(true);
+    let mut writer = if minify {
+        Writer::new(Vec::new())
+    } else {
+        Writer::new_with_indent(Vec::new(), b' ', INDENT)
+    };
+    let error = |message: String, offset: usize| ParseError {
+        message,
+        pos: position_of(text, offset),
+    };
+    // Names of the elements we are in, which quick-xml doesn't check at the end
+    let mut open = vec![];
+    loop {
+        let event = reader
+            .read_event()
+            .map_err(|e| error(e.to_string(), reader.buffer_position()))?;
+        match &event {
+            Event::Eof => break,
+            Event::Start(start) => open.push(start.name().as_ref().to_vec()),
+            Event::End(_) => {
+                open.pop();
+            }
+            _ => {}
+        }
+        writer
+            .write_event(event)
+            .map_err(|e| error(e.to_string(), reader.buffer_position()))?;
+    }
+    if let Some(name) = open.last() {
+        return Err(error(
+            format!(
+                "Element <{}> is never closed",
+                String::from_utf8_lossy(name)
+            ),
+            text.len(),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&writer.into_inner()).into_owned())
+}
+
+/// The documents in `text`.
+pub fn load_yaml(text: &str) -> Result<Vec<Yaml>, ParseError> {
+    YamlLoader::load_from_str(text).map_err(|e| ParseError {
+        message: e.to_string(),
+        pos: Position::new(e.marker().line().saturating_sub(1), e.marker().col()),
+    })
+}
+
+fn yaml(text: &str, minify: bool) -> Result<String, ParseError> {
+    let documents = load_yaml(text)?;
+    let emit_error = |e: yaml_rust::EmitError| ParseError {
+        message: format!("Can't write the document: {e:?}"),
+        pos: Position::default(),
+    };
+    let mut out = String::new();
+    for (i, document) in documents.iter().enumerate() {
+        let mut emitted = String::new();
+        if minify {
+            flow_yaml(document, &mut emitted).map_err(emit_error)?;
+        } else {
+            YamlEmitter::new(&mut emitted)
+                .dump(document)
+                .map_err(emit_error)?;
+            emitted = emitted
+                .strip_prefix("---\n")
+                .unwrap_or(&emitted)
+                .to_string();
+        }
+        // Only separate documents if there is more than one
+        if documents.len() > 1 {
+            if i > 0 {
+                out.push('\n');
+            }
+            out.push_str(if minify { "--- " } else { "---\n" });
+        }
+        out.push_str(&emitted);
+    }
+    Ok(out)
+}
+
+/// `node` on one line, in flow style.
+fn flow_yaml(node: &Yaml, out: &mut String) -> Result<(), yaml_rust::EmitError> {
+    match node {
+        Yaml::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                flow_yaml(item, out)?;
+            }
+            out.push(']');
+        }
+        Yaml::Hash(entries) => {
+            out.push('{');
+            for (i, (key, value)) in entries.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                flow_yaml(key, out)?;
+                out.push_str(": ");
+                flow_yaml(value, out)?;
+            }
+            out.push('}');
+        }
+        scalar => {
+            // The emitter knows when scalars need quotes
+            let mut emitted = String::new();
+            YamlEmitter::new(&mut emitted).dump(scalar)?;
+            out.push_str(emitted.strip_prefix("---\n").unwrap_or(&emitted).trim());
+        }
+    }
+    Ok(())
+}
+
+/// Key order and the way values are written stay as they were.
+fn toml(text: &str, minify: bool) -> Result<String, ParseError> {
+    use toml_edit::{Document, Item, Table, Value};
+
+    fn fmt_value(value: &mut Value) {
+        value.decor_mut().clear();
+        match value {
+            Value::Array(array) => {
+                array.iter_mut().for_each(fmt_value);
+                array.fmt();
+            }
+            Value::InlineTable(table) => {
+                table.iter_mut().for_each(|(_, v)| fmt_value(v));
+                table.fmt();
+            }
+            _ => {}
+        }
+    }
+
+    /// Inline tables and arrays of them become sections of their own.
+    fn expand(table: &mut Table) {
+        for (mut key, item) in table.iter_mut() {
+            key.fmt();
+            let expandable = match &*item {
+                Item::Value(Value::InlineTable(_)) => true,
+                Item::Value(Value::Array(array)) => {
+                    !array.is_empty() && array.iter().all(Value::is_inline_table)
+                }
+                _ => false,
+            };
+            if expandable {
+                let value = std::mem::take(item);
+                *item = match value {
+                    Item::Value(Value::InlineTable(t)) => Item::Table(t.into_table()),
+                    other => other
+                        .into_array_of_tables()
+                        .map_or_else(|i| i, Item::ArrayOfTables),
+                };
+            }
+            match item {
+                Item::Table(t) => expand(t),
+                Item::ArrayOfTables(tables) => tables.iter_mut().for_each(expand),
+                Item::Value(v) => fmt_value(v),
+                Item::None => {}
+            }
+        }
+        table.decor_mut().clear();
+        table.fmt();
+    }
+
+    let mut document = text.parse::<Document>().map_err(|e| ParseError {
+        message: e.message().trim().to_string(),
+        pos: position_of(text, e.span().map_or(0, |s| s.start)),
+    })?;
+    document.set_trailing("");
+    let root = document.as_table_mut();
+    if minify {
+        // One line per top level key
+        for (_, item) in root.iter_mut() {
+            item.make_value();
+            if let Some(value) = item.as_value_mut() {
+                fmt_value(value);
+            }
+        }
+        root.fmt();
+    } else {
+        expand(root);
+    }
+    Ok(document.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_whitespace() {
+        let text = r#"{"a": [1, 2.50, {}], "b":{"c" :"x, \"y\""}, "e": []}"#;
+        assert_eq!(
+            Format::Json.apply(text, false).unwrap(),
+            "{\n  \"a\": [\n    1,\n    2.50,\n    {}\n  ],\n  \"b\": {\n    \"c\": \"x, \\\"y\\\"\"\n  },\n  \"e\": []\n}"
+        );
+        assert_eq!(
+            Format::Json.apply(&format!("{text}\n"), true).unwrap(),
+            "{\"a\":[1,2.50,{}],\"b\":{\"c\":\"x, \\\"y\\\"\"},\"e\":[]}\n"
+        );
+        let error = Format::Json
+            .apply("{\n  \"a\": trux\n}", false)
+            .unwrap_err();
+        assert_eq!(error.pos.line, 1);
+    }
+
+    #[test]
+    fn xml_indents_elements() {
+        let text = "<a><b x=\"1\">text</b><c/></a>";
+        assert_eq!(
+            Format::Xml.apply(text, false).unwrap(),
+            "<a>\n  <b x=\"1\">text</b>\n  <c/>\n</a>"
+        );
+        assert_eq!(
+            Format::Xml.apply("<a>\n  <b/>\n</a>\n", true).unwrap(),
+            "<a><b/></a>\n"
+        );
+        let error = Format::Xml.apply("<a>\n<b></c>\n</a>", false).unwrap_err();
+        assert_eq!(error.pos.line, 1);
+        assert!(Format::Xml.apply("<a><b></b>", false).is_err());
+    }
+
+    #[test]
+    fn yaml_flow_and_block() {
+        let text = "a: 1\nb:\n  - x\n  - \"2\"\nc: {d: true}\n";
+        assert_eq!(
+            Format::Yaml.apply(text, true).unwrap(),
+            "{a: 1, b: [x, \"2\"], c: {d: true}}\n"
+        );
+        assert_eq!(
+            Format::Yaml.apply("{a: 1, b: [x, y]}", false).unwrap(),
+            "a: 1\nb:\n  - x\n  - y"
+        );
+        let error = Format::Yaml.apply("a: 1\nb: [x\n", false).unwrap_err();
+        assert_eq!(error.pos.line, 2);
+    }
+
+    #[test]
+    fn toml_sections() {
+        let text = "b = 1\na = {x = [1,2], y = \"s\"}\n";
+        assert_eq!(
+            Format::Toml.apply(text, false).unwrap(),
+            "b = 1\n\n[a]\nx = [1, 2]\ny = \"s\"\n"
+        );
+        assert_eq!(
+            Format::Toml.apply("[t]\nk =   2\n", true).unwrap(),
+            "t = { k = 2 }\n"
+        );
+        let error = Format::Toml.apply("a = 1\nb = \n", false).unwrap_err();
+        assert_eq!(error.pos.line, 1);
+    }
+}
@@ -0,0 +1,71 @@
+use crate::syntax_highlighting::EditorColors;
+use egui::{Align2, FontId, Pos2, Rect, Ui};
+
+/// Shown in place of the line number in the rows that continue a wrapped line
+const WRAP_MARKER: &str = "↪";
+
+/// How the line number column should be drawn for one frame.
+pub struct Gutter {
+    /// Number of the first line in the buffer (one-based)
+    pub first_line: u64,
+    /// Zero-based line in the buffer the caret is on
+    pub current_line: Option<usize>,
+    /// Show the distance to the current line instead of absolute numbers
+    pub relative: bool,
+    /// Same font as the editor, so the rows line up
+    pub font_id: FontId,
+    pub colors: EditorColors,
+}
+
+impl Gutter {
+    /// Width needed to fit the largest line number of a buffer with `lines` lines.
+    pub fn width(&self, ui: &Ui, lines: usize) -> f32 {
+        let digits = (self.first_line + lines as u64).to_string().len().max(3);
+        let digit_width = ui.fonts(|f| f.glyph_width(&self.font_id, '0'));
+        digit_width * digits as f32 + 2.0 * ui.spacing().item_spacing.x
+    }
+
+    /// Paint the line numbers into `rect`. `rows` are the zero-based line, screen rect and
+    /// whether it continues a wrapped line, which gets a wrap marker instead of a number.
+    pub fn paint(&self, ui: &Ui, rect: Rect, rows: impl IntoIterator<Item = (usize, Rect, bool)>) {
+        let painter = ui.painter();
+        painter.rect_filled(rect, 0.0, self.colors.gutter);
+
+        for (line, row_rect, continued) in rows {
+            let is_current = self.current_line == Some(line);
+            if is_current {
+                painter.rect_filled(
+                    Rect::from_x_y_ranges(rect.x_range(), row_rect.y_range()),
+                    0.0,
+                    self.colors.line_highlight,
+                );
+            }
+            let pos = Pos2::new(rect.right() - ui.spacing().item_spacing.x, row_rect.top());
+            if continued {
+                painter.text(
+                    pos,
+                    Align2::RIGHT_TOP,
+                    WRAP_MARKER,
+                    self.font_id.clone(),
+                    self.colors.gutter_foreground,
+                );
+                continue;
+            }
+            let number = match self.current_line {
+                Some(current) if self.relative && !is_current => line.abs_diff(current) as u64,
+                _ => self.first_line + line as u64,
+            };
+            painter.text(
+                pos,
+                Align2::RIGHT_TOP,
+                number.to_string(),
+                self.font_id.clone(),
+                if is_current {
+                    self.colors.foreground
+                } else {
+                    self.colors.gutter_foreground
+                },
+            );
+        }
+    }
+}
@@ -1,5 +1,24 @@
 #![warn(clippy::all, rust_2018_idioms)]
 
 mod app;
+mod delimited;
+mod editor;
+mod encoding;
+mod file_tree;
+mod format;
+mod gutter;
+mod line_buffer;
+mod line_index;
+mod markdown;
+mod markdown_preview;
+mod multi_cursor;
+mod recent;
+mod settings;
 pub use app::MicronApp;
 mod syntax_highlighting;
+mod system_theme;
+mod table_view;
+mod theme_editor;
+mod tree_view;
+mod whole_file;
+mod wrap;
@@ -0,0 +1,344 @@
+//! The text of a document as a list of lines, edited in place by the editor widget.
+
+use std::ops::Range;
+
+/// A place in a [`LineBuffer`]: zero-based line and column, counted in chars.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Default, Hash)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Position {
+    pub fn new(line: usize, column: usize) -> Self {
+        Self { line, column }
+    }
+}
+
+/// Lines without their line breaks. There is always at least one.
+#[derive(Clone, Debug)]
+pub struct LineBuffer {
+    lines: Vec<String>,
+}
+
+impl Default for LineBuffer {
+    fn default() -> Self {
+        Self {
+            lines: vec![String::new()],
+        }
+    }
+}
+
+/// One replacement, with what it takes to undo it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Edit {
+    pub at: Position,
+    pub removed: String,
+    /// With `\n` line breaks, as it is in the buffer
+    pub inserted: String,
+}
+
+impl Edit {
+    pub fn apply(&self, buffer: &mut LineBuffer) {
+        buffer.remove(self.at..end_of(self.at, &self.removed));
+        buffer.insert(self.at, &self.inserted);
+    }
+
+    pub fn revert(&self, buffer: &mut LineBuffer) {
+        buffer.remove(self.at..end_of(self.at, &self.inserted));
+        buffer.insert(self.at, &self.removed);
+    }
+
+    /// The edit that undoes this one.
+    pub fn inverted(&self) -> Self {
+        Self {
+            at: self.at,
+            removed: self.inserted.clone(),
+            inserted: self.removed.clone(),
+        }
+    }
+}
+
+impl LineBuffer {
+    /// Split `text` at `\n`, `\r\n` and `\r`.
+    pub fn new(text: &str) -> Self {
+        let mut lines = vec![];
+        let bytes = text.as_bytes();
+        let mut start = 0;
+        let mut i = 0;
+        while i < bytes.len() {
+            match bytes[i] {
+                b'\n' => {
+                    lines.push(text[start..i].to_owned());
+                    start = i + 1;
+                }
+                b'\r' => {
+                    lines.push(text[start..i].to_owned());
+                    if bytes.get(i + 1) == Some(&b'\n') {
+                        i += 1;
+                    }
+                    start = i + 1;
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+        lines.push(text[start..].to_owned());
+        Self { lines }
+    }
+
+    /// All lines, joined with `line_break`.
+    pub fn text(&self, line_break: &str) -> String {
+        self.lines.join(line_break)
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn lines(&self) -> impl Iterator<Item = &str> {
+        self.lines.iter().map(String::as_str)
+    }
+
+    /// Line `line` without its line break, empty past the end.
+    pub fn line(&self, line: usize) -> &str {
+        self.lines.get(line).map_or("", String::as_str)
+    }
+
+    /// Length of `line` in chars.
+    pub fn line_len(&self, line: usize) -> usize {
+        char_len(self.line(line))
+    }
+
+    /// Length of the longest line in chars.
+    pub fn longest_line(&self) -> usize {
+        self.lines.iter().map(|l| char_len(l)).max().unwrap_or(0)
+    }
+
+    pub fn end(&self) -> Position {
+        let line = self.lines.len() - 1;
+        Position::new(line, self.line_len(line))
+    }
+
+    /// The nearest position that exists.
+    pub fn clamp(&self, pos: Position) -> Position {
+        if pos.line >= self.lines.len() {
+            self.end()
+        } else {
+            Position::new(pos.line, pos.column.min(self.line_len(pos.line)))
+        }
+    }
+
+    /// The text in `range`, with `\n` line breaks.
+    pub fn slice(&self, range: Range<Position>) -> String {
+        let (start, end) = (self.clamp(range.start), self.clamp(range.end));
+        if end <= start {
+            return String::new();
+        }
+        let first = self.line(start.line);
+        if start.line == end.line {
+            return first[byte_index(first, start.column)..byte_index(first, end.column)].into();
+        }
+        let mut text = first[byte_index(first, start.column)..].to_owned();
+        for line in &self.lines[start.line + 1..end.line] {
+            text.push('\n');
+            text.push_str(line);
+        }
+        let last = self.line(end.line);
+        text.push('\n');
+        text.push_str(&last[..byte_index(last, end.column)]);
+        text
+    }
+
+    /// Insert `text` at `at` and return where it ends. Line breaks of any kind become new lines.
+    pub fn insert(&mut self, at: Position, text: &str) -> Position {
+        let at = self.clamp(at);
+        let split = byte_index(&self.lines[at.line], at.column);
+        let tail = self.lines[at.line].split_off(split);
+        let mut new = Self::new(text).lines.into_iter();
+        self.lines[at.line].push_str(&new.next().unwrap_or_default());
+        let rest = new.collect::<Vec<_>>();
+        let end = match rest.last() {
+            Some(last) => Position::new(at.line + rest.len(), char_len(last)),
+            None => Position::new(at.line, self.line_len(at.line)),
+        };
+        self.lines.splice(at.line + 1..at.line + 1, rest);
+        self.lines[end.line].push_str(&tail);
+        end
+    }
+
+    /// Remove `range` and return the removed text.
+    pub fn remove(&mut self, range: Range<Position>) -> String {
+        let (start, end) = (self.clamp(range.start), self.clamp(range.end));
+        if end <= start {
+            return String::new();
+        }
+        let removed = self.slice(start..end);
+        let last = &self.lines[end.line];
+        let tail = last[byte_index(last, end.column)..].to_owned();
+        let first = &mut self.lines[start.line];
+        first.truncate(byte_index(first, start.column));
+        first.push_str(&tail);
+        self.lines.drain(start.line + 1..=end.line);
+        removed
+    }
+
+    /// Replace `range` with `text`, returning how to undo it.
+    pub fn replace(&mut self, range: Range<Position>, text: &str) -> Edit {
+        let at = self.clamp(range.start);
+        let removed = self.remove(at..range.end);
+        let end = self.insert(at, text);
+        Edit {
+            at,
+            removed,
+            inserted: if text.contains('\r') {
+                self.slice(at..end)
+            } else {
+                text.into()
+            },
+        }
+    }
+
+    /// The next occurrence of `needle` after `from`, wrapping around at the end.
+    pub fn find(&self, needle: &str, from: Position) -> Option<Range<Position>> {
+        if needle.is_empty() {
+            return None;
+        }
+        if needle.contains('\n') {
+            return self.find_multiline(needle, from);
+        }
+        let from = self.clamp(from);
+        let found_in = |line: usize, start_column: usize| {
+            let text = self.line(line);
+            let start = byte_index(text, start_column);
+            text[start..].find(needle).map(|i| {
+                let column = start_column + char_len(&text[start..start + i]);
+                Position::new(line, column)..Position::new(line, column + char_len(needle))
+            })
+        };
+        found_in(from.line, from.column)
+            .or_else(|| (from.line + 1..self.lines.len()).find_map(|l| found_in(l, 0)))
+            .or_else(|| (0..=from.line).find_map(|l| found_in(l, 0)))
+    }
+
+    fn find_multiline(&self, needle: &str, from: Position) -> Option<Range<Position>> {
+        let text = self.text("\n");
+        let start = self.byte_offset(from);
+        let byte = text[start..]
+            .find(needle)
+            .map(|i| start + i)
+            .or_else(|| text.find(needle))?;
+        let at = position_of(&text, byte);
+        Some(at..end_of(at, needle))
+    }
+
+    /// Offset of `pos` in [`Self::text`] joined with `\n`.
+    fn byte_offset(&self, pos: Position) -> usize {
+        let pos = self.clamp(pos);
+        let before = self.lines[..pos.line]
+            .iter()
+            .map(|l| l.len() + 1)
+            .sum::<usize>();
+        before + byte_index(self.line(pos.line), pos.column)
+    }
+
+    /// The word around `pos`, empty if there is none.
+    pub fn word_at(&self, pos: Position) -> Range<Position> {
+        let pos = self.clamp(pos);
+        let chars = self.line(pos.line).chars().collect::<Vec<_>>();
+        let mut start = pos.column;
+        while start > 0 && is_word_char(chars[start - 1]) {
+            start -= 1;
+        }
+        let mut end = pos.column;
+        while end < chars.len() && is_word_char(chars[end]) {
+            end += 1;
+        }
+        Position::new(pos.line, start)..Position::new(pos.line, end)
+    }
+}
+
+pub fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn char_len(s: &str) -> usize {
+    if s.is_ascii() {
+        s.len()
+    } else {
+        s.chars().count()
+    }
+}
+
+/// Byte index of char `column` in `line`, or its length if it is shorter.
+pub fn byte_index(line: &str, column: usize) -> usize {
+    if line.is_ascii() {
+        return column.min(line.len());
+    }
+    line.char_indices()
+        .nth(column)
+        .map_or(line.len(), |(i, _)| i)
+}
+
+/// Where `text` ends when inserted at `at`.
+pub fn end_of(at: Position, text: &str) -> Position {
+    match text.rsplit_once('\n') {
+        Some((before, last)) => {
+            Position::new(at.line + before.matches('\n').count() + 1, char_len(last))
+        }
+        None => Position::new(at.line, at.column + char_len(text)),
+    }
+}
+
+/// Position of byte `byte` in a text with `\n` line breaks.
+fn position_of(text: &str, byte: usize) -> Position {
+    let before = &text[..byte];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    Position::new(
+        before.matches('\n').count(),
+        char_len(&before[line_start..]),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_breaks() {
+        let buffer = LineBuffer::new("a\r\nb\rc\n");
+        assert_eq!(buffer.line_count(), 4);
+        assert_eq!(buffer.text("\n"), "a\nb\nc\n");
+    }
+
+    #[test]
+    fn edits_undo() {
+        let mut buffer = LineBuffer::new("fn main() {\n}\n");
+        let original = buffer.text("\n");
+        let edit = buffer.replace(
+            Position::new(0, 10)..Position::new(1, 0),
+            "{\r\n    ünïcode();\n",
+        );
+        assert_eq!(buffer.text("\n"), "fn main() {\n    ünïcode();\n}\n");
+        assert_eq!(edit.inserted, "{\n    ünïcode();\n");
+        edit.revert(&mut buffer);
+        assert_eq!(buffer.text("\n"), original);
+        edit.apply(&mut buffer);
+        assert_eq!(
+            buffer.slice(Position::new(1, 4)..Position::new(1, 11)),
+            "ünïcode"
+        );
+    }
+
+    #[test]
+    fn find_wraps() {
+        let buffer = LineBuffer::new("foo bar\nbar foo\n");
+        let next = |line, column| buffer.find("foo", Position::new(line, column));
+        assert_eq!(next(0, 1), Some(Position::new(1, 4)..Position::new(1, 7)));
+        assert_eq!(next(1, 5), Some(Position::new(0, 0)..Position::new(0, 3)));
+        assert_eq!(
+            buffer.find("bar\nbar", Position::new(1, 0)),
+            Some(Position::new(0, 4)..Position::new(1, 3))
+        );
+    }
+}
@@ -0,0 +1,246 @@
+use crate::encoding::Encoding;
+use log::warn;
+use positioned_io::{RandomAccessFile, ReadAt};
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Receiver, Sender, TryRecvError},
+        Arc, Mutex,
+    },
+};
+
+/// Size of the blocks the file is scanned in. We remember the line count at every block start.
+const CHUNK: u64 = 1 << 20;
+
+#[derive(Default)]
+struct IndexState {
+    /// Number of newlines before the start of each chunk
+    chunk_lines: Vec<u64>,
+    /// Number of newlines in the whole file, once scanning has finished
+    total: Option<u64>,
+    /// The last offset passed to [`LineIndex::line_at`], and its line once it was counted
+    lookup: Option<(u64, Option<u64>)>,
+}
+
+/// A sparse newline index of a file on disk, built in a background thread.
+/// Used to show absolute line numbers for files we only hold a window of.
+#[derive(Clone)]
+pub struct LineIndex {
+    state: Arc<Mutex<IndexState>>,
+    /// Offsets to count the line of. The thread stops once all senders are gone.
+    lookups: Sender<u64>,
+}
+
+impl LineIndex {
+    pub fn build(path: &Path, len: u64, encoding: Encoding) -> Self {
+        Self::with_chunk(path, len, encoding, CHUNK)
+    }
+
+    fn with_chunk(path: &Path, len: u64, encoding: Encoding, chunk: u64) -> Self {
+        let (lookups, requests) = mpsc::channel();
+        let index = Self {
+            state: Default::default(),
+            lookups,
+        };
+        let indexer = Indexer {
+            path: path.into(),
+            encoding,
+            chunk,
+            state: index.state.clone(),
+        };
+        std::thread::spawn(move || indexer.run(len, requests));
+        index
+    }
+
+    /// Zero-based number of the line containing `offset`. `None` until the indexing thread has
+    /// counted it, so this is cheap to call every frame.
+    pub fn line_at(&self, offset: u64) -> Option<u64> {
+        let mut state = self.state.lock().ok()?;
+        match state.lookup {
+            Some((asked, line)) if asked == offset => line,
+            _ => {
+                state.lookup = Some((offset, None));
+                self.lookups.send(offset).ok();
+                None
+            }
+        }
+    }
+
+    /// Number of lines in the file, once indexing has finished.
+    pub fn total_lines(&self) -> Option<u64> {
+        self.state.lock().ok()?.total.map(|n| n + 1)
+    }
+}
+
+/// The background half of a [`LineIndex`].
+struct Indexer {
+    path: PathBuf,
+    encoding: Encoding,
+    /// Even, so chunks start at UTF-16 code unit boundaries
+    chunk: u64,
+    state: Arc<Mutex<IndexState>>,
+}
+
+impl Indexer {
+    /// Scan the file, answering lookups in the chunks scanned so far as they come in.
+    fn run(&self, len: u64, requests: Receiver<u64>) {
+        let raf = match RandomAccessFile::open(&self.path) {
+            Ok(raf) => raf,
+            Err(e) => {
+                warn!("Can't index {}: {e}", self.path.display());
+                return;
+            }
+        };
+        let mut buf = vec![0; self.chunk as usize];
+        let mut pending = None;
+        let mut lines = 0;
+        let mut offset = 0;
+        while offset < len {
+            let read = self.chunk.min(len - offset) as usize;
+            if let Err(e) = raf.read_exact_at(offset, &mut buf[..read]) {
+                warn!("Indexing {} failed: {e}", self.path.display());
+                return;
+            }
+            if let Ok(mut state) = self.state.lock() {
+                state.chunk_lines.push(lines);
+            }
+            lines += count_newlines(&buf[..read], self.encoding);
+            offset += read as u64;
+
+            loop {
+                match requests.try_recv() {
+                    Ok(requested) => pending = Some(requested),
+                    Err(TryRecvError::Empty) => break,
+                    // The file was closed
+                    Err(TryRecvError::Disconnected) => return,
+                }
+            }
+            if let Some(requested) = pending.filter(|r| *r < offset) {
+                self.answer(&raf, requested);
+                pending = None;
+            }
+        }
+        if let Ok(mut state) = self.state.lock() {
+            state.total = Some(lines);
+        }
+
+        if let Some(requested) = pending {
+            self.answer(&raf, requested);
+        }
+        for requested in requests {
+            self.answer(&raf, requested);
+        }
+    }
+
+    /// Count the line of `offset` from the start of its chunk, which must have been scanned.
+    fn answer(&self, raf: &RandomAccessFile, offset: u64) {
+        let Some(line) = self.line_at(raf, offset) else {
+            return;
+        };
+        if let Ok(mut state) = self.state.lock() {
+            if state.lookup.is_some_and(|(asked, _)| asked == offset) {
+                state.lookup = Some((offset, Some(line)));
+            }
+        }
+    }
+
+    fn line_at(&self, raf: &RandomAccessFile, offset: u64) -> Option<u64> {
+        let chunk = offset / self.chunk;
+        let chunk_start = *self.state.lock().ok()?.chunk_lines.get(chunk as usize)?;
+        let mut buf = vec![0; (offset - chunk * self.chunk) as usize];
+        match raf.read_exact_at(chunk * self.chunk, &mut buf) {
+            Ok(()) => Some(chunk_start + count_newlines(&buf, self.encoding)),
+            Err(e) => {
+                warn!("Reading {} failed: {e}", self.path.display());
+                None
+            }
+        }
+    }
+}
+
+/// Newlines in `buf`, which starts at a character boundary.
+fn count_newlines(buf: &[u8], encoding: Encoding) -> u64 {
+    let newline = match encoding {
+        Encoding::Utf16Le => [b'\n', 0],
+        Encoding::Utf16Be => [0, b'\n'],
+        // In UTF-8 and Latin-1 a newline byte is never part of another character
+        Encoding::Utf8 | Encoding::Utf8Bom | Encoding::Latin1 => {
+            return buf.iter().filter(|b| **b == b'\n').count() as u64
+        }
+    };
+    buf.chunks_exact(2).filter(|unit| *unit == newline).count() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, thread, time::Duration};
+
+    /// Ask until the thread answered.
+    fn wait_for(index: &LineIndex, offset: u64) -> u64 {
+        for _ in 0..500 {
+            if let Some(line) = index.line_at(offset) {
+                return line;
+            }
+            thread::sleep(Duration::from_millis(2));
+        }
+        panic!("no line for offset {offset}");
+    }
+
+    #[test]
+    fn lines_across_chunks() {
+        // Lines of growing length, so chunk boundaries fall anywhere in a line
+        let text = (0..40).map(|i| "x".repeat(i) + "\n").collect::<String>();
+        let path = env::temp_dir().join(format!("micron-line-index-{}", std::process::id()));
+        fs::write(&path, &text).unwrap();
+        let index = LineIndex::with_chunk(&path, text.len() as u64, Encoding::Utf8, 16);
+
+        for offset in [0, 1, 15, 16, 17, 31, 32, 100, 511, text.len() as u64 - 1] {
+            let expected = text[..offset as usize].matches('\n').count() as u64;
+            assert_eq!(wait_for(&index, offset), expected, "offset {offset}");
+        }
+        for _ in 0..500 {
+            if index.total_lines().is_some() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(2));
+        }
+        assert_eq!(index.total_lines(), Some(41));
+        fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn utf16_lines() {
+        // U+0A0A and U+0A00 contain 0x0A bytes that aren't newlines
+        let text = (0..40)
+            .map(|i| "\u{0a0a}\u{0a00}".repeat(i % 5) + "x\n")
+            .collect::<String>();
+        for encoding in [Encoding::Utf16Le, Encoding::Utf16Be] {
+            let bytes = encoding.encode(&text);
+            let path = env::temp_dir().join(format!(
+                "micron-line-index-{}-{}",
+                encoding.name(),
+                std::process::id()
+            ));
+            fs::write(&path, &bytes).unwrap();
+            let index = LineIndex::with_chunk(&path, bytes.len() as u64, encoding, 16);
+
+            // Offsets of whole code units after the byte order mark
+            for offset in [2, 16, 18, 30, 100, 250, bytes.len() as u64 - 2] {
+                let chars = (offset as usize - 2) / 2;
+                let expected = text
+                    .encode_utf16()
+                    .take(chars)
+                    .filter(|u| *u == 0x0a)
+                    .count();
+                assert_eq!(
+                    wait_for(&index, offset),
+                    expected as u64,
+                    "{} offset {offset}",
+                    encoding.name()
+                );
+            }
+            fs::remove_file(path).ok();
+        }
+    }
+}
@@ -3,6 +3,7 @@
 
 fn main() -> eframe::Result<()> {
     env_logger::init();
+    let start = std::time::Instant::now();
 
     let options = eframe::NativeOptions {
         icon_data: Some(load_icon()), // an example
@@ -13,7 +14,10 @@ fn main() -> eframe::Result<()> {
     eframe::run_native(
         "micron",
         options,
-        Box::new(|cc| Box::new(micron::MicronApp::new(cc))),
+        Box::new(move |cc| {
+            log::info!("Window created after {:?}", start.elapsed());
+            Box::new(micron::MicronApp::new(cc))
+        }),
     )
 }
 
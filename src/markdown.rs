@@ -0,0 +1,1306 @@
+//! Markdown for the preview: the blocks and inlines of CommonMark, plus GitHub's tables,
+//! strikethrough, task lists and bare links. Raw HTML is dropped, except for images and line
+//! breaks.
+
+use std::collections::HashMap;
+
+/// A block and the zero-based source line it starts at.
+#[derive(Debug, PartialEq)]
+pub struct Block {
+    pub line: usize,
+    pub kind: BlockKind,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BlockKind {
+    Heading(u8, Vec<Inline>),
+    Paragraph(Vec<Inline>),
+    /// Fenced or indented code, with the language of the fence
+    Code {
+        language: String,
+        text: String,
+    },
+    Quote(Vec<Block>),
+    List {
+        /// Number of the first item of an ordered list
+        start: Option<u64>,
+        items: Vec<Item>,
+    },
+    Table {
+        aligns: Vec<Align>,
+        header: Vec<Vec<Inline>>,
+        rows: Vec<Vec<Vec<Inline>>>,
+    },
+    Rule,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Item {
+    /// Whether the task is done, for task list items
+    pub checked: Option<bool>,
+    pub blocks: Vec<Block>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Align {
+    None,
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Style {
+    pub strong: bool,
+    pub emphasis: bool,
+    pub strikethrough: bool,
+    pub code: bool,
+    pub link: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Inline {
+    Text(String, Style),
+    Image {
+        alt: String,
+        url: String,
+        link: Option<String>,
+    },
+    LineBreak,
+}
+
+/// The blocks of `text`.
+pub fn parse(text: &str) -> Vec<Block> {
+    let lines = text
+        .lines()
+        .enumerate()
+        .map(|(number, text)| Line {
+            number,
+            text: expand_tabs(text),
+        })
+        .collect::<Vec<_>>();
+    let refs = definitions(&lines);
+    blocks(&lines, &refs)
+}
+
+/// Link reference definitions by normalized label
+type Refs = HashMap<String, String>;
+
+#[derive(Clone)]
+struct Line {
+    number: usize,
+    text: String,
+}
+
+/// Tabs in the indentation as 4 spaces.
+fn expand_tabs(text: &str) -> String {
+    let indent = text.len() - text.trim_start_matches([' ', '\t']).len();
+    let (indent, rest) = text.split_at(indent);
+    indent.replace('\t', "    ") + rest
+}
+
+fn indentation(text: &str) -> usize {
+    text.len() - text.trim_start_matches(' ').len()
+}
+
+fn is_blank(text: &str) -> bool {
+    text.trim().is_empty()
+}
+
+/// `text` without up to 3 spaces of indentation, or `None` if it is indented more.
+fn unindent(text: &str) -> Option<&str> {
+    (indentation(text) < 4).then(|| text.trim_start_matches(' '))
+}
+
+/// Definitions like `[label]: url` anywhere outside code blocks.
+fn definitions(lines: &[Line]) -> Refs {
+    let mut refs = Refs::new();
+    let mut fence = None;
+    for line in lines {
+        match &fence {
+            Some(open) if Fence::closes(open, &line.text) => fence = None,
+            Some(_) => {}
+            None => {
+                fence = Fence::open(&line.text);
+                if let Some((label, url)) = definition(&line.text) {
+                    refs.entry(label).or_insert(url);
+                }
+            }
+        }
+    }
+    refs
+}
+
+/// Label and url of a link reference definition.
+fn definition(text: &str) -> Option<(String, String)> {
+    let rest = unindent(text)?.strip_prefix('[')?;
+    let (label, rest) = rest.split_once("]:")?;
+    let url = rest.split_whitespace().next()?;
+    let url = url.trim_start_matches('<').trim_end_matches('>');
+    (!label.trim().is_empty()).then(|| (normalize_label(label), url.to_string()))
+}
+
+fn normalize_label(label: &str) -> String {
+    label
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+struct Fence {
+    indent: usize,
+    char: char,
+    len: usize,
+    language: String,
+}
+
+impl Fence {
+    fn open(text: &str) -> Option<Self> {
+        let trimmed = unindent(text)?;
+        let char = trimmed.chars().next().filter(|c| matches!(c, '`' | '~'))?;
+        let len = trimmed.len() - trimmed.trim_start_matches(char).len();
+        let info = trimmed[len..].trim();
+        if len < 3 || (char == '`' && info.contains('`')) {
+            return None;
+        }
+        Some(Self {
+            indent: indentation(text),
+            char,
+            len,
+            language: info.split_whitespace().next().unwrap_or_default().into(),
+        })
+    }
+
+    fn closes(&self, text: &str) -> bool {
+        unindent(text).is_some_and(|trimmed| {
+            let len = trimmed.len() - trimmed.trim_start_matches(self.char).len();
+            len >= self.len && is_blank(&trimmed[len..])
+        })
+    }
+
+    /// The code block this fence opens at the first of `lines`, and how many lines it takes.
+    fn code(self, lines: &[Line]) -> (BlockKind, usize) {
+        let mut code = vec![];
+        let mut used = 1;
+        for line in &lines[1..] {
+            used += 1;
+            if self.closes(&line.text) {
+                break;
+            }
+            let indent = indentation(&line.text).min(self.indent);
+            code.push(&line.text[indent..]);
+        }
+        let kind = BlockKind::Code {
+            language: self.language,
+            text: code.join("\n"),
+        };
+        (kind, used)
+    }
+}
+
+/// Level and text of an ATX heading like `## Title ##`.
+fn atx_heading(text: &str) -> Option<(u8, &str)> {
+    let trimmed = unindent(text)?;
+    let level = trimmed.len() - trimmed.trim_start_matches('#').len();
+    let rest = &trimmed[level..];
+    if !(1..=6).contains(&level) || !(rest.is_empty() || rest.starts_with([' ', '\t'])) {
+        return None;
+    }
+    let rest = rest.trim();
+    let without_closing = rest.trim_end_matches('#');
+    let rest = if without_closing.is_empty() || without_closing.ends_with(' ') {
+        without_closing.trim_end()
+    } else {
+        rest
+    };
+    Some((level as u8, rest))
+}
+
+fn is_rule(text: &str) -> bool {
+    let Some(trimmed) = unindent(text) else {
+        return false;
+    };
+    let mut chars = trimmed.chars().filter(|c| !c.is_whitespace());
+    let Some(first) = chars.next().filter(|c| matches!(c, '-' | '*' | '_')) else {
+        return false;
+    };
+    let mut count = 1;
+    for c in chars {
+        if c != first {
+            return false;
+        }
+        count += 1;
+    }
+    count >= 3
+}
+
+/// Level of a setext heading underlined by `text`.
+fn setext_underline(text: &str) -> Option<u8> {
+    let trimmed = unindent(text)?.trim_end();
+    if !trimmed.is_empty() && trimmed.chars().all(|c| c == '=') {
+        Some(1)
+    } else if !trimmed.is_empty() && trimmed.chars().all(|c| c == '-') {
+        Some(2)
+    } else {
+        None
+    }
+}
+
+fn is_quote(text: &str) -> bool {
+    unindent(text).is_some_and(|t| t.starts_with('>'))
+}
+
+fn is_html(text: &str) -> bool {
+    unindent(text)
+        .and_then(|t| t.strip_prefix('<'))
+        .and_then(|t| t.chars().next())
+        .is_some_and(|c| c.is_ascii_alphabetic() || matches!(c, '/' | '!' | '?'))
+}
+
+struct Marker {
+    /// Number of an ordered list item
+    number: Option<u64>,
+    /// Bullet, or what follows the number
+    char: char,
+    /// Column the content of the item starts at
+    content: usize,
+    /// Whether the item has no text after the marker
+    empty: bool,
+}
+
+impl Marker {
+    fn parse(text: &str) -> Option<Self> {
+        let indent = indentation(text);
+        let trimmed = unindent(text)?;
+        let digits = trimmed.len()
+            - trimmed
+                .trim_start_matches(|c: char| c.is_ascii_digit())
+                .len();
+        let (number, char) = match trimmed[digits..].chars().next()? {
+            c @ ('-' | '+' | '*') if digits == 0 => (None, c),
+            c @ ('.' | ')') if (1..=9).contains(&digits) => (trimmed[..digits].parse().ok(), c),
+            _ => return None,
+        };
+        let marker_end = indent + digits + 1;
+        let rest = &text[marker_end..];
+        if !(rest.is_empty() || rest.starts_with(' ')) {
+            return None;
+        }
+        let spaces = indentation(rest);
+        let empty = is_blank(rest);
+        let content = if empty || spaces > 4 {
+            marker_end + 1
+        } else {
+            marker_end + spaces
+        };
+        Some(Self {
+            number,
+            char,
+            content,
+            empty,
+        })
+    }
+
+    fn same_list(&self, other: &Self) -> bool {
+        self.char == other.char && self.number.is_some() == other.number.is_some()
+    }
+}
+
+/// Whether `text` starts a block that ends a paragraph.
+fn interrupts_paragraph(text: &str) -> bool {
+    Fence::open(text).is_some()
+        || atx_heading(text).is_some()
+        || is_rule(text)
+        || is_quote(text)
+        || is_html(text)
+        || Marker::parse(text).is_some_and(|m| !m.empty && m.number.map_or(true, |n| n == 1))
+}
+
+fn blocks(lines: &[Line], refs: &Refs) -> Vec<Block> {
+    let mut blocks = vec![];
+    let mut i = 0;
+    while i < lines.len() {
+        let text = lines[i].text.as_str();
+        if is_blank(text) {
+            i += 1;
+            continue;
+        }
+        let (kind, used) = if indentation(text) >= 4 {
+            indented_code(&lines[i..])
+        } else if let Some(fence) = Fence::open(text) {
+            fence.code(&lines[i..])
+        } else if let Some((level, title)) = atx_heading(text) {
+            (BlockKind::Heading(level, inlines(title, refs)), 1)
+        } else if is_rule(text) {
+            (BlockKind::Rule, 1)
+        } else if is_quote(text) {
+            quote(&lines[i..], refs)
+        } else if let Some(marker) = Marker::parse(text) {
+            list(&lines[i..], marker, refs)
+        } else if is_html(text) {
+            let used = lines[i..]
+                .iter()
+                .position(|l| is_blank(&l.text))
+                .unwrap_or(lines.len() - i);
+            let html = join(&lines[i..i + used]);
+            match inlines(&html, refs) {
+                inlines if inlines.is_empty() => {
+                    i += used;
+                    continue;
+                }
+                inlines => (BlockKind::Paragraph(inlines), used),
+            }
+        } else if let Some(table) = table(&lines[i..], refs) {
+            table
+        } else if definition(text).is_some() {
+            i += 1;
+            continue;
+        } else {
+            paragraph(&lines[i..], refs)
+        };
+        blocks.push(Block {
+            line: lines[i].number,
+            kind,
+        });
+        i += used;
+    }
+    blocks
+}
+
+/// The lines trimmed and joined by line breaks.
+fn join(lines: &[Line]) -> String {
+    lines
+        .iter()
+        .map(|l| l.text.trim_start())
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim_end()
+        .to_string()
+}
+
+fn indented_code(lines: &[Line]) -> (BlockKind, usize) {
+    let used = lines
+        .iter()
+        .position(|l| !is_blank(&l.text) && indentation(&l.text) < 4)
+        .unwrap_or(lines.len());
+    let code = lines[..used]
+        .iter()
+        .map(|l| l.text.get(4..).unwrap_or_default())
+        .collect::<Vec<_>>();
+    let kind = BlockKind::Code {
+        language: String::new(),
+        text: code.join("\n").trim_end_matches('\n').to_string(),
+    };
+    (kind, used)
+}
+
+fn paragraph(lines: &[Line], refs: &Refs) -> (BlockKind, usize) {
+    let mut used = 1;
+    while let Some(line) = lines.get(used) {
+        if is_blank(&line.text) || (interrupts_paragraph(&line.text) && !is_rule(&line.text)) {
+            break;
+        }
+        if let Some(level) = setext_underline(&line.text) {
+            let title = join(&lines[..used]);
+            return (BlockKind::Heading(level, inlines(&title, refs)), used + 1);
+        }
+        if is_rule(&line.text) {
+            break;
+        }
+        used += 1;
+    }
+    let text = join(&lines[..used]);
+    (BlockKind::Paragraph(inlines(&text, refs)), used)
+}
+
+fn quote(lines: &[Line], refs: &Refs) -> (BlockKind, usize) {
+    let mut inner = vec![];
+    let mut used = 0;
+    for line in lines {
+        let text = match unindent(&line.text).and_then(|t| t.strip_prefix('>')) {
+            Some(rest) => rest.strip_prefix(' ').unwrap_or(rest).to_string(),
+            // A lazy continuation of a paragraph in the quote
+            None if !is_blank(&line.text)
+                && !interrupts_paragraph(&line.text)
+                && inner.last().is_some_and(|l: &Line| !is_blank(&l.text)) =>
+            {
+                line.text.clone()
+            }
+            None => break,
+        };
+        inner.push(Line {
+            number: line.number,
+            text,
+        });
+        used += 1;
+    }
+    (BlockKind::Quote(blocks(&inner, refs)), used)
+}
+
+fn list(lines: &[Line], first: Marker, refs: &Refs) -> (BlockKind, usize) {
+    let start = first.number;
+    let mut items = vec![];
+    let mut marker = first;
+    let mut i = 0;
+    loop {
+        let mut item = vec![Line {
+            number: lines[i].number,
+            text: lines[i]
+                .text
+                .get(marker.content..)
+                .unwrap_or_default()
+                .into(),
+        }];
+        i += 1;
+        while let Some(line) = lines.get(i) {
+            if is_blank(&line.text) {
+                let next = lines[i..].iter().position(|l| !is_blank(&l.text));
+                match next.map(|n| &lines[i + n]) {
+                    Some(next) if indentation(&next.text) >= marker.content => {
+                        item.push(Line {
+                            number: line.number,
+                            text: String::new(),
+                        });
+                        i += 1;
+                        continue;
+                    }
+                    _ => break,
+                }
+            }
+            if indentation(&line.text) >= marker.content {
+                item.push(Line {
+                    number: line.number,
+                    text: line.text[marker.content..].into(),
+                });
+            } else if Marker::parse(&line.text).is_some() || interrupts_paragraph(&line.text) {
+                break;
+            } else {
+                // A lazy continuation line
+                item.push(line.clone());
+            }
+            i += 1;
+        }
+
+        let checked = task(&mut item[0].text);
+        items.push(Item {
+            checked,
+            blocks: blocks(&item, refs),
+        });
+
+        let next = lines[i..]
+            .iter()
+            .position(|l| !is_blank(&l.text))
+            .map(|n| i + n);
+        match next.and_then(|n| Some((n, Marker::parse(&lines[n].text)?))) {
+            Some((n, next)) if next.same_list(&marker) => {
+                i = n;
+                marker = next;
+            }
+            _ => break,
+        }
+    }
+    (BlockKind::List { start, items }, i)
+}
+
+/// Removes the `[ ]` or `[x]` of a task list item and says whether it was checked.
+fn task(text: &mut String) -> Option<bool> {
+    let checked = match text.get(..4)? {
+        "[ ] " => false,
+        "[x] " | "[X] " => true,
+        _ => return None,
+    };
+    text.replace_range(..4, "");
+    Some(checked)
+}
+
+/// A table at the first of `lines`, if its second line is a delimiter row with as many cells
+/// as the first.
+fn table(lines: &[Line], refs: &Refs) -> Option<(BlockKind, usize)> {
+    let header = cells(&lines.first()?.text);
+    let aligns = cells(&lines.get(1)?.text)
+        .iter()
+        .map(|cell| {
+            let dashes = cell.trim_start_matches(':').trim_end_matches(':');
+            if dashes.is_empty() || !dashes.chars().all(|c| c == '-') {
+                return None;
+            }
+            Some(match (cell.starts_with(':'), cell.ends_with(':')) {
+                (true, true) => Align::Center,
+                (true, false) => Align::Left,
+                (false, true) => Align::Right,
+                (false, false) => Align::None,
+            })
+        })
+        .collect::<Option<Vec<_>>>()?;
+    if !lines[0].text.contains('|') || aligns.len() != header.len() {
+        return None;
+    }
+    let used = lines[2..]
+        .iter()
+        .position(|l| is_blank(&l.text) || interrupts_paragraph(&l.text))
+        .map_or(lines.len(), |n| n + 2);
+    let row = |text: &str| {
+        let mut cells = cells(text);
+        cells.resize(header.len(), String::new());
+        cells.iter().map(|c| inlines(c, refs)).collect::<Vec<_>>()
+    };
+    let kind = BlockKind::Table {
+        rows: lines[2..used].iter().map(|l| row(&l.text)).collect(),
+        header: header.iter().map(|c| inlines(c, refs)).collect(),
+        aligns,
+    };
+    Some((kind, used))
+}
+
+/// The trimmed cells of a table row, split at pipes that aren't escaped.
+fn cells(text: &str) -> Vec<String> {
+    let text = text.trim();
+    let text = text.strip_prefix('|').unwrap_or(text);
+    let text = match text.strip_suffix('|') {
+        Some(rest) if !rest.ends_with('\\') => rest,
+        _ => text,
+    };
+    let mut cells = vec![];
+    let mut cell = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'|') => {
+                cell.push('|');
+                chars.next();
+            }
+            '|' => cells.push(std::mem::take(&mut cell).trim().to_string()),
+            c => cell.push(c),
+        }
+    }
+    cells.push(cell.trim().to_string());
+    cells
+}
+
+// ----------------------------------------------------------------------------
+
+enum Node {
+    Text(String),
+    Code(String),
+    Emphasis(Vec<Node>),
+    Strong(Vec<Node>),
+    Strikethrough(Vec<Node>),
+    Link { url: String, children: Vec<Node> },
+    Image { alt: String, url: String },
+    Break,
+}
+
+/// A run of `*`, `_` or `~` that may start or end emphasis.
+#[derive(Clone, Copy)]
+struct Delimiter {
+    char: char,
+    count: usize,
+    open: bool,
+    close: bool,
+}
+
+enum Token {
+    Node(Node),
+    Delimiter(Delimiter),
+}
+
+impl Token {
+    fn into_node(self) -> Node {
+        match self {
+            Self::Node(node) => node,
+            Self::Delimiter(d) => Node::Text(d.char.to_string().repeat(d.count)),
+        }
+    }
+
+    fn delimiter(&mut self) -> Option<&mut Delimiter> {
+        match self {
+            Self::Delimiter(d) => Some(d),
+            Self::Node(_) => None,
+        }
+    }
+}
+
+/// The styled text of a paragraph, heading or table cell.
+fn inlines(text: &str, refs: &Refs) -> Vec<Inline> {
+    let mut inlines = vec![];
+    flatten(nodes(text, refs), &Style::default(), &mut inlines);
+    inlines
+}
+
+fn flatten(nodes: Vec<Node>, style: &Style, out: &mut Vec<Inline>) {
+    let with = |f: fn(&mut Style)| {
+        let mut style = style.clone();
+        f(&mut style);
+        style
+    };
+    for node in nodes {
+        match node {
+            Node::Text(text) => push_text(out, text, style),
+            Node::Code(text) => push_text(out, text, &with(|s| s.code = true)),
+            Node::Emphasis(children) => flatten(children, &with(|s| s.emphasis = true), out),
+            Node::Strong(children) => flatten(children, &with(|s| s.strong = true), out),
+            Node::Strikethrough(children) => {
+                flatten(children, &with(|s| s.strikethrough = true), out)
+            }
+            Node::Link { url, children } => {
+                let style = Style {
+                    link: Some(url),
+                    ..style.clone()
+                };
+                flatten(children, &style, out);
+            }
+            Node::Image { alt, url } => out.push(Inline::Image {
+                alt,
+                url,
+                link: style.link.clone(),
+            }),
+            Node::Break => out.push(Inline::LineBreak),
+        }
+    }
+}
+
+/// Adds `text` to the last text if it has the same style.
+fn push_text(out: &mut Vec<Inline>, text: String, style: &Style) {
+    if text.is_empty() {
+        return;
+    }
+    match out.last_mut() {
+        Some(Inline::Text(last, last_style)) if last_style == style => last.push_str(&text),
+        _ => out.push(Inline::Text(text, style.clone())),
+    }
+}
+
+fn is_punctuation(c: char) -> bool {
+    c.is_ascii_punctuation() || !(c.is_alphanumeric() || c.is_whitespace())
+}
+
+fn nodes(text: &str, refs: &Refs) -> Vec<Node> {
+    let mut tokens = vec![];
+    let mut buf = String::new();
+    let flush = |buf: &mut String, tokens: &mut Vec<Token>| {
+        if !buf.is_empty() {
+            tokens.push(Token::Node(Node::Text(std::mem::take(buf))));
+        }
+    };
+    let mut i = 0;
+    while let Some(c) = text[i..].chars().next() {
+        let rest = &text[i..];
+        let prev = text[..i].chars().next_back();
+        match c {
+            '\\' => match rest[1..].chars().next() {
+                Some('\n') => {
+                    flush(&mut buf, &mut tokens);
+                    tokens.push(Token::Node(Node::Break));
+                    i += 2;
+                    continue;
+                }
+                Some(next) if next.is_ascii_punctuation() => {
+                    buf.push(next);
+                    i += 2;
+                    continue;
+                }
+                _ => {}
+            },
+            '`' => {
+                let run = rest.len() - rest.trim_start_matches('`').len();
+                if let Some((code, end)) = code_span(&rest[run..], run) {
+                    flush(&mut buf, &mut tokens);
+                    tokens.push(Token::Node(Node::Code(code)));
+                    i += run + end;
+                } else {
+                    buf.push_str(&rest[..run]);
+                    i += run;
+                }
+                continue;
+            }
+            '*' | '_' | '~' => {
+                let count = rest.len() - rest.trim_start_matches(c).len();
+                let next = rest[count..].chars().next();
+                let (prev_space, next_space) = (
+                    prev.map_or(true, char::is_whitespace),
+                    next.map_or(true, char::is_whitespace),
+                );
+                let (prev_punct, next_punct) = (
+                    prev.is_some_and(is_punctuation),
+                    next.is_some_and(is_punctuation),
+                );
+                let left = !next_space && (!next_punct || prev_space || prev_punct);
+                let right = !prev_space && (!prev_punct || next_space || next_punct);
+                let (open, close) = match c {
+                    '_' => (
+                        left && (!right || prev_punct),
+                        right && (!left || next_punct),
+                    ),
+                    _ => (left, right),
+                };
+                if c == '~' && count > 2 {
+                    buf.push_str(&rest[..count]);
+                } else {
+                    flush(&mut buf, &mut tokens);
+                    tokens.push(Token::Delimiter(Delimiter {
+                        char: c,
+                        count,
+                        open,
+                        close,
+                    }));
+                }
+                i += count;
+                continue;
+            }
+            '!' | '[' => {
+                let image = c == '!';
+                let start = usize::from(image);
+                if rest[start..].starts_with('[') {
+                    if let Some((node, end)) = link(text, i + start, image, refs) {
+                        flush(&mut buf, &mut tokens);
+                        tokens.push(Token::Node(node));
+                        i = end;
+                        continue;
+                    }
+                }
+            }
+            '<' => {
+                if let Some((node, len)) = autolink(rest).or_else(|| html_tag(rest)) {
+                    flush(&mut buf, &mut tokens);
+                    tokens.extend(node.map(Token::Node));
+                    i += len;
+                    continue;
+                }
+            }
+            '&' => {
+                if let Some((entity, len)) = entity(rest) {
+                    buf.push(entity);
+                    i += len;
+                    continue;
+                }
+            }
+            '\n' => {
+                let spaces = buf.len() - buf.trim_end_matches(' ').len();
+                buf.truncate(buf.len() - spaces);
+                if spaces >= 2 {
+                    flush(&mut buf, &mut tokens);
+                    tokens.push(Token::Node(Node::Break));
+                } else {
+                    buf.push(' ');
+                }
+                i += 1;
+                continue;
+            }
+            'h' | 'w' if prev.map_or(true, |p| p.is_whitespace() || p == '(') => {
+                if let Some(len) = bare_link(rest) {
+                    let url = &rest[..len];
+                    let href = if url.starts_with("www.") {
+                        format!("http://{url}")
+                    } else {
+                        url.to_string()
+                    };
+                    flush(&mut buf, &mut tokens);
+                    tokens.push(Token::Node(Node::Link {
+                        url: href,
+                        children: vec![Node::Text(url.into())],
+                    }));
+                    i += len;
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        buf.push(c);
+        i += c.len_utf8();
+    }
+    flush(&mut buf, &mut tokens);
+    emphasis(tokens)
+}
+
+/// Content of a code span opened by `run` backticks, and where it ends in `text`, which
+/// follows them.
+fn code_span(text: &str, run: usize) -> Option<(String, usize)> {
+    let mut i = 0;
+    while i < text.len() {
+        let start = i + text[i..].find('`')?;
+        let len = text[start..].len() - text[start..].trim_start_matches('`').len();
+        if len == run {
+            let code = text[..start].replace('\n', " ");
+            let code = match code.strip_prefix(' ').and_then(|c| c.strip_suffix(' ')) {
+                Some(inner) if !inner.trim().is_empty() => inner.to_string(),
+                _ => code,
+            };
+            return Some((code, start + len));
+        }
+        i = start + len;
+    }
+    None
+}
+
+/// A link or image whose text starts with the `[` at byte `open` of `text`, and where it ends.
+fn link(text: &str, open: usize, image: bool, refs: &Refs) -> Option<(Node, usize)> {
+    let mut depth = 0;
+    let mut close = None;
+    let mut chars = text[open..].char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '[' => depth += 1,
+            ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    close = Some(open + i);
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+    let close = close?;
+    let label = &text[open + 1..close];
+    let after = &text[close + 1..];
+    let (url, end) = if let Some(rest) = after.strip_prefix('(') {
+        let (url, len) = destination(rest)?;
+        (url, close + 2 + len)
+    } else if let Some((reference, _)) = after.strip_prefix('[').and_then(|r| r.split_once(']')) {
+        // `[text][]` uses the text as reference
+        let key = if reference.trim().is_empty() {
+            label
+        } else {
+            reference
+        };
+        (
+            refs.get(&normalize_label(key))?.clone(),
+            close + 3 + reference.len(),
+        )
+    } else {
+        (refs.get(&normalize_label(label))?.clone(), close + 1)
+    };
+    let children = nodes(label, refs);
+    let node = if image {
+        Node::Image {
+            alt: plain_text(&children),
+            url,
+        }
+    } else {
+        Node::Link { url, children }
+    };
+    Some((node, end))
+}
+
+/// Url of an inline link after its `(`, and how many bytes up to and including the `)`.
+fn destination(text: &str) -> Option<(String, usize)> {
+    let start = text.len() - text.trim_start().len();
+    let rest = &text[start..];
+    let (url, len) = if let Some(inner) = rest.strip_prefix('<') {
+        let end = inner.find(['>', '\n'])?;
+        (&inner[..end], end + 2)
+    } else {
+        let mut depth = 0;
+        let end = rest
+            .char_indices()
+            .find(|(_, c)| match c {
+                '(' => {
+                    depth += 1;
+                    false
+                }
+                ')' if depth == 0 => true,
+                ')' => {
+                    depth -= 1;
+                    false
+                }
+                c => c.is_whitespace(),
+            })
+            .map_or(rest.len(), |(i, _)| i);
+        (&rest[..end], end)
+    };
+    let mut i = start + len;
+    let after = text[i..].trim_start();
+    i = text.len() - after.len();
+    // An optional title, which isn't shown
+    if let Some(quote) = after
+        .chars()
+        .next()
+        .filter(|c| matches!(c, '"' | '\'' | '('))
+    {
+        let closing = if quote == '(' { ')' } else { quote };
+        let end = after[1..].find(closing)?;
+        let after_title = after[end + 2..].trim_start();
+        i = text.len() - after_title.len();
+    }
+    text[i..].starts_with(')').then(|| (url.to_string(), i + 1))
+}
+
+/// A link like `<https://example.com>`, and how long it is.
+fn autolink(text: &str) -> Option<(Option<Node>, usize)> {
+    let end = text.find('>')?;
+    let url = &text[1..end];
+    if url.contains(|c: char| c.is_whitespace() || c == '<') {
+        return None;
+    }
+    let scheme = url.split_once(':').is_some_and(|(scheme, _)| {
+        scheme.len() >= 2
+            && scheme
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '+')
+    });
+    let href = if scheme {
+        url.to_string()
+    } else if url.contains('@') && !url.contains('/') {
+        format!("mailto:{url}")
+    } else {
+        return None;
+    };
+    let node = Node::Link {
+        url: href,
+        children: vec![Node::Text(url.into())],
+    };
+    Some((Some(node), end + 1))
+}
+
+/// An HTML tag or comment, and how long it is. Only images and line breaks are kept.
+fn html_tag(text: &str) -> Option<(Option<Node>, usize)> {
+    if text.starts_with("<!--") {
+        return Some((None, text.find("-->")? + 3));
+    }
+    let inner = text[1..].strip_prefix('/').unwrap_or(&text[1..]);
+    if !inner.starts_with(|c: char| c.is_ascii_alphabetic() || c == '!') {
+        return None;
+    }
+    let end = text.find('>')?;
+    let tag = &text[1..end];
+    let name = tag
+        .split(|c: char| c.is_whitespace() || c == '/')
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+    let node = match name.as_str() {
+        "br" => Some(Node::Break),
+        "img" => Some(Node::Image {
+            alt: attribute(tag, "alt").unwrap_or_default(),
+            url: attribute(tag, "src")?,
+        }),
+        _ => None,
+    };
+    Some((node, end + 1))
+}
+
+/// Value of the attribute `name` in the text of an HTML tag.
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let end_of_name = |c: char| c.is_whitespace() || matches!(c, '=' | '/' | '>');
+    // Skip the tag name
+    let mut rest = tag.trim_start_matches(|c: char| !end_of_name(c));
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+        if rest.is_empty() {
+            return None;
+        }
+        let attr_end = rest.find(end_of_name).unwrap_or(rest.len()).max(1);
+        let attr = &rest[..attr_end];
+        rest = rest[attr_end..].trim_start();
+        let value = match rest.strip_prefix('=') {
+            Some(after) => {
+                let after = after.trim_start();
+                let (value, len) = match after.chars().next() {
+                    Some(quote @ ('"' | '\'')) => {
+                        let inner = &after[1..];
+                        let end = inner.find(quote).unwrap_or(inner.len());
+                        (&inner[..end], (end + 2).min(after.len()))
+                    }
+                    _ => {
+                        let end = after
+                            .find(|c: char| c.is_whitespace() || c == '>')
+                            .unwrap_or(after.len());
+                        (&after[..end], end)
+                    }
+                };
+                rest = &after[len..];
+                value
+            }
+            None => "",
+        };
+        if attr.eq_ignore_ascii_case(name) {
+            return Some(value.to_string());
+        }
+    }
+}
+
+/// The char of an entity like `&amp;` or `&#169;`, and how long the entity is.
+fn entity(text: &str) -> Option<(char, usize)> {
+    let (end, _) = text.char_indices().take(12).find(|(_, c)| *c == ';')?;
+    let name = &text[1..end];
+    let c = match name {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{a0}',
+        "copy" => '©',
+        "reg" => '®',
+        "trade" => '™',
+        "mdash" => '—',
+        "ndash" => '–',
+        "hellip" => '…',
+        _ => {
+            let number = name.strip_prefix('#')?;
+            let code = match number.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => number.parse().ok()?,
+            };
+            char::from_u32(code).filter(|c| *c != '\0')?
+        }
+    };
+    Some((c, end + 1))
+}
+
+/// Length of a url like `https://example.com` or `www.example.com` at the start of `text`.
+fn bare_link(text: &str) -> Option<usize> {
+    let prefix = ["https://", "http://", "www."]
+        .into_iter()
+        .find(|prefix| text.starts_with(prefix))?;
+    let end = text
+        .find(|c: char| c.is_whitespace() || c == '<')
+        .unwrap_or(text.len());
+    // Punctuation at the end belongs to the sentence
+    let url = text[..end].trim_end_matches(['.', ',', ':', ';', '!', '?', '"', '\'', '*', '_']);
+    let url = if url.ends_with(')') && url.matches('(').count() < url.matches(')').count() {
+        &url[..url.len() - 1]
+    } else {
+        url
+    };
+    (url.len() > prefix.len()).then_some(url.len())
+}
+
+fn plain_text(nodes: &[Node]) -> String {
+    nodes
+        .iter()
+        .map(|node| match node {
+            Node::Text(text) | Node::Code(text) => text.clone(),
+            Node::Emphasis(children)
+            | Node::Strong(children)
+            | Node::Strikethrough(children)
+            | Node::Link { children, .. } => plain_text(children),
+            Node::Image { alt, .. } => alt.clone(),
+            Node::Break => " ".into(),
+        })
+        .collect()
+}
+
+/// Match the `*`, `_` and `~` runs into emphasis, strong emphasis and strikethrough.
+fn emphasis(mut tokens: Vec<Token>) -> Vec<Node> {
+    let mut c = 0;
+    while c < tokens.len() {
+        let Some(closer) = tokens[c].delimiter().copied().filter(|d| d.close) else {
+            c += 1;
+            continue;
+        };
+        let opener = (0..c).rev().find(|&o| {
+            tokens[o].delimiter().is_some_and(|d| {
+                let rule_of_three = (d.close || closer.open)
+                    && (d.count + closer.count) % 3 == 0
+                    && !(d.count % 3 == 0 && closer.count % 3 == 0);
+                d.char == closer.char
+                    && d.open
+                    && !rule_of_three
+                    && (d.char != '~' || d.count == closer.count)
+            })
+        });
+        let Some(o) = opener else {
+            c += 1;
+            continue;
+        };
+        let opener_count = tokens[o].delimiter().map_or(0, |d| d.count);
+        let used = if closer.char == '~' {
+            closer.count
+        } else if opener_count >= 2 && closer.count >= 2 {
+            2
+        } else {
+            1
+        };
+        let inner = tokens
+            .drain(o + 1..c)
+            .map(Token::into_node)
+            .collect::<Vec<_>>();
+        let node = match (closer.char, used) {
+            ('~', _) => Node::Strikethrough(inner),
+            (_, 2) => Node::Strong(inner),
+            _ => Node::Emphasis(inner),
+        };
+        tokens.insert(o + 1, Token::Node(node));
+        c = o + 2;
+        for i in [c, o] {
+            if let Some(d) = tokens[i].delimiter() {
+                d.count -= used;
+            }
+        }
+        if tokens[c].delimiter().is_some_and(|d| d.count == 0) {
+            tokens.remove(c);
+        }
+        if tokens[o].delimiter().is_some_and(|d| d.count == 0) {
+            tokens.remove(o);
+            c -= 1;
+        }
+    }
+    tokens.into_iter().map(Token::into_node).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> Inline {
+        Inline::Text(s.into(), Style::default())
+    }
+
+    fn styled(s: &str, f: impl FnOnce(&mut Style)) -> Inline {
+        let mut style = Style::default();
+        f(&mut style);
+        Inline::Text(s.into(), style)
+    }
+
+    #[test]
+    fn blocks_and_lines() {
+        let doc = "# Title\n\nSome *text*\nmore\n\n```rust\nfn main() {}\n```\n\n> quoted\n\n---\n";
+        let blocks = parse(doc);
+        let lines = blocks.iter().map(|b| b.line).collect::<Vec<_>>();
+        assert_eq!(lines, [0, 2, 5, 9, 11]);
+        assert_eq!(blocks[0].kind, BlockKind::Heading(1, vec![text("Title")]));
+        assert_eq!(
+            blocks[1].kind,
+            BlockKind::Paragraph(vec![
+                text("Some "),
+                styled("text", |s| s.emphasis = true),
+                text(" more"),
+            ])
+        );
+        assert_eq!(
+            blocks[2].kind,
+            BlockKind::Code {
+                language: "rust".into(),
+                text: "fn main() {}".into()
+            }
+        );
+        assert!(matches!(&blocks[3].kind, BlockKind::Quote(inner) if inner[0].line == 9));
+        assert_eq!(blocks[4].kind, BlockKind::Rule);
+
+        let setext = parse("Title\n=====\ntext");
+        assert_eq!(setext[0].kind, BlockKind::Heading(1, vec![text("Title")]));
+        assert_eq!(setext[1].line, 2);
+    }
+
+    #[test]
+    fn lists() {
+        let blocks = parse("- [x] done\n- todo\n  still todo\n\n  more\n1. one\n2. two\n");
+        let BlockKind::List { start, items } = &blocks[0].kind else {
+            panic!("{:?}", blocks[0]);
+        };
+        assert_eq!(*start, None);
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].checked, Some(true));
+        assert_eq!(items[1].checked, None);
+        assert_eq!(
+            items[1].blocks[0].kind,
+            BlockKind::Paragraph(vec![text("todo still todo")])
+        );
+        assert_eq!(items[1].blocks[1].line, 4);
+        assert!(matches!(
+            &blocks[1].kind,
+            BlockKind::List { start: Some(1), items } if items.len() == 2
+        ));
+    }
+
+    #[test]
+    fn tables() {
+        let blocks = parse("| a | b |\n|:--|--:|\n| 1 | `x\\|y` |\n| 2 |\n\nafter");
+        let BlockKind::Table {
+            aligns,
+            header,
+            rows,
+        } = &blocks[0].kind
+        else {
+            panic!("{:?}", blocks[0]);
+        };
+        assert_eq!(aligns, &[Align::Left, Align::Right]);
+        assert_eq!(header[1], [text("b")]);
+        assert_eq!(rows[0][1], [styled("x|y", |s| s.code = true)]);
+        assert!(rows[1][1].is_empty());
+        assert_eq!(blocks[1].line, 5);
+    }
+
+    #[test]
+    fn inline_styles() {
+        let refs = Refs::from([("home".into(), "https://example.com".into())]);
+        let link = |s: &str| styled(s, |s| s.link = Some("https://example.com".into()));
+        assert_eq!(
+            inlines("**bold _both_** ~~gone~~ `*code*`", &refs),
+            [
+                styled("bold ", |s| s.strong = true),
+                styled("both", |s| {
+                    s.strong = true;
+                    s.emphasis = true;
+                }),
+                text(" "),
+                styled("gone", |s| s.strikethrough = true),
+                text(" "),
+                styled("*code*", |s| s.code = true),
+            ]
+        );
+        assert_eq!(
+            inlines("[a](https://example.com \"t\")", &refs),
+            [link("a")]
+        );
+        assert_eq!(
+            inlines("[b][home] [Home]", &refs),
+            [link("b"), text(" "), link("Home")]
+        );
+        assert_eq!(
+            inlines("see https://example.com.", &refs),
+            [text("see "), link("https://example.com"), text(".")]
+        );
+        assert_eq!(
+            inlines("[![logo](img/a.png)](https://example.com)", &refs),
+            [Inline::Image {
+                alt: "logo".into(),
+                url: "img/a.png".into(),
+                link: Some("https://example.com".into()),
+            }]
+        );
+        assert_eq!(
+            inlines("a  \nb <br> 1 &lt; 2 \\*no\\* snake_case_name", &refs),
+            [
+                text("a"),
+                Inline::LineBreak,
+                text("b "),
+                Inline::LineBreak,
+                text(" 1 < 2 *no* snake_case_name"),
+            ]
+        );
+    }
+
+    #[test]
+    fn html_images() {
+        let blocks = parse("<p align=\"center\">\n  <img src=\"logo.png\" alt='Logo' width=100>\n</p>\n<!-- hidden -->\n");
+        assert_eq!(blocks.len(), 1);
+        let BlockKind::Paragraph(inlines) = &blocks[0].kind else {
+            panic!("{:?}", blocks[0]);
+        };
+        assert!(inlines.contains(&Inline::Image {
+            alt: "Logo".into(),
+            url: "logo.png".into(),
+            link: None,
+        }));
+    }
+
+    #[test]
+    fn non_ascii_attributes() {
+        assert_eq!(
+            attribute("img title=\"İİİ\" SRC='bild.png' ALT=Größe", "src").as_deref(),
+            Some("bild.png")
+        );
+        assert_eq!(
+            attribute("img title=\"İİİ\" SRC='bild.png' ALT=Größe", "alt").as_deref(),
+            Some("Größe")
+        );
+        assert_eq!(attribute("img alt=\"İ src=x\"", "src"), None);
+        assert_eq!(attribute("img İsrc=x", "src"), None);
+    }
+}
@@ -0,0 +1,400 @@
+//! A live preview of Markdown beside the editor, scrolled along with it.
+
+use crate::{
+    markdown::{self, Align, Block, BlockKind, Inline, Style},
+    syntax_highlighting::{self, CodeTheme},
+};
+use egui::{text::LayoutJob, FontId, RichText, Sense, TextureHandle, Vec2};
+use log::warn;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+/// Extensions of Markdown files
+const EXTENSIONS: [&str; 4] = ["md", "markdown", "mdown", "mkd"];
+
+/// Size of headings relative to the text, by level
+const HEADING_SIZES: [f32; 6] = [2.0, 1.6, 1.3, 1.15, 1.0, 0.9];
+
+/// Width of the bullets and numbers of list items
+const LIST_INDENT: f32 = 24.0;
+
+/// Whether `path` looks like a Markdown file.
+pub fn is_markdown(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|e| EXTENSIONS.contains(&e.to_string_lossy().to_lowercase().as_str()))
+}
+
+/// What a preview is drawn with.
+struct Fonts<'a> {
+    theme: &'a CodeTheme,
+    body: f32,
+    code: &'a FontId,
+}
+
+/// A Markdown document as it would be rendered.
+pub struct MarkdownPreview {
+    /// Text version the blocks are from
+    version: u64,
+    blocks: Vec<Block>,
+    /// Directory that links and images are relative to
+    base: PathBuf,
+    /// Local images by path, `None` if they can't be loaded
+    images: HashMap<PathBuf, Option<TextureHandle>>,
+    /// Source line and top of every block, as of the last frame
+    positions: Vec<(usize, f32)>,
+    /// Top of the content of the scroll area, this frame
+    content_top: f32,
+    /// Source line the preview was last scrolled to
+    synced_line: Option<usize>,
+    /// Highlighted code blocks by source line
+    code_jobs: HashMap<usize, LayoutJob>,
+    /// Theme, font and highlighter revision the code blocks were highlighted with
+    code_style: Option<(CodeTheme, FontId, Option<u64>)>,
+    /// Local file a link was clicked to, to be opened in a tab
+    clicked_file: Option<PathBuf>,
+}
+
+impl MarkdownPreview {
+    /// Links and images in `text` are relative to `base`.
+    pub fn new(text: &str, version: u64, base: PathBuf) -> Self {
+        Self {
+            version,
+            blocks: markdown::parse(text),
+            base,
+            images: HashMap::new(),
+            positions: vec![],
+            content_top: 0.0,
+            synced_line: None,
+            code_jobs: HashMap::new(),
+            code_style: None,
+            clicked_file: None,
+        }
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn set_text(&mut self, text: &str, version: u64) {
+        self.blocks = markdown::parse(text);
+        self.version = version;
+        self.code_jobs.clear();
+    }
+
+    /// The local file a link to was clicked, once.
+    pub fn take_clicked_file(&mut self) -> Option<PathBuf> {
+        self.clicked_file.take()
+    }
+
+    /// Whether the preview was scrolled to source line `line` already.
+    pub fn is_synced(&self, line: usize) -> bool {
+        self.synced_line == Some(line)
+    }
+
+    /// Show the document, scrolled to the part at source line `top_line` when that changed.
+    /// Code blocks are highlighted with `theme` in the `code` font.
+    pub fn ui(&mut self, ui: &mut egui::Ui, theme: &CodeTheme, code: &FontId, top_line: usize) {
+        let mut scroll = egui::ScrollArea::vertical()
+            .id_source("markdown preview")
+            .auto_shrink([false; 2]);
+        if !self.is_synced(top_line) {
+            if self.positions.is_empty() && !self.blocks.is_empty() {
+                // The blocks are only laid out once they were shown
+                ui.ctx().request_repaint();
+            } else {
+                if let Some(offset) = self.offset_of(top_line) {
+                    scroll = scroll.vertical_scroll_offset(offset);
+                }
+                self.synced_line = Some(top_line);
+            }
+        }
+        let code_style = (theme.clone(), code.clone(), syntax_highlighting::revision());
+        if self.code_style.as_ref() != Some(&code_style) {
+            self.code_jobs.clear();
+            self.code_style = Some(code_style);
+        }
+        let fonts = Fonts {
+            theme,
+            body: egui::TextStyle::Body.resolve(ui.style()).size,
+            code,
+        };
+        let blocks = std::mem::take(&mut self.blocks);
+        scroll.show(ui, |ui| {
+            self.content_top = ui.min_rect().top();
+            self.positions.clear();
+            self.blocks_ui(ui, &blocks, &fonts);
+        });
+        self.blocks = blocks;
+    }
+
+    /// Offset of source line `line`, between the blocks around it.
+    fn offset_of(&self, line: usize) -> Option<f32> {
+        let (first_line, _) = self.positions.first()?;
+        if line <= *first_line {
+            return Some(0.0);
+        }
+        let before = self.positions.iter().rposition(|(l, _)| *l <= line)?;
+        let (l0, y0) = self.positions[before];
+        let Some(&(l1, y1)) = self.positions[before + 1..].iter().find(|(l, _)| *l > l0) else {
+            return Some(y0);
+        };
+        Some(y0 + (y1 - y0) * (line - l0) as f32 / (l1 - l0) as f32)
+    }
+
+    fn blocks_ui(&mut self, ui: &mut egui::Ui, blocks: &[Block], fonts: &Fonts<'_>) {
+        for block in blocks {
+            self.positions
+                .push((block.line, ui.cursor().top() - self.content_top));
+            self.block_ui(ui, block, fonts);
+            ui.add_space(fonts.body * 0.5);
+        }
+    }
+
+    fn block_ui(&mut self, ui: &mut egui::Ui, block: &Block, fonts: &Fonts<'_>) {
+        match &block.kind {
+            BlockKind::Heading(level, inlines) => {
+                let size = fonts.body * HEADING_SIZES[usize::from(*level).clamp(1, 6) - 1];
+                ui.add_space(size * 0.3);
+                self.inlines_ui(ui, inlines, size, true);
+                if *level <= 2 {
+                    ui.separator();
+                }
+            }
+            BlockKind::Paragraph(inlines) => self.inlines_ui(ui, inlines, fonts.body, false),
+            BlockKind::Code { language, text } => {
+                let job = self
+                    .code_jobs
+                    .entry(block.line)
+                    .or_insert_with(|| {
+                        syntax_highlighting::highlight_code(fonts.theme, language, text, fonts.code)
+                    })
+                    .clone();
+                egui::Frame::none()
+                    .fill(ui.visuals().code_bg_color)
+                    .rounding(4.0)
+                    .inner_margin(8.0)
+                    .show(ui, |ui| {
+                        ui.set_width(ui.available_width());
+                        egui::ScrollArea::horizontal()
+                            .id_source(("code", block.line))
+                            .show(ui, |ui| ui.label(job));
+                    });
+            }
+            BlockKind::Quote(blocks) => {
+                let response = ui.horizontal(|ui| {
+                    ui.add_space(12.0);
+                    ui.vertical(|ui| self.blocks_ui(ui, blocks, fonts));
+                });
+                let rect = response.response.rect;
+                ui.painter().vline(
+                    rect.left() + 3.0,
+                    rect.y_range(),
+                    egui::Stroke::new(3.0, ui.visuals().widgets.noninteractive.bg_stroke.color),
+                );
+            }
+            BlockKind::List { start, items } => {
+                for (i, item) in items.iter().enumerate() {
+                    let marker = match (item.checked, start) {
+                        (Some(true), _) => "☑".to_string(),
+                        (Some(false), _) => "☐".to_string(),
+                        (None, Some(start)) => format!("{}.", start + i as u64),
+                        (None, None) => "•".to_string(),
+                    };
+                    ui.horizontal_top(|ui| {
+                        ui.allocate_ui_with_layout(
+                            Vec2::new(LIST_INDENT, 0.0),
+                            egui::Layout::right_to_left(egui::Align::TOP),
+                            |ui| ui.label(RichText::new(marker).size(fonts.body)),
+                        );
+                        ui.vertical(|ui| self.blocks_ui(ui, &item.blocks, fonts));
+                    });
+                }
+            }
+            BlockKind::Table {
+                aligns,
+                header,
+                rows,
+            } => {
+                egui::ScrollArea::horizontal()
+                    .id_source(("table", block.line))
+                    .show(ui, |ui| {
+                        egui::Grid::new(("table", block.line))
+                            .striped(true)
+                            .spacing(Vec2::new(16.0, 4.0))
+                            .show(ui, |ui| {
+                                let cells = std::iter::once((header, true))
+                                    .chain(rows.iter().map(|row| (row, false)));
+                                for (row, strong) in cells {
+                                    for (cell, align) in row.iter().zip(aligns) {
+                                        let layout = match align {
+                                            Align::Right => {
+                                                egui::Layout::right_to_left(egui::Align::Center)
+                                            }
+                                            Align::Center => {
+                                                egui::Layout::top_down(egui::Align::Center)
+                                            }
+                                            Align::None | Align::Left => {
+                                                egui::Layout::left_to_right(egui::Align::Center)
+                                            }
+                                        };
+                                        ui.with_layout(layout, |ui| {
+                                            self.inlines_ui(ui, cell, fonts.body, strong)
+                                        });
+                                    }
+                                    ui.end_row();
+                                }
+                            });
+                    });
+            }
+            BlockKind::Rule => {
+                ui.separator();
+            }
+        }
+    }
+
+    /// Text with links and images, wrapped at the edge of the preview.
+    fn inlines_ui(&mut self, ui: &mut egui::Ui, inlines: &[Inline], size: f32, strong: bool) {
+        ui.horizontal_wrapped(|ui| {
+            ui.spacing_mut().item_spacing.x = 0.0;
+            for inline in inlines {
+                match inline {
+                    Inline::Text(text, style) => {
+                        let text = rich_text(text, style, size, strong);
+                        match &style.link {
+                            Some(url) => {
+                                let link = ui.add(egui::Link::new(text)).on_hover_text(url);
+                                if link.clicked() {
+                                    self.open_link(ui, url);
+                                }
+                            }
+                            None => {
+                                ui.label(text);
+                            }
+                        }
+                    }
+                    Inline::Image { alt, url, link } => {
+                        let image = self.image_ui(ui, alt, url);
+                        if let Some(url) = link {
+                            let image = image.interact(Sense::click()).on_hover_text(url);
+                            if image.clicked() {
+                                self.open_link(ui, url);
+                            }
+                        }
+                    }
+                    Inline::LineBreak => ui.end_row(),
+                }
+            }
+        });
+    }
+
+    /// A local image, or its alt text if it can't be shown.
+    fn image_ui(&mut self, ui: &mut egui::Ui, alt: &str, url: &str) -> egui::Response {
+        match self.texture(ui.ctx(), url) {
+            Some(texture) => {
+                let size = texture.size_vec2();
+                let scale = (ui.max_rect().width() / size.x).min(1.0);
+                ui.image(texture.id(), size * scale).on_hover_text(alt)
+            }
+            None => ui
+                .label(RichText::new(format!("[{alt}]")).weak())
+                .on_hover_text(url),
+        }
+    }
+
+    /// The image at `url`, loaded once. Only local images relative to the document are.
+    fn texture(&mut self, ctx: &egui::Context, url: &str) -> Option<TextureHandle> {
+        let path = self.local_path(url)?;
+        self.images
+            .entry(path)
+            .or_insert_with_key(|path| load_image(ctx, path))
+            .clone()
+    }
+
+    /// Path of a link or image relative to the document, `None` for web urls.
+    fn local_path(&self, url: &str) -> Option<PathBuf> {
+        if url.contains("://") || url.starts_with("mailto:") || url.starts_with("data:") {
+            return None;
+        }
+        let path = url.split(['#', '?']).next().unwrap_or_default();
+        (!path.is_empty()).then(|| self.base.join(percent_decode(path)))
+    }
+
+    /// Open a web link in the browser, or a local file in a tab of its own. Other links are
+    /// never handed to the system, which could run programs.
+    fn open_link(&mut self, ui: &egui::Ui, url: &str) {
+        match self.local_path(url) {
+            Some(path) if path.is_file() => self.clicked_file = Some(path),
+            Some(path) => warn!("Can't open link to {}", path.display()),
+            None if is_web_link(url) => ui
+                .ctx()
+                .output_mut(|o| o.open_url = Some(egui::output::OpenUrl::new_tab(url))),
+            // Links to headings in the document
+            None if url.starts_with('#') => {}
+            None => warn!("Not opening link {url}"),
+        }
+    }
+}
+
+/// Whether `url` is for the browser or mail app.
+fn is_web_link(url: &str) -> bool {
+    let scheme = url.split_once(':').map_or("", |(scheme, _)| scheme);
+    ["http", "https", "mailto"]
+        .iter()
+        .any(|s| scheme.eq_ignore_ascii_case(s))
+}
+
+fn rich_text(text: &str, style: &Style, size: f32, strong: bool) -> RichText {
+    let mut text = RichText::new(text).size(size);
+    if style.strong || strong {
+        text = text.strong();
+    }
+    if style.emphasis {
+        text = text.italics();
+    }
+    if style.strikethrough {
+        text = text.strikethrough();
+    }
+    if style.code {
+        text = text.code();
+    }
+    text
+}
+
+fn load_image(ctx: &egui::Context, path: &Path) -> Option<TextureHandle> {
+    let image = match image::open(path) {
+        Ok(image) => image.to_rgba8(),
+        Err(e) => {
+            warn!("Can't load image {}: {e}", path.display());
+            return None;
+        }
+    };
+    let size = [image.width() as usize, image.height() as usize];
+    let image = egui::ColorImage::from_rgba_unmultiplied(size, image.as_raw());
+    Some(ctx.load_texture(path.to_string_lossy(), image, Default::default()))
+}
+
+/// `path` with escapes like `%20` replaced by the chars they stand for.
+fn percent_decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
@@ -0,0 +1,512 @@
+//! The carets and selections of the editor. There is always at least one; editing commands
+//! apply to all of them at once.
+
+use crate::line_buffer::{end_of, is_word_char, Edit, LineBuffer, Position};
+use std::ops::Range;
+
+/// A caret and the selection it extends.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Selection {
+    pub anchor: Position,
+    pub head: Position,
+}
+
+impl Selection {
+    pub fn caret(pos: Position) -> Self {
+        Self {
+            anchor: pos,
+            head: pos,
+        }
+    }
+
+    pub fn range(&self) -> Range<Position> {
+        self.anchor.min(self.head)..self.anchor.max(self.head)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.anchor == self.head
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Motion {
+    Left,
+    Right,
+    WordLeft,
+    WordRight,
+    Home,
+    End,
+    DocumentStart,
+    DocumentEnd,
+}
+
+#[derive(Clone, Debug)]
+pub struct MultiCursor {
+    /// Sorted and not overlapping
+    selections: Vec<Selection>,
+    /// The one that is scrolled to and reported in the status bar, usually the one added last
+    primary: usize,
+    /// Line and column where an Alt+drag started
+    block_origin: Option<(usize, usize)>,
+}
+
+impl Default for MultiCursor {
+    fn default() -> Self {
+        Self {
+            selections: vec![Selection::default()],
+            primary: 0,
+            block_origin: None,
+        }
+    }
+}
+
+impl MultiCursor {
+    pub fn selections(&self) -> &[Selection] {
+        &self.selections
+    }
+
+    pub fn primary(&self) -> Selection {
+        self.selections[self.primary]
+    }
+
+    /// Whether there is more than one caret.
+    pub fn is_multiple(&self) -> bool {
+        self.selections.len() > 1
+    }
+
+    /// Drop all selections but `selection`.
+    pub fn set(&mut self, selection: Selection) {
+        self.selections = vec![selection];
+        self.primary = 0;
+    }
+
+    /// Move the head of the primary selection, e.g. while dragging.
+    pub fn set_primary_head(&mut self, head: Position) {
+        self.selections[self.primary].head = head;
+        self.normalize();
+    }
+
+    /// Add a caret or selection and make it the primary one.
+    pub fn add(&mut self, selection: Selection) {
+        self.selections.push(selection);
+        self.primary = self.selections.len() - 1;
+        self.normalize();
+    }
+
+    /// Keep only the primary selection.
+    pub fn collapse(&mut self) {
+        self.set(self.primary());
+    }
+
+    /// Make sure all selections are inside `buffer`, e.g. after it was replaced.
+    pub fn clamp(&mut self, buffer: &LineBuffer) {
+        for s in &mut self.selections {
+            s.anchor = buffer.clamp(s.anchor);
+            s.head = buffer.clamp(s.head);
+        }
+        self.normalize();
+    }
+
+    /// Ctrl+D: select the word at the caret, or add the next occurrence of the primary selection.
+    pub fn add_next_occurrence(&mut self, buffer: &LineBuffer) {
+        let primary = self.primary();
+        if primary.is_empty() {
+            let word = buffer.word_at(primary.head);
+            if word.start != word.end {
+                self.selections[self.primary] = Selection {
+                    anchor: word.start,
+                    head: word.end,
+                };
+            }
+            return;
+        }
+        let range = primary.range();
+        let needle = buffer.slice(range.clone());
+        if let Some(found) = buffer.find(&needle, range.end) {
+            let selection = Selection {
+                anchor: found.start,
+                head: found.end,
+            };
+            if !self.selections.iter().any(|s| s.range() == found) {
+                self.add(selection);
+            }
+        }
+    }
+
+    /// Alt+drag: one selection per line between the two positions, in the same columns.
+    /// Positions are zero-based lines and columns, which may be past the end of a line.
+    pub fn set_block(&mut self, buffer: &LineBuffer, from: (usize, usize), to: (usize, usize)) {
+        let last = buffer.line_count() - 1;
+        let lines = from.0.min(to.0).min(last)..=from.0.max(to.0).min(last);
+        self.selections = lines
+            .map(|line| {
+                let len = buffer.line_len(line);
+                Selection {
+                    anchor: Position::new(line, from.1.min(len)),
+                    head: Position::new(line, to.1.min(len)),
+                }
+            })
+            .collect();
+        self.primary = if to.0 >= from.0 {
+            self.selections.len() - 1
+        } else {
+            0
+        };
+    }
+
+    /// Start or continue an Alt+drag at line and column `at`.
+    pub fn drag_block(&mut self, buffer: &LineBuffer, at: (usize, usize)) {
+        let origin = *self.block_origin.get_or_insert(at);
+        self.set_block(buffer, origin, at);
+    }
+
+    pub fn end_block(&mut self) {
+        self.block_origin = None;
+    }
+
+    pub fn is_dragging_block(&self) -> bool {
+        self.block_origin.is_some()
+    }
+
+    /// Replace every selection with `text`.
+    pub fn insert(&mut self, buffer: &mut LineBuffer, text: &str) -> Vec<Edit> {
+        let ranges = self.ranges();
+        self.replace(buffer, &ranges, |_| text)
+    }
+
+    /// Distributes the lines of `text` over the carets if there are as many, like copied from them.
+    pub fn paste(&mut self, buffer: &mut LineBuffer, text: &str) -> Vec<Edit> {
+        let lines = text.lines().collect::<Vec<_>>();
+        let ranges = self.ranges();
+        if ranges.len() > 1 && lines.len() == ranges.len() {
+            self.replace(buffer, &ranges, |i| lines[i])
+        } else {
+            self.replace(buffer, &ranges, |_| text)
+        }
+    }
+
+    /// The selected text, one line per selection.
+    pub fn copy(&self, buffer: &LineBuffer) -> String {
+        self.selections
+            .iter()
+            .map(|s| buffer.slice(s.range()))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Backspace or delete: the selections, or what `motion` passes over from empty ones.
+    pub fn delete(&mut self, buffer: &mut LineBuffer, motion: Motion) -> Vec<Edit> {
+        let mut ranges = self
+            .selections
+            .iter()
+            .map(|s| {
+                if s.is_empty() {
+                    let to = moved(buffer, s.head, motion);
+                    s.head.min(to)..s.head.max(to)
+                } else {
+                    s.range()
+                }
+            })
+            .collect::<Vec<_>>();
+        // Neighbouring carets may want to delete the same chars
+        for i in 1..ranges.len() {
+            ranges[i].start = ranges[i].start.max(ranges[i - 1].end);
+            ranges[i].end = ranges[i].end.max(ranges[i].start);
+        }
+        self.replace(buffer, &ranges, |_| "")
+    }
+
+    /// Replace `ranges`, one per selection, with the text for each, leaving a caret after it.
+    fn replace<'a>(
+        &mut self,
+        buffer: &mut LineBuffer,
+        ranges: &[Range<Position>],
+        text: impl Fn(usize) -> &'a str,
+    ) -> Vec<Edit> {
+        let mut edits = Vec::with_capacity(ranges.len());
+        // End of the last range before and after its edit. Everything behind it moved along.
+        let mut moved: Option<(Position, Position)> = None;
+        let map = |pos: Position, moved: Option<(Position, Position)>| match moved {
+            Some((old, new)) if pos.line == old.line => {
+                Position::new(new.line, new.column + pos.column - old.column)
+            }
+            Some((old, new)) => Position::new(pos.line + new.line - old.line, pos.column),
+            None => pos,
+        };
+        for (i, range) in ranges.iter().enumerate() {
+            let start = map(range.start, moved);
+            let end = map(range.end, moved);
+            let edit = buffer.replace(start..end, text(i));
+            let new_end = end_of(edit.at, &edit.inserted);
+            self.selections[i] = Selection::caret(new_end);
+            moved = Some((range.end, new_end));
+            edits.push(edit);
+        }
+        self.normalize();
+        edits
+    }
+
+    /// Select what `edits` inserted, like the text being composed with an input method.
+    pub fn select_inserted(&mut self, edits: &[Edit]) {
+        self.selections = edits
+            .iter()
+            .map(|e| Selection {
+                anchor: e.at,
+                head: end_of(e.at, &e.inserted),
+            })
+            .collect();
+        self.primary = self.primary.min(self.selections.len().saturating_sub(1));
+        if self.selections.is_empty() {
+            self.selections.push(Selection::default());
+        }
+        self.normalize();
+    }
+
+    pub fn move_carets(&mut self, buffer: &LineBuffer, motion: Motion, extend: bool) {
+        self.move_heads(extend, |s| {
+            let range = s.range();
+            match motion {
+                Motion::Left if !extend && !s.is_empty() => range.start,
+                Motion::Right if !extend && !s.is_empty() => range.end,
+                _ => moved(buffer, s.head, motion),
+            }
+        });
+    }
+
+    /// Move every head to where `to` says, dragging the anchor along unless `extend`ing the
+    /// selection. Used for motions that depend on the layout, like up and down.
+    pub fn move_heads(&mut self, extend: bool, to: impl Fn(&Selection) -> Position) {
+        for s in &mut self.selections {
+            let head = to(s);
+            *s = if extend {
+                Selection {
+                    anchor: s.anchor,
+                    head,
+                }
+            } else {
+                Selection::caret(head)
+            };
+        }
+        self.normalize();
+    }
+
+    pub fn select_all(&mut self, buffer: &LineBuffer) {
+        self.set(Selection {
+            anchor: Position::default(),
+            head: buffer.end(),
+        });
+    }
+
+    fn ranges(&self) -> Vec<Range<Position>> {
+        self.selections.iter().map(|s| s.range()).collect()
+    }
+
+    /// Sort and merge overlapping selections, keeping track of the primary one.
+    fn normalize(&mut self) {
+        let primary = self.primary();
+        self.selections.sort_by_key(|s| s.range().start);
+        let mut merged: Vec<Selection> = Vec::with_capacity(self.selections.len());
+        for s in self.selections.drain(..) {
+            match merged.last_mut() {
+                Some(last)
+                    if s.range().start < last.range().end
+                        || s.range().start == last.range().start =>
+                {
+                    let start = last.range().start;
+                    let end = last.range().end.max(s.range().end);
+                    *last = if s.head < s.anchor {
+                        Selection {
+                            anchor: end,
+                            head: start,
+                        }
+                    } else {
+                        Selection {
+                            anchor: start,
+                            head: end,
+                        }
+                    };
+                }
+                _ => merged.push(s),
+            }
+        }
+        self.selections = merged;
+        self.primary = self
+            .selections
+            .iter()
+            .position(|s| s.range().contains(&primary.head) || s.head == primary.head)
+            .unwrap_or(self.selections.len() - 1);
+    }
+}
+
+/// Where `motion` takes a caret at `pos`.
+fn moved(buffer: &LineBuffer, pos: Position, motion: Motion) -> Position {
+    let line_len = |line| buffer.line_len(line);
+    let last_line = buffer.line_count() - 1;
+    match motion {
+        Motion::Left if pos.column > 0 => Position::new(pos.line, pos.column - 1),
+        Motion::Left if pos.line > 0 => Position::new(pos.line - 1, line_len(pos.line - 1)),
+        Motion::Left => pos,
+        Motion::Right if pos.column < line_len(pos.line) => Position::new(pos.line, pos.column + 1),
+        Motion::Right if pos.line < last_line => Position::new(pos.line + 1, 0),
+        Motion::Right => pos,
+        Motion::WordLeft => word_left(buffer, pos),
+        Motion::WordRight => word_right(buffer, pos),
+        Motion::Home => Position::new(pos.line, 0),
+        Motion::End => Position::new(pos.line, line_len(pos.line)),
+        Motion::DocumentStart => Position::default(),
+        Motion::DocumentEnd => buffer.end(),
+    }
+}
+
+/// Skip whitespace, then a word or a run of punctuation. Stops at line starts.
+fn word_left(buffer: &LineBuffer, pos: Position) -> Position {
+    if pos.column == 0 {
+        return moved(buffer, pos, Motion::Left);
+    }
+    let chars = buffer.line(pos.line).chars().collect::<Vec<_>>();
+    let mut column = pos.column.min(chars.len());
+    while column > 0 && chars[column - 1].is_whitespace() {
+        column -= 1;
+    }
+    if column > 0 {
+        let word = is_word_char(chars[column - 1]);
+        while column > 0
+            && !chars[column - 1].is_whitespace()
+            && is_word_char(chars[column - 1]) == word
+        {
+            column -= 1;
+        }
+    }
+    Position::new(pos.line, column)
+}
+
+/// Skip whitespace, then a word or a run of punctuation. Stops at line ends.
+fn word_right(buffer: &LineBuffer, pos: Position) -> Position {
+    let chars = buffer.line(pos.line).chars().collect::<Vec<_>>();
+    if pos.column >= chars.len() {
+        return moved(buffer, pos, Motion::Right);
+    }
+    let mut column = pos.column;
+    while column < chars.len() && chars[column].is_whitespace() {
+        column += 1;
+    }
+    if column < chars.len() {
+        let word = is_word_char(chars[column]);
+        while column < chars.len()
+            && !chars[column].is_whitespace()
+            && is_word_char(chars[column]) == word
+        {
+            column += 1;
+        }
+    }
+    Position::new(pos.line, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn carets(positions: &[(usize, usize)]) -> MultiCursor {
+        let mut cursors = MultiCursor::default();
+        cursors.set(Selection::caret(Position::new(
+            positions[0].0,
+            positions[0].1,
+        )));
+        for (line, column) in &positions[1..] {
+            cursors.add(Selection::caret(Position::new(*line, *column)));
+        }
+        cursors
+    }
+
+    fn ranges(cursors: &MultiCursor) -> Vec<Range<Position>> {
+        cursors.selections.iter().map(|s| s.range()).collect()
+    }
+
+    #[test]
+    fn typing_at_all_carets() {
+        let mut buffer = LineBuffer::new("ab\ncd\nef");
+        let mut cursors = carets(&[(0, 0), (1, 0), (2, 0)]);
+        cursors.insert(&mut buffer, "> ");
+        assert_eq!(buffer.text("\n"), "> ab\n> cd\n> ef");
+        cursors.delete(&mut buffer, Motion::Left);
+        assert_eq!(buffer.text("\n"), ">ab\n>cd\n>ef");
+        cursors.move_carets(&buffer, Motion::End, false);
+        cursors.insert(&mut buffer, ";");
+        assert_eq!(buffer.text("\n"), ">ab;\n>cd;\n>ef;");
+    }
+
+    #[test]
+    fn typing_on_one_line() {
+        let mut buffer = LineBuffer::new("a b c");
+        let mut cursors = carets(&[(0, 1), (0, 3), (0, 5)]);
+        let edits = cursors.insert(&mut buffer, "\n");
+        assert_eq!(buffer.text("\n"), "a\n b\n c\n");
+        assert_eq!(
+            ranges(&cursors),
+            [1, 2, 3].map(|line| Position::new(line, 0)..Position::new(line, 0))
+        );
+        for edit in edits.iter().rev() {
+            edit.revert(&mut buffer);
+        }
+        assert_eq!(buffer.text("\n"), "a b c");
+    }
+
+    #[test]
+    fn adjacent_carets_merge() {
+        let mut buffer = LineBuffer::new("abc");
+        let mut cursors = carets(&[(0, 1), (0, 2)]);
+        cursors.delete(&mut buffer, Motion::Left);
+        assert_eq!(buffer.text("\n"), "c");
+        assert!(!cursors.is_multiple());
+    }
+
+    #[test]
+    fn next_occurrence() {
+        let buffer = LineBuffer::new("foo bar foo\nbaz foo");
+        let mut cursors = carets(&[(0, 1)]);
+        cursors.add_next_occurrence(&buffer);
+        let at = |line, column| Position::new(line, column)..Position::new(line, column + 3);
+        assert_eq!(cursors.primary().range(), at(0, 0));
+        cursors.add_next_occurrence(&buffer);
+        cursors.add_next_occurrence(&buffer);
+        assert_eq!(ranges(&cursors), [at(0, 0), at(0, 8), at(1, 4)]);
+        assert_eq!(cursors.primary().range(), at(1, 4));
+        // Wraps around to the ones we have already
+        cursors.add_next_occurrence(&buffer);
+        assert_eq!(cursors.selections.len(), 3);
+    }
+
+    #[test]
+    fn block_selection() {
+        let mut buffer = LineBuffer::new("a,1\nbb,2\nc");
+        let mut cursors = MultiCursor::default();
+        cursors.set_block(&buffer, (0, 1), (2, 3));
+        assert_eq!(
+            ranges(&cursors),
+            [
+                Position::new(0, 1)..Position::new(0, 3),
+                Position::new(1, 1)..Position::new(1, 3),
+                Position::new(2, 1)..Position::new(2, 1),
+            ]
+        );
+        assert_eq!(cursors.copy(&buffer), ",1\nb,\n");
+        cursors.paste(&mut buffer, "x\ny\nz");
+        assert_eq!(buffer.text("\n"), "ax\nby2\ncz");
+    }
+
+    #[test]
+    fn word_motions() {
+        let buffer = LineBuffer::new("let x = foo(bar);");
+        let mut pos = Position::new(0, 0);
+        let mut stops = vec![];
+        for _ in 0..7 {
+            pos = moved(&buffer, pos, Motion::WordRight);
+            stops.push(pos.column);
+        }
+        assert_eq!(stops, [3, 5, 7, 11, 12, 15, 17]);
+        assert_eq!(
+            moved(&buffer, Position::new(0, 15), Motion::WordLeft),
+            Position::new(0, 12)
+        );
+    }
+}
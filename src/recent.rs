@@ -0,0 +1,144 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct RecentFile {
+    pub path: PathBuf,
+    /// Seconds since the unix epoch
+    #[serde(default)]
+    pub opened: u64,
+    #[serde(default)]
+    pub pinned: bool,
+}
+
+/// Recently opened files, most recent first. Pinned entries are kept regardless of the limit.
+#[derive(serde::Deserialize, serde::Serialize, Default, Clone)]
+#[serde(transparent)]
+pub struct RecentFiles(Vec<RecentFile>);
+
+impl RecentFiles {
+    /// Move `path` to the front, dropping the oldest unpinned entries beyond `limit`.
+    pub fn touch(&mut self, path: &Path, limit: usize) {
+        let pinned = match self.0.iter().position(|r| r.path == path) {
+            Some(i) => self.0.remove(i).pinned,
+            None => false,
+        };
+        self.0.insert(
+            0,
+            RecentFile {
+                path: path.into(),
+                opened: now(),
+                pinned,
+            },
+        );
+        let mut unpinned = 0;
+        self.0.retain(|r| {
+            unpinned += usize::from(!r.pinned);
+            r.pinned || unpinned <= limit
+        });
+    }
+
+    pub fn remove(&mut self, path: &Path) {
+        self.0.retain(|r| r.path != path);
+    }
+
+    /// Forget everything except pinned entries.
+    pub fn clear(&mut self) {
+        self.0.retain(|r| r.pinned);
+    }
+
+    /// Drop entries whose file no longer exists.
+    pub fn prune(&mut self) {
+        self.0.retain(|r| r.path.exists());
+    }
+
+    pub fn toggle_pin(&mut self, path: &Path) {
+        if let Some(r) = self.0.iter_mut().find(|r| r.path == path) {
+            r.pinned = !r.pinned;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Entries in menu order: pinned first, then by last opened, with a display name
+    /// that has just enough parent folders to tell same-named files apart.
+    pub fn entries(&self) -> Vec<(String, RecentFile)> {
+        let mut entries = self.0.clone();
+        entries.sort_by_key(|r| !r.pinned);
+        let paths = entries.iter().map(|r| r.path.as_path()).collect::<Vec<_>>();
+        display_names(&paths).into_iter().zip(entries).collect()
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Shortest trailing part of each path that is unique among `paths`.
+fn display_names(paths: &[&Path]) -> Vec<String> {
+    let components = paths
+        .iter()
+        .map(|p| {
+            p.components()
+                .rev()
+                .map(|c| c.as_os_str().to_string_lossy().to_string())
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let mut depth = vec![1; paths.len()];
+
+    loop {
+        let names = components
+            .iter()
+            .zip(&depth)
+            .map(|(c, d)| {
+                let mut parts = c.iter().take(*d).cloned().collect::<Vec<_>>();
+                parts.reverse();
+                parts.join("/")
+            })
+            .collect::<Vec<_>>();
+
+        let mut counts = HashMap::new();
+        for name in &names {
+            *counts.entry(name.as_str()).or_insert(0) += 1;
+        }
+
+        let mut grew = false;
+        for (i, name) in names.iter().enumerate() {
+            if counts[name.as_str()] > 1 && depth[i] < components[i].len() {
+                depth[i] += 1;
+                grew = true;
+            }
+        }
+        if !grew {
+            return names;
+        }
+    }
+}
+
+/// Rough age of a timestamp, e.g. "3 hours ago".
+pub fn age(opened: u64) -> String {
+    if opened == 0 {
+        return "unknown".into();
+    }
+    let secs = now().saturating_sub(opened);
+    let (n, unit) = match secs {
+        0..=59 => return "just now".into(),
+        60..=3599 => (secs / 60, "minute"),
+        3600..=86399 => (secs / 3600, "hour"),
+        _ => (secs / 86400, "day"),
+    };
+    format!("{n} {unit}{} ago", if n == 1 { "" } else { "s" })
+}
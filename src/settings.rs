@@ -0,0 +1,667 @@
+use crate::{
+    recent::RecentFiles,
+    syntax_highlighting::{self, CodeTheme, SyntaxQuery},
+    wrap::DEFAULT_WRAP_COLUMN,
+};
+use anyhow::{bail, Result};
+use log::{info, warn};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+pub const EDITOR_FONT: &str = "editor";
+/// Font families of the bold and italic faces of the editor font
+pub const EDITOR_FONT_BOLD: &str = "editor-bold";
+pub const EDITOR_FONT_ITALIC: &str = "editor-italic";
+pub const EDITOR_FONT_BOLD_ITALIC: &str = "editor-bold-italic";
+pub const DEFAULT_FONT_SIZE: f32 = 12.0;
+pub const MIN_FONT_SIZE: f32 = 6.0;
+pub const MAX_FONT_SIZE: f32 = 72.0;
+
+/// Version of the settings file layout. Bump this and add a step to [`migrate`] when
+/// renaming fields or changing their meaning.
+const VERSION: i64 = 3;
+
+/// How often the settings file is checked for external changes
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct Settings {
+    pub line_numbers: bool,
+    pub relative_line_numbers: bool,
+    pub tree_view: bool,
+    /// Folder shown in the file tree
+    pub tree_root: Option<PathBuf>,
+    pub recent_files: RecentFiles,
+    /// How many unpinned recent files to remember
+    pub recent_limit: usize,
+    pub editor_font_size: f32,
+    /// User supplied .ttf/.otf used in the editor instead of the bundled font
+    pub editor_font: Option<PathBuf>,
+    /// Column of the vertical ruler in the editor, if there is one
+    pub ruler: Option<usize>,
+    /// Lines longer than this many chars are shown in chunks of that length
+    pub max_line_columns: usize,
+    /// Lines longer than this many bytes are not highlighted
+    pub max_highlight_len: usize,
+    /// Syntax names picked by hand for single files
+    pub language_overrides: BTreeMap<PathBuf, String>,
+    /// File name or extension to syntax name, e.g. `"h" = "C++"`
+    pub syntax_mappings: BTreeMap<String, String>,
+    pub theme_mode: ThemeMode,
+    /// Highlighting theme in dark mode
+    pub dark_theme: String,
+    /// Highlighting theme in light mode
+    pub light_theme: String,
+    /// Syntax name to theme, used instead of the dark or light theme
+    pub language_themes: BTreeMap<String, String>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ThemeMode {
+    Dark,
+    Light,
+    /// Whatever the operating system prefers
+    #[default]
+    System,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            line_numbers: false,
+            relative_line_numbers: false,
+            tree_view: false,
+            tree_root: None,
+            recent_files: Default::default(),
+            recent_limit: 10,
+            editor_font_size: DEFAULT_FONT_SIZE,
+            editor_font: None,
+            ruler: None,
+            max_line_columns: 5000,
+            max_highlight_len: 20_000,
+            language_overrides: Default::default(),
+            syntax_mappings: Default::default(),
+            theme_mode: ThemeMode::System,
+            dark_theme: CodeTheme::dark().theme_name().into(),
+            light_theme: CodeTheme::light().theme_name().into(),
+            language_themes: Default::default(),
+        }
+    }
+}
+
+impl Settings {
+    pub fn editor_font_id(&self) -> egui::FontId {
+        let size = if self.editor_font_size > 0.0 {
+            self.editor_font_size
+        } else {
+            DEFAULT_FONT_SIZE
+        };
+        egui::FontId::new(size, egui::FontFamily::Name(EDITOR_FONT.into()))
+    }
+
+    /// The user's choice for this file, then mappings by file name and extension.
+    pub fn syntax_query(&self, path: &Path) -> SyntaxQuery {
+        let mut query = SyntaxQuery::for_path(path);
+        query.name = self
+            .language_overrides
+            .get(path)
+            .or_else(|| self.syntax_mappings.get(&query.file_name))
+            .or_else(|| self.syntax_mappings.get(&query.extension))
+            .cloned();
+        query
+    }
+
+    /// Whether to use dark mode, given what the system prefers if we know it.
+    pub fn is_dark(&self, system_dark: Option<bool>) -> bool {
+        match self.theme_mode {
+            ThemeMode::Dark => true,
+            ThemeMode::Light => false,
+            ThemeMode::System => system_dark.unwrap_or(true),
+        }
+    }
+
+    /// Highlighting theme for a document in `syntax`, or in general with `None`.
+    pub fn code_theme(&self, dark: bool, syntax: Option<&str>) -> CodeTheme {
+        let default = if dark {
+            &self.dark_theme
+        } else {
+            &self.light_theme
+        };
+        let name = syntax
+            .and_then(|s| self.language_themes.get(s))
+            .unwrap_or(default);
+        CodeTheme::new(name.clone(), dark)
+    }
+
+    /// The dark or light theme, to change it.
+    pub fn theme_mut(&mut self, dark: bool) -> &mut String {
+        if dark {
+            &mut self.dark_theme
+        } else {
+            &mut self.light_theme
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        egui::Grid::new("settings")
+            .num_columns(2)
+            .striped(true)
+            .show(ui, |ui| {
+                ui.label("Line numbers");
+                ui.checkbox(&mut self.line_numbers, "");
+                ui.end_row();
+
+                ui.label("Relative line numbers");
+                ui.checkbox(&mut self.relative_line_numbers, "");
+                ui.end_row();
+
+                ui.label("File tree");
+                ui.checkbox(&mut self.tree_view, "");
+                ui.end_row();
+
+                ui.label("File tree folder");
+                ui.horizontal(|ui| {
+                    path_label(ui, self.tree_root.as_ref());
+                    if ui.button("…").clicked() {
+                        if let Some(p) = rfd::FileDialog::new().pick_folder() {
+                            self.tree_root = Some(p);
+                        }
+                    }
+                });
+                ui.end_row();
+
+                ui.label("Editor font size");
+                let mut size = self.editor_font_id().size;
+                if ui
+                    .add(egui::DragValue::new(&mut size).clamp_range(MIN_FONT_SIZE..=MAX_FONT_SIZE))
+                    .changed()
+                {
+                    self.editor_font_size = size;
+                }
+                ui.end_row();
+
+                ui.label("Editor font");
+                ui.horizontal(|ui| {
+                    path_label(ui, self.editor_font.as_ref());
+                    if ui.button("…").clicked() {
+                        if let Some(p) = rfd::FileDialog::new()
+                            .add_filter("Fonts", &["ttf", "otf"])
+                            .pick_file()
+                        {
+                            self.editor_font = Some(p);
+                        }
+                    }
+                    if self.editor_font.is_some() && ui.button("Reset").clicked() {
+                        self.editor_font = None;
+                    }
+                });
+                ui.end_row();
+
+                ui.label("Ruler");
+                self.ruler_ui(ui);
+                ui.end_row();
+
+                ui.label("Split lines longer than");
+                ui.horizontal(|ui| {
+                    ui.add(
+                        egui::DragValue::new(&mut self.max_line_columns)
+                            .clamp_range(100..=1_000_000),
+                    );
+                    ui.label("chars");
+                });
+                ui.end_row();
+
+                ui.label("Highlight lines up to");
+                ui.horizontal(|ui| {
+                    ui.add(
+                        egui::DragValue::new(&mut self.max_highlight_len)
+                            .clamp_range(100..=10_000_000),
+                    );
+                    ui.label("bytes");
+                });
+                ui.end_row();
+
+                ui.label("Recent files");
+                ui.horizontal(|ui| {
+                    ui.label(self.recent_files.len().to_string());
+                    if ui.button("Clear").clicked() {
+                        self.recent_files.clear();
+                    }
+                });
+                ui.end_row();
+
+                ui.label("Recent files limit");
+                ui.add(egui::DragValue::new(&mut self.recent_limit).clamp_range(1..=100));
+                ui.end_row();
+
+                ui.label("Language overrides");
+                ui.horizontal(|ui| {
+                    ui.label(self.language_overrides.len().to_string());
+                    if ui.button("Clear").clicked() {
+                        self.language_overrides.clear();
+                    }
+                });
+                ui.end_row();
+            });
+
+        ui.separator();
+        ui.label("Syntax mappings (file name or extension → syntax)");
+        mapping_ui(ui, &mut self.syntax_mappings);
+
+        ui.separator();
+        ui.label("Appearance");
+        self.appearance_ui(ui);
+    }
+
+    fn appearance_ui(&mut self, ui: &mut egui::Ui) {
+        egui::Grid::new("appearance")
+            .num_columns(2)
+            .striped(true)
+            .show(ui, |ui| {
+                ui.label("Mode");
+                ui.horizontal(|ui| self.theme_mode_ui(ui));
+                ui.end_row();
+
+                ui.label("Dark theme");
+                syntax_highlighting::theme_combo(ui, "dark_theme", &mut self.dark_theme);
+                ui.end_row();
+
+                ui.label("Light theme");
+                syntax_highlighting::theme_combo(ui, "light_theme", &mut self.light_theme);
+                ui.end_row();
+            });
+
+        ui.label("Themes per language");
+        let mut remove = None;
+        egui::Grid::new("language_themes")
+            .num_columns(3)
+            .show(ui, |ui| {
+                for (syntax, theme) in self.language_themes.iter_mut() {
+                    ui.label(syntax);
+                    syntax_highlighting::theme_combo(ui, syntax, theme);
+                    if ui.small_button("🗑").clicked() {
+                        remove = Some(syntax.clone());
+                    }
+                    ui.end_row();
+                }
+            });
+        if let Some(syntax) = remove {
+            self.language_themes.remove(&syntax);
+        }
+
+        let id = ui.id().with("new_language_theme");
+        let mut new: (String, String) = ui.data_mut(|d| d.get_temp(id).unwrap_or_default());
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_source("new_language_theme_syntax")
+                .selected_text(if new.0.is_empty() { "Language" } else { &new.0 })
+                .show_ui(ui, |ui| {
+                    for name in syntax_highlighting::syntax_names() {
+                        ui.selectable_value(&mut new.0, name.clone(), name);
+                    }
+                });
+            syntax_highlighting::theme_combo(ui, "new_language_theme", &mut new.1);
+            if ui.button("Add").clicked() && !new.0.is_empty() && !new.1.is_empty() {
+                self.language_themes
+                    .insert(std::mem::take(&mut new.0), std::mem::take(&mut new.1));
+            }
+        });
+        ui.data_mut(|d| d.insert_temp(id, new));
+    }
+
+    /// Checkbox to show the ruler, and its column
+    pub fn ruler_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            let mut shown = self.ruler.is_some();
+            let mut column = self.ruler.unwrap_or(DEFAULT_WRAP_COLUMN);
+            ui.checkbox(&mut shown, "At column");
+            ui.add_enabled(
+                shown,
+                egui::DragValue::new(&mut column).clamp_range(1..=1000),
+            );
+            self.ruler = shown.then_some(column);
+        });
+    }
+
+    /// Radio buttons for dark, light or system mode
+    pub fn theme_mode_ui(&mut self, ui: &mut egui::Ui) {
+        ui.radio_value(&mut self.theme_mode, ThemeMode::Dark, "Dark");
+        ui.radio_value(&mut self.theme_mode, ThemeMode::Light, "Light");
+        ui.radio_value(&mut self.theme_mode, ThemeMode::System, "Follow system");
+    }
+}
+
+fn mapping_ui(ui: &mut egui::Ui, mappings: &mut BTreeMap<String, String>) {
+    let mut remove = None;
+    egui::Grid::new("syntax_mappings")
+        .num_columns(3)
+        .show(ui, |ui| {
+            for (pattern, syntax) in mappings.iter_mut() {
+                ui.label(pattern);
+                ui.text_edit_singleline(syntax);
+                if ui.small_button("🗑").clicked() {
+                    remove = Some(pattern.clone());
+                }
+                ui.end_row();
+            }
+        });
+    if let Some(pattern) = remove {
+        mappings.remove(&pattern);
+    }
+
+    let id = ui.id().with("new_mapping");
+    let mut new: (String, String) = ui.data_mut(|d| d.get_temp(id).unwrap_or_default());
+    ui.horizontal(|ui| {
+        ui.add(
+            egui::TextEdit::singleline(&mut new.0)
+                .hint_text("rs, Makefile, …")
+                .desired_width(100.0),
+        );
+        ui.add(
+            egui::TextEdit::singleline(&mut new.1)
+                .hint_text("Syntax")
+                .desired_width(100.0),
+        );
+        if ui.button("Add").clicked() && !new.0.is_empty() && !new.1.is_empty() {
+            mappings.insert(std::mem::take(&mut new.0), std::mem::take(&mut new.1));
+        }
+    });
+    ui.data_mut(|d| d.insert_temp(id, new));
+}
+
+fn path_label(ui: &mut egui::Ui, path: Option<&PathBuf>) {
+    match path {
+        Some(p) => ui.label(p.display().to_string()),
+        None => ui.weak("(none)"),
+    };
+}
+
+/// The settings layout micron persisted in eframe's storage before the settings file existed.
+/// Read once to seed the settings file, as version 1.
+#[derive(serde::Deserialize, serde::Serialize, Default)]
+#[serde(default)]
+pub struct LegacySettings {
+    line_numbers: bool,
+    relative_line_numbers: bool,
+    tree_view: bool,
+    tree_root: Option<PathBuf>,
+    recent_files: BTreeSet<PathBuf>,
+    editor_font_size: f32,
+    editor_font: Option<PathBuf>,
+}
+
+impl LegacySettings {
+    pub fn migrate(&self) -> Result<Settings> {
+        let toml::Value::Table(mut table) = toml::Value::try_from(self)? else {
+            bail!("Legacy settings are not a table");
+        };
+        table.insert("version".into(), 1.into());
+        from_table(table)
+    }
+}
+
+/// Upgrade `table` one version at a time until it matches the current [`Settings`].
+fn migrate(table: &mut toml::Table, version: i64) -> Result<()> {
+    match version {
+        // v1 stored 0.0 for "never set"
+        1 => {
+            if table.get("editor_font_size").and_then(|v| v.as_float()) == Some(0.0) {
+                table.insert("editor_font_size".into(), (DEFAULT_FONT_SIZE as f64).into());
+            }
+        }
+        // v2 stored recent files as a sorted list of paths
+        2 => {
+            if let Some(toml::Value::Array(paths)) = table.get_mut("recent_files") {
+                for path in paths.iter_mut() {
+                    let mut entry = toml::Table::new();
+                    entry.insert("path".into(), path.clone());
+                    *path = entry.into();
+                }
+            }
+        }
+        _ => bail!("Don't know how to migrate settings version {version}"),
+    }
+    Ok(())
+}
+
+fn from_table(mut table: toml::Table) -> Result<Settings> {
+    // A hand written file without a version is assumed to be current
+    let mut version = match table.remove("version") {
+        Some(v) => v.as_integer().unwrap_or(VERSION),
+        None => VERSION,
+    };
+    if version > VERSION {
+        bail!("Settings version {version} is newer than this micron ({VERSION})");
+    }
+    while version < VERSION {
+        migrate(&mut table, version)?;
+        version += 1;
+    }
+    Ok(toml::Value::Table(table).try_into()?)
+}
+
+fn to_string(settings: &Settings) -> Result<String> {
+    let toml::Value::Table(mut table) = toml::Value::try_from(settings)? else {
+        bail!("Settings are not a table");
+    };
+    table.insert("version".into(), VERSION.into());
+    Ok(toml::to_string_pretty(&table)?)
+}
+
+/// Micron's folder in the user's config dir, e.g. `~/.config/micron`.
+pub fn config_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|d| d.join("micron"))
+}
+
+/// The human editable settings file in the user's config dir, e.g. `~/.config/micron/settings.toml`.
+/// Written whenever the settings change, and reloaded when it is edited externally.
+#[derive(Default)]
+pub struct SettingsFile {
+    path: Option<PathBuf>,
+    /// Modification time of the file when we last read or wrote it
+    modified: Option<SystemTime>,
+    /// What we last wrote, to avoid writing the same content every frame
+    written: String,
+    last_poll: Option<Instant>,
+    /// Parse error of the file on disk. While set, we don't overwrite the file.
+    error: Option<String>,
+}
+
+impl SettingsFile {
+    pub fn new() -> Self {
+        Self {
+            path: config_dir().map(|d| d.join("settings.toml")),
+            ..Default::default()
+        }
+    }
+
+    /// Read the settings file. Returns `None` if there is none yet or it is invalid.
+    pub fn load(&mut self) -> Option<Settings> {
+        let path = self.path.as_ref()?;
+        self.last_poll = Some(Instant::now());
+        self.modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+        let content = fs::read_to_string(path).ok()?;
+        match toml::from_str(&content)
+            .map_err(anyhow::Error::from)
+            .and_then(from_table)
+        {
+            Ok(settings) => {
+                info!("Loaded settings from {}", path.display());
+                self.error = None;
+                // Compare against the canonical form, so we only rewrite the file on real changes
+                self.written = to_string(&settings).unwrap_or(content);
+                Some(settings)
+            }
+            Err(e) => {
+                warn!("Invalid settings file {}: {e}", path.display());
+                self.error = Some(e.to_string());
+                None
+            }
+        }
+    }
+
+    /// Returns the new settings if the file was changed on disk since we last saw it.
+    pub fn poll(&mut self, ctx: &egui::Context) -> Option<Settings> {
+        ctx.request_repaint_after(POLL_INTERVAL);
+        if self
+            .last_poll
+            .is_some_and(|last| last.elapsed() < POLL_INTERVAL)
+        {
+            return None;
+        }
+        self.last_poll = Some(Instant::now());
+        let modified = fs::metadata(self.path.as_ref()?)
+            .and_then(|m| m.modified())
+            .ok();
+        if modified.is_none() || modified == self.modified {
+            return None;
+        }
+        self.load()
+    }
+
+    /// Force a reload on the next [`Self::poll`].
+    pub fn reload(&mut self) {
+        self.modified = None;
+        self.last_poll = None;
+    }
+
+    /// Write `settings` to disk if they differ from what is there.
+    pub fn store(&mut self, settings: &Settings) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        if self.error.is_some() {
+            return;
+        }
+        let content = match to_string(settings) {
+            Ok(content) => content,
+            Err(e) => {
+                warn!("Can't serialize settings: {e}");
+                return;
+            }
+        };
+        if content == self.written {
+            return;
+        }
+        if let Some(dir) = path.parent() {
+            _ = fs::create_dir_all(dir);
+        }
+        match fs::write(path, &content) {
+            Ok(()) => {
+                self.modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+                self.written = content;
+            }
+            Err(e) => warn!("Can't write {}: {e}", path.display()),
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Settings file:");
+            path_label(ui, self.path.as_ref());
+            if ui.button("Reload").clicked() {
+                self.reload();
+            }
+        });
+        if let Some(error) = &self.error {
+            ui.colored_label(ui.visuals().error_fg_color, error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recent_paths(settings: &Settings) -> Vec<PathBuf> {
+        let entries = settings.recent_files.entries().into_iter();
+        entries.map(|(_, recent)| recent.path).collect()
+    }
+
+    #[test]
+    fn migrations() {
+        // A settings file, and the font size and recent files it loads with
+        let cases: [(&str, f32, &[&str]); 5] = [
+            (
+                r#"
+                version = 1
+                line_numbers = true
+                editor_font_size = 0.0
+                recent_files = ["/a.txt", "/b.rs"]
+                "#,
+                DEFAULT_FONT_SIZE,
+                &["/a.txt", "/b.rs"],
+            ),
+            (
+                r#"
+                version = 1
+                line_numbers = true
+                editor_font_size = 14.0
+                "#,
+                14.0,
+                &[],
+            ),
+            (
+                r#"
+                version = 2
+                line_numbers = true
+                editor_font_size = 16.0
+                recent_files = ["/c.md"]
+                "#,
+                16.0,
+                &["/c.md"],
+            ),
+            (
+                r#"
+                version = 3
+                line_numbers = true
+                recent_files = [{ path = "/d", opened = 5, pinned = true }]
+                "#,
+                DEFAULT_FONT_SIZE,
+                &["/d"],
+            ),
+            (
+                r#"
+                line_numbers = true
+                recent_files = [{ path = "/e" }]
+                "#,
+                DEFAULT_FONT_SIZE,
+                &["/e"],
+            ),
+        ];
+        for (input, font_size, recent) in cases {
+            let settings = from_table(toml::from_str(input).unwrap()).unwrap();
+            assert!(settings.line_numbers, "{input}");
+            assert_eq!(settings.editor_font_size, font_size, "{input}");
+            assert_eq!(
+                recent_paths(&settings),
+                recent.iter().map(PathBuf::from).collect::<Vec<_>>(),
+                "{input}"
+            );
+        }
+
+        assert!(from_table(toml::from_str("version = 4").unwrap()).is_err());
+    }
+
+    #[test]
+    fn legacy_settings() {
+        let legacy = LegacySettings {
+            tree_view: true,
+            recent_files: ["/b", "/a"].into_iter().map(PathBuf::from).collect(),
+            ..Default::default()
+        };
+        let settings = legacy.migrate().unwrap();
+        assert!(settings.tree_view);
+        assert_eq!(settings.editor_font_size, DEFAULT_FONT_SIZE);
+        assert_eq!(recent_paths(&settings), [PathBuf::from("/a"), "/b".into()]);
+
+        let written = to_string(&settings).unwrap();
+        let reloaded = from_table(toml::from_str(&written).unwrap()).unwrap();
+        assert_eq!(to_string(&reloaded).unwrap(), written);
+    }
+}
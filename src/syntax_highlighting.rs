@@ -1,28 +1,33 @@
-use egui::text::LayoutJob;
-
-
-
-/// Memoized Code highlighting
-pub fn highlight(ctx: &egui::Context, theme: &CodeTheme, code: &str, language: &str) -> LayoutJob {
-    impl egui::util::cache::ComputerMut<(&CodeTheme, &str, &str), LayoutJob> for Highlighter {
-        fn compute(&mut self, (theme, code, lang): (&CodeTheme, &str, &str)) -> LayoutJob {
-            self.highlight(theme, code, lang)
-        }
-    }
-
-    type HighlightCache = egui::util::cache::FrameCache<LayoutJob, Highlighter>;
-
-    ctx.memory_mut(|mem| {
-        mem.caches
-            .cache::<HighlightCache>()
-            .get((theme, code, language))
-    })
-}
+use crate::{
+    delimited,
+    line_buffer::{Edit, LineBuffer},
+    settings::{EDITOR_FONT, EDITOR_FONT_BOLD, EDITOR_FONT_BOLD_ITALIC, EDITOR_FONT_ITALIC},
+};
+use egui::{
+    text::{LayoutJob, LayoutSection, TextFormat},
+    FontId,
+};
+use log::{info, warn};
+use std::{
+    fs,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, AtomicU8, Ordering},
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex, RwLock,
+    },
+    time::Instant,
+};
+use syntect::{
+    highlighting::{HighlightState, RangedHighlightIterator, Style, Theme, ThemeSet},
+    parsing::{ParseState, ScopeStack, SyntaxDefinition, SyntaxReference, SyntaxSet},
+};
 
 // ----------------------------------------------------------------------------
 
+/// The themes that come with syntect. More can be added as `.tmTheme` files.
 #[derive(Clone, Copy, Hash, PartialEq)]
-#[derive(serde::Deserialize, serde::Serialize)]
 enum SyntectTheme {
     Base16EightiesDark,
     Base16MochaDark,
@@ -72,7 +77,7 @@ impl SyntectTheme {
         }
     }
 
-    pub fn is_dark(&self) -> bool {
+    fn is_dark(&self) -> bool {
         match self {
             Self::Base16EightiesDark
             | Self::Base16MochaDark
@@ -85,11 +90,10 @@ impl SyntectTheme {
 }
 
 #[derive(Clone, Hash, PartialEq)]
-#[derive(serde::Deserialize, serde::Serialize)]
-#[serde(default)]
 pub struct CodeTheme {
     dark_mode: bool,
-    syntect_theme: SyntectTheme,
+    /// Key of the theme in the theme set, either built in or loaded from the config dir
+    theme_name: String,
 }
 
 impl Default for CodeTheme {
@@ -99,154 +103,1579 @@ impl Default for CodeTheme {
 }
 
 impl CodeTheme {
-    pub fn from_style(style: &egui::Style) -> Self {
-        if style.visuals.dark_mode {
-            Self::dark()
-        } else {
-            Self::light()
+    /// `dark_mode` is the mode the theme was picked for, which decides the fallback colors.
+    pub fn new(theme_name: String, dark_mode: bool) -> Self {
+        Self {
+            dark_mode,
+            theme_name,
         }
     }
 
-    pub fn from_memory(ctx: &egui::Context) -> Self {
-        if ctx.style().visuals.dark_mode {
-            ctx.data_mut(|d| {
-                d.get_persisted(egui::Id::new("dark"))
-                    .unwrap_or_else(CodeTheme::dark)
-            })
-        } else {
-            ctx.data_mut(|d| {
-                d.get_persisted(egui::Id::new("light"))
-                    .unwrap_or_else(CodeTheme::light)
-            })
+    pub fn dark() -> Self {
+        Self {
+            dark_mode: true,
+            theme_name: SyntectTheme::Base16MochaDark.syntect_key_name().into(),
         }
     }
 
-    pub fn store_in_memory(self, ctx: &egui::Context) {
-        if self.dark_mode {
-            ctx.data_mut(|d| d.insert_persisted(egui::Id::new("dark"), self));
-        } else {
-            ctx.data_mut(|d| d.insert_persisted(egui::Id::new("light"), self));
+    pub fn light() -> Self {
+        Self {
+            dark_mode: false,
+            theme_name: SyntectTheme::SolarizedLight.syntect_key_name().into(),
+        }
+    }
+
+    /// Key of the theme in the theme set
+    pub fn theme_name(&self) -> &str {
+        &self.theme_name
+    }
+
+    /// Whether the theme has a dark background
+    pub fn is_dark(&self) -> bool {
+        SyntectTheme::all()
+            .find(|t| t.syntect_key_name() == self.theme_name)
+            .map(|t| t.is_dark())
+            .or_else(|| with_theme(&self.theme_name, is_dark))
+            .unwrap_or(self.dark_mode)
+    }
+
+    /// Switch egui to dark or light mode, whichever goes with the theme.
+    pub fn sync_dark_mode(&self, ctx: &egui::Context) {
+        let dark = self.is_dark();
+        if ctx.style().visuals.dark_mode != dark {
+            ctx.set_visuals(if dark {
+                egui::Visuals::dark()
+            } else {
+                egui::Visuals::light()
+            });
         }
     }
+
+    /// Colors for the editor around the text. What the theme doesn't specify comes from `visuals`.
+    pub fn colors(&self, visuals: &egui::Visuals) -> EditorColors {
+        let fallback = EditorColors {
+            background: visuals.panel_fill,
+            foreground: plain_color(self),
+            selection: visuals.selection.bg_fill,
+            caret: visuals.selection.stroke.color,
+            gutter: visuals.panel_fill,
+            gutter_foreground: visuals.weak_text_color(),
+            line_highlight: visuals.faint_bg_color,
+        };
+        with_theme(&self.theme_name, |theme| {
+            let s = &theme.settings;
+            let color =
+                |c: Option<syntect::highlighting::Color>, fallback| c.map_or(fallback, to_color32);
+            let background = color(s.background, fallback.background);
+            let foreground = color(s.foreground, fallback.foreground);
+            EditorColors {
+                background,
+                foreground,
+                selection: color(s.selection, fallback.selection),
+                caret: color(s.caret, foreground),
+                gutter: color(s.gutter, background),
+                gutter_foreground: color(s.gutter_foreground, foreground.gamma_multiply(0.5)),
+                line_highlight: color(s.line_highlight, fallback.line_highlight),
+            }
+        })
+        .unwrap_or(fallback)
+    }
 }
 
-impl CodeTheme {
-    pub fn dark() -> Self {
-        Self {
-            dark_mode: true,
-            syntect_theme: SyntectTheme::Base16MochaDark,
+/// Radio buttons for the themes that go with dark or light mode.
+pub fn theme_radios(ui: &mut egui::Ui, selected: &mut String, dark: bool) {
+    for (name, label, is_dark) in theme_choices() {
+        if is_dark == dark {
+            ui.radio_value(selected, name, label);
         }
     }
+}
 
-    pub fn light() -> Self {
-        Self {
-            dark_mode: false,
-            syntect_theme: SyntectTheme::SolarizedLight,
+/// Combo box with all themes, dark and light.
+pub fn theme_combo(ui: &mut egui::Ui, id_source: impl std::hash::Hash, selected: &mut String) {
+    let choices = theme_choices();
+    let text = choices
+        .iter()
+        .find(|(name, ..)| name == selected)
+        .map_or_else(|| selected.clone(), |(_, label, _)| label.clone());
+    egui::ComboBox::from_id_source(id_source)
+        .selected_text(text)
+        .show_ui(ui, |ui| {
+            for (name, label, _) in choices {
+                ui.selectable_value(selected, name, label);
+            }
+        });
+}
+
+/// Key, display name and darkness of all themes: the built-in ones first, then user themes.
+/// Only the built-in ones while the themes are still loading.
+pub fn theme_choices() -> Vec<(String, String, bool)> {
+    let mut choices = SyntectTheme::all()
+        .map(|t| (t.syntect_key_name().into(), t.name().into(), t.is_dark()))
+        .collect::<Vec<_>>();
+    if let Some(highlighter) = Highlighter::try_get() {
+        for (name, theme) in &highlighter.ts.themes {
+            if !SyntectTheme::all().any(|t| t.syntect_key_name() == name) {
+                choices.push((name.clone(), name.clone(), is_dark(theme)));
+            }
         }
     }
+    choices
+}
 
-    pub fn ui(&mut self, ui: &mut egui::Ui) {
-        egui::widgets::global_dark_light_mode_buttons(ui);
+/// Run `f` with the theme called `name`. Returns `None` if there is no such theme, or while
+/// the themes are still loading.
+fn with_theme<R>(name: &str, f: impl FnOnce(&Theme) -> R) -> Option<R> {
+    if let Some((_, theme)) = PREVIEW.read().ok()?.as_ref().filter(|(n, _)| n == name) {
+        return Some(f(theme));
+    }
+    Highlighter::try_get()?.ts.themes.get(name).map(f)
+}
+
+pub fn to_color32(c: syntect::highlighting::Color) -> egui::Color32 {
+    egui::Color32::from_rgba_unmultiplied(c.r, c.g, c.b, c.a)
+}
+
+/// A copy of the theme called `name`, to base a new one on. `None` if there is no such theme,
+/// or while the themes are still loading.
+pub fn theme(name: &str) -> Option<Theme> {
+    Highlighter::try_get()?.ts.themes.get(name).cloned()
+}
+
+/// Changes whenever the syntaxes or themes do. `None` while they are still loading.
+pub fn revision() -> Option<u64> {
+    Highlighter::try_get().map(|_| REVISION.load(Ordering::Relaxed))
+}
 
-        for theme in SyntectTheme::all() {
-            if theme.is_dark() == self.dark_mode {
-                ui.radio_value(&mut self.syntect_theme, theme, theme.name());
+/// Show `theme` instead of the theme called `name` in all documents, or stop doing so with
+/// `None`. The settings keep naming the theme it replaces.
+pub fn preview_theme(preview: Option<(String, Theme)>) {
+    *PREVIEW.write().unwrap_or_else(|e| e.into_inner()) =
+        preview.map(|(name, theme)| (name, Arc::new(theme)));
+    REVISION.fetch_add(1, Ordering::Relaxed);
+}
+
+/// The bold, italic and bold italic faces of the editor font
+const FACE_FAMILIES: [&str; 3] = [
+    EDITOR_FONT_BOLD,
+    EDITOR_FONT_ITALIC,
+    EDITOR_FONT_BOLD_ITALIC,
+];
+
+/// Tell the highlighter which of the editor font's bold and italic face families hold a
+/// real face. The others are only fallbacks to the regular face.
+pub fn set_font_faces(faces: &[&str]) {
+    let bits = FACE_FAMILIES
+        .iter()
+        .enumerate()
+        .filter(|(_, family)| faces.contains(family))
+        .fold(0, |bits, (i, _)| bits | 1 << i);
+    if FONT_FACES.swap(bits, Ordering::Relaxed) != bits {
+        REVISION.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Highlight a short piece of text in one go, e.g. for a preview. Plain while the syntaxes
+/// are still loading.
+pub fn highlight_snippet(
+    theme: &Theme,
+    extension: &str,
+    text: &str,
+    font_id: &FontId,
+) -> LayoutJob {
+    let highlighter = Highlighter::try_get();
+    let syntax = highlighter
+        .as_ref()
+        .and_then(|h| h.ps.find_syntax_by_extension(extension));
+    snippet_job(
+        highlighter.as_deref(),
+        theme,
+        syntax,
+        text,
+        font_id,
+        egui::Color32::GRAY,
+    )
+}
+
+/// Highlight a code block in `language`, a syntax name or extension like the language of a
+/// fenced code block in Markdown. Plain if there is no such language, or while the syntaxes
+/// are still loading.
+pub fn highlight_code(
+    theme: &CodeTheme,
+    language: &str,
+    text: &str,
+    font_id: &FontId,
+) -> LayoutJob {
+    let highlighter = Highlighter::try_get();
+    let syntax = highlighter
+        .as_ref()
+        .filter(|_| !language.is_empty())
+        .and_then(|h| h.ps.find_syntax_by_token(language));
+    let plain = plain_color(theme);
+    with_theme(&theme.theme_name, |t| {
+        snippet_job(highlighter.as_deref(), t, syntax, text, font_id, plain)
+    })
+    .unwrap_or_else(|| LayoutJob::simple(text.into(), font_id.clone(), plain, f32::INFINITY))
+}
+
+/// `text` highlighted as `syntax`, or in the `plain` color without one.
+fn snippet_job(
+    highlighter: Option<&Highlighter>,
+    theme: &Theme,
+    syntax: Option<&SyntaxReference>,
+    text: &str,
+    font_id: &FontId,
+    plain: egui::Color32,
+) -> LayoutJob {
+    let mut job = LayoutJob::default();
+    let (Some(highlighter), Some(syntax)) = (highlighter, syntax) else {
+        job.append(text, 0.0, TextFormat::simple(font_id.clone(), plain));
+        return job;
+    };
+    let mut lines = syntect::easy::HighlightLines::new(syntax, theme);
+    for line in syntect::util::LinesWithEndings::from(text) {
+        match lines.highlight_line(line, &highlighter.ps) {
+            Ok(styles) => {
+                for (style, range) in styles {
+                    job.append(range, 0.0, text_format(&style, font_id));
+                }
             }
+            Err(_) => job.append(line, 0.0, TextFormat::simple(font_id.clone(), plain)),
         }
     }
+    job
+}
+
+/// Guess from the background color, for themes we don't know.
+fn is_dark(theme: &Theme) -> bool {
+    theme.settings.background.map_or(true, |c| {
+        0.299 * f32::from(c.r) + 0.587 * f32::from(c.g) + 0.114 * f32::from(c.b) < 128.0
+    })
+}
+
+/// Colors of the editor itself, as opposed to the highlighted text
+#[derive(Clone, Copy)]
+pub struct EditorColors {
+    pub background: egui::Color32,
+    /// Text that isn't highlighted (yet)
+    pub foreground: egui::Color32,
+    pub selection: egui::Color32,
+    pub caret: egui::Color32,
+    pub gutter: egui::Color32,
+    pub gutter_foreground: egui::Color32,
+    pub line_highlight: egui::Color32,
 }
 
 // ----------------------------------------------------------------------------
 
 struct Highlighter {
-    ps: syntect::parsing::SyntaxSet,
-    ts: syntect::highlighting::ThemeSet,
+    ps: SyntaxSet,
+    ts: ThemeSet,
 }
 
-impl Default for Highlighter {
-    fn default() -> Self {
+const SYNTAX_DUMP: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/syntaxes.packdump"));
+const THEME_DUMP: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/themes.themedump"));
+
+/// Loading the syntaxes is slow, so all files share one instance.
+static HIGHLIGHTER: RwLock<Option<Arc<Highlighter>>> = RwLock::new(None);
+/// Held while loading, so only one thread does it
+static LOADING: Mutex<()> = Mutex::new(());
+/// Bumped whenever syntaxes or themes change, so all documents are highlighted again.
+static REVISION: AtomicU64 = AtomicU64::new(0);
+/// A theme being edited, and the name of the theme it is shown instead of
+static PREVIEW: RwLock<Option<(String, Arc<Theme>)>> = RwLock::new(None);
+/// Which of the [`FACE_FAMILIES`] are installed, one bit each
+static FONT_FACES: AtomicU8 = AtomicU8::new(0);
+
+impl Highlighter {
+    /// The shared instance, loading it first if needed.
+    fn get() -> Arc<Self> {
+        if let Some(highlighter) = Self::try_get() {
+            return highlighter;
+        }
+        let _loading = LOADING.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(highlighter) = Self::try_get() {
+            return highlighter;
+        }
+        let highlighter = Arc::new(Self::load());
+        *HIGHLIGHTER.write().unwrap_or_else(|e| e.into_inner()) = Some(highlighter.clone());
+        highlighter
+    }
+
+    /// The shared instance, unless it is still loading. Never blocks for long.
+    fn try_get() -> Option<Arc<Self>> {
+        HIGHLIGHTER.read().ok().and_then(|h| h.clone())
+    }
+
+    /// The bundled syntaxes and themes, precompiled by `build.rs`, plus the user's from
+    /// `syntaxes/` and `themes/` in the config dir. Files that fail to load are skipped.
+    fn load() -> Self {
+        let start = Instant::now();
+        let mut ps: SyntaxSet = syntect::dumps::from_uncompressed_data(SYNTAX_DUMP)
+            .expect("the syntax dump is built with the same syntect");
+        let mut ts: ThemeSet = syntect::dumps::from_binary(THEME_DUMP);
+        info!(
+            "Loaded bundled syntaxes and themes in {:?}",
+            start.elapsed()
+        );
+
+        if let Some(dir) = crate::settings::config_dir() {
+            let user_syntaxes = files_with_extension(&dir.join("syntaxes"), "sublime-syntax");
+            // Linking the syntaxes again is slow, so only do it if there is something to add
+            if !user_syntaxes.is_empty() {
+                let mut builder = ps.into_builder();
+                for path in user_syntaxes {
+                    let name = path.file_stem().and_then(|s| s.to_str());
+                    match fs::read_to_string(&path)
+                        .map_err(anyhow::Error::from)
+                        .and_then(|s| Ok(SyntaxDefinition::load_from_str(&s, true, name)?))
+                    {
+                        Ok(syntax) => {
+                            info!("Loaded syntax {} from {}", syntax.name, path.display());
+                            builder.add(syntax);
+                        }
+                        Err(e) => warn!("Can't load syntax {}: {e}", path.display()),
+                    }
+                }
+                ps = builder.build();
+            }
+            for path in files_with_extension(&dir.join("themes"), "tmTheme") {
+                let Some(name) = path.file_stem().map(|s| s.to_string_lossy().to_string()) else {
+                    continue;
+                };
+                match ThemeSet::get_theme(&path) {
+                    Ok(theme) => {
+                        info!("Loaded theme {name} from {}", path.display());
+                        ts.themes.insert(name, theme);
+                    }
+                    Err(e) => warn!("Can't load theme {}: {e}", path.display()),
+                }
+            }
+        }
+
+        info!("Syntaxes and themes ready after {:?}", start.elapsed());
+        Self { ps, ts }
+    }
+
+    /// Pick a syntax: explicit choice first, then well known file names, the first line
+    /// (e.g. a shebang) and finally the extension.
+    fn find_syntax(&self, text: &str, query: &SyntaxQuery) -> Option<&SyntaxReference> {
+        if let Some(name) = &query.name {
+            if let Some(syntax) = self
+                .ps
+                .find_syntax_by_name(name)
+                .or_else(|| self.ps.find_syntax_by_extension(name))
+            {
+                return Some(syntax);
+            }
+        }
+        let by_file_name = FILE_NAME_RULES
+            .iter()
+            .find(|(file_name, _)| *file_name == query.file_name)
+            .and_then(|(_, name)| self.ps.find_syntax_by_name(name));
+        let first_line = text.lines().next().unwrap_or_default();
+        by_file_name
+            .or_else(|| self.ps.find_syntax_by_extension(&query.file_name))
+            .or_else(|| self.ps.find_syntax_by_first_line(first_line))
+            .or_else(|| self.ps.find_syntax_by_extension(&query.extension))
+    }
+}
+
+/// Start loading the syntaxes and themes in the background, so they are ready by the time
+/// the first document is shown.
+pub fn preload() {
+    let spawned = std::thread::Builder::new()
+        .name("syntax loader".into())
+        .spawn(|| drop(Highlighter::get()));
+    if let Err(e) = spawned {
+        warn!("Can't start syntax loader thread: {e}");
+    }
+}
+
+/// Read the syntaxes and themes again, e.g. after adding some to the config dir.
+/// Loading happens in the background; open documents are highlighted again once it is done.
+pub fn reload(ctx: &egui::Context) {
+    let ctx = ctx.clone();
+    let spawned = std::thread::Builder::new()
+        .name("syntax loader".into())
+        .spawn(move || {
+            let highlighter = Arc::new(Highlighter::load());
+            *HIGHLIGHTER.write().unwrap_or_else(|e| e.into_inner()) = Some(highlighter);
+            // Only now, so nobody highlights the new revision with the old syntaxes
+            REVISION.fetch_add(1, Ordering::Relaxed);
+            ctx.request_repaint();
+        });
+    if let Err(e) = spawned {
+        warn!("Can't start syntax loader thread: {e}");
+    }
+}
+
+/// Sorted files in `dir` ending in `.{extension}`. Missing folders are fine.
+fn files_with_extension(dir: &Path, extension: &str) -> Vec<PathBuf> {
+    let mut files = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|e| e == extension))
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+/// Files without a telling extension
+const FILE_NAME_RULES: &[(&str, &str)] = &[
+    ("Dockerfile", "Dockerfile"),
+    ("Containerfile", "Dockerfile"),
+    ("Makefile", "Makefile"),
+    ("GNUmakefile", "Makefile"),
+    ("makefile", "Makefile"),
+    ("CMakeLists.txt", "CMake"),
+    ("Cargo.lock", "TOML"),
+    ("Gemfile", "Ruby"),
+    ("Rakefile", "Ruby"),
+    ("Vagrantfile", "Ruby"),
+    ("PKGBUILD", "Bourne Again Shell (bash)"),
+    (".bashrc", "Bourne Again Shell (bash)"),
+    (".bash_profile", "Bourne Again Shell (bash)"),
+    (".bash_aliases", "Bourne Again Shell (bash)"),
+    (".profile", "Bourne Again Shell (bash)"),
+    (".zshrc", "Bourne Again Shell (bash)"),
+    (".zprofile", "Bourne Again Shell (bash)"),
+    (".gitconfig", "Git Config"),
+    (".gitignore", "Git Ignore"),
+];
+
+/// How to pick the syntax of a document
+#[derive(Clone, Default, PartialEq)]
+pub struct SyntaxQuery {
+    /// Syntax name chosen by the user or mapped in the settings
+    pub name: Option<String>,
+    pub file_name: String,
+    /// Lowercase extension
+    pub extension: String,
+}
+
+impl SyntaxQuery {
+    pub fn for_path(path: &std::path::Path) -> Self {
         Self {
-            ps: syntect::parsing::SyntaxSet::load_defaults_newlines(),
-            ts: syntect::highlighting::ThemeSet::load_defaults(),
+            name: None,
+            file_name: path
+                .file_name()
+                .map(|f| f.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            extension: path
+                .extension()
+                .map(|e| e.to_string_lossy().to_lowercase())
+                .unwrap_or_default(),
         }
     }
 }
 
-impl Highlighter {
-    #[allow(clippy::unused_self, clippy::unnecessary_wraps)]
-    fn highlight(&self, theme: &CodeTheme, code: &str, lang: &str) -> LayoutJob {
-        self.highlight_impl(theme, code, lang).unwrap_or_else(|| {
-            // Fallback:
-            LayoutJob::simple(
-                code.into(),
-                egui::FontId::monospace(12.0),
-                if theme.dark_mode {
-                    egui::Color32::LIGHT_GRAY
-                } else {
-                    egui::Color32::DARK_GRAY
-                },
-                f32::INFINITY,
-            )
-        })
+/// Searchable list of all syntaxes. Returns `Some(None)` for "auto detect",
+/// `Some(Some(name))` when a syntax was picked.
+pub fn syntax_picker(ui: &mut egui::Ui, search: &mut String) -> Option<Option<String>> {
+    let mut picked = None;
+    ui.add(egui::TextEdit::singleline(search).hint_text("Search"))
+        .request_focus();
+    if ui.button("Auto detect").clicked() {
+        picked = Some(None);
+    }
+    ui.separator();
+    let names = syntax_names();
+    if names.is_empty() {
+        ui.horizontal(|ui| {
+            ui.spinner();
+            ui.label("Loading syntaxes…");
+        });
+    }
+    let search = search.to_lowercase();
+    egui::ScrollArea::vertical()
+        .max_height(300.0)
+        .show(ui, |ui| {
+            for name in names {
+                if name.to_lowercase().contains(&search)
+                    && ui.selectable_label(false, &name).clicked()
+                {
+                    picked = Some(Some(name));
+                }
+            }
+        });
+    picked
+}
+
+/// Names of all syntaxes that can be picked by hand, sorted. Empty while they are still loading.
+pub fn syntax_names() -> Vec<String> {
+    let Some(highlighter) = Highlighter::try_get() else {
+        return vec![];
+    };
+    let mut names = highlighter
+        .ps
+        .syntaxes()
+        .iter()
+        .filter(|s| !s.hidden)
+        .map(|s| s.name.clone())
+        .collect::<Vec<_>>();
+    names.sort_by_key(|n| n.to_lowercase());
+    names
+}
+
+fn text_format(style: &Style, font_id: &FontId) -> TextFormat {
+    use syntect::highlighting::FontStyle;
+
+    let fg = style.foreground;
+    let text_color = egui::Color32::from_rgb(fg.r, fg.g, fg.b);
+    let bold = style.font_style.contains(FontStyle::BOLD);
+    let italic = style.font_style.contains(FontStyle::ITALIC);
+    let underline = if style.font_style.contains(FontStyle::UNDERLINE) {
+        egui::Stroke::new(1.0, text_color)
+    } else {
+        egui::Stroke::NONE
+    };
+    let (font_id, italics) = styled_font(font_id, bold, italic);
+    TextFormat {
+        font_id,
+        color: text_color,
+        italics,
+        underline,
+        ..Default::default()
+    }
+}
+
+/// The bold and italic faces of the editor font. Other fonts, and editor fonts without such a
+/// face, have none, so for them bold is ignored and egui slants the regular face for italics.
+fn styled_font(font_id: &FontId, bold: bool, italic: bool) -> (FontId, bool) {
+    faced_font(font_id, bold, italic, FONT_FACES.load(Ordering::Relaxed))
+}
+
+/// [`styled_font`] with the installed faces as bits of [`FACE_FAMILIES`]
+fn faced_font(font_id: &FontId, bold: bool, italic: bool, faces: u8) -> (FontId, bool) {
+    let face = match (bold, italic) {
+        (true, false) => 0,
+        (false, true) => 1,
+        (true, true) => 2,
+        (false, false) => return (font_id.clone(), false),
+    };
+    if font_id.family != egui::FontFamily::Name(EDITOR_FONT.into()) || faces & 1 << face == 0 {
+        return (font_id.clone(), italic);
+    }
+    let family = FACE_FAMILIES[face];
+    (
+        FontId::new(font_id.size, egui::FontFamily::Name(family.into())),
+        false,
+    )
+}
+
+// ----------------------------------------------------------------------------
+
+/// Every this many lines we keep the parser state, so we can restart highlighting there.
+const CHECKPOINT_INTERVAL: usize = 64;
+
+/// Lines highlighted past the visible ones, so scrolling a bit doesn't show plain text
+const LOOKAHEAD: usize = 100;
+
+type State = (ParseState, HighlightState);
+
+/// Styled byte ranges of one line, relative to the line start
+type LineStyles = Arc<Vec<(Range<usize>, Style)>>;
+
+/// Lines `first..=old_last` of the old text were replaced by `first..=new_last` in the new one.
+struct LineDiff {
+    first: usize,
+    old_last: usize,
+    new_last: usize,
+}
+
+impl LineDiff {
+    fn new(edit: &Edit) -> Self {
+        let first = edit.at.line;
+        Self {
+            first,
+            old_last: first + count_newlines(edit.removed.as_bytes()),
+            new_last: first + count_newlines(edit.inserted.as_bytes()),
+        }
+    }
+
+    /// Where a line below the edit ended up
+    fn shift(&self, line: usize) -> usize {
+        (line + self.new_last).saturating_sub(self.old_last)
+    }
+
+    /// Apply the edit to a per-line vector, filling in new lines with `T::default()`.
+    fn splice<T: Default>(&self, v: &mut Vec<T>, new_len: usize) {
+        if v.len() > self.old_last {
+            v.splice(
+                self.first..=self.old_last,
+                std::iter::repeat_with(T::default).take(self.new_last - self.first + 1),
+            );
+        } else {
+            v.clear();
+        }
+        v.resize_with(new_len, T::default);
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+#[derive(Default)]
+struct Line {
+    /// State at the start of this line, kept for every [`CHECKPOINT_INTERVAL`]th line
+    state: Option<Box<State>>,
+    /// `None` if never highlighted
+    styles: Option<LineStyles>,
+}
+
+/// Incremental highlighting state of one document, owned by its worker thread.
+///
+/// After an edit only the changed lines are highlighted again, continuing until the parser
+/// state matches the one we had before at that point. Lines far below the visible area are
+/// only highlighted once they are scrolled into view.
+#[derive(Default)]
+struct Engine {
+    /// Theme, syntax, [`REVISION`] and longest highlighted line the cached lines were
+    /// highlighted with
+    key: Option<(String, String, u64, usize)>,
+    lines: LineBuffer,
+    line_state: Vec<Line>,
+    /// Lines before this one are highlighted with up-to-date state.
+    valid: usize,
+    /// Lines highlighted before the last edit, consistent among themselves.
+    /// If we reach a checkpoint in here with an unchanged state we can stop.
+    stale: Range<usize>,
+}
+
+impl Engine {
+    fn set_key(&mut self, key: (String, String, u64, usize)) {
+        if self.key.as_ref() != Some(&key) {
+            self.key = Some(key);
+            self.reset();
+        }
     }
 
-    fn highlight_impl(&self, theme: &CodeTheme, text: &str, language: &str) -> Option<LayoutJob> {
-        use syntect::easy::HighlightLines;
-        use syntect::highlighting::FontStyle;
-        use syntect::util::LinesWithEndings;
+    /// Forget all highlighting.
+    fn reset(&mut self) {
+        self.line_state = std::iter::repeat_with(Line::default)
+            .take(self.lines.line_count())
+            .collect();
+        self.valid = 0;
+        self.stale = 0..0;
+    }
+
+    /// Replace the text, if it was `replaced`, then apply `edits` and invalidate the lines
+    /// they changed.
+    fn update_text(&mut self, replaced: Option<LineBuffer>, edits: &[Edit]) {
+        if let Some(lines) = replaced {
+            self.lines = lines;
+            self.reset();
+        }
+        for edit in edits {
+            edit.apply(&mut self.lines);
+            self.invalidate(LineDiff::new(edit));
+        }
+    }
+
+    fn invalidate(&mut self, diff: LineDiff) {
+        diff.splice(&mut self.line_state, self.lines.line_count());
+
+        // The part of the stale lines below the edit stays consistent
+        let stale_below = if self.stale.end > diff.old_last + 1 {
+            diff.shift(self.stale.start.max(diff.old_last + 1))..diff.shift(self.stale.end)
+        } else {
+            0..0
+        };
+        if diff.first < self.valid {
+            self.stale = if self.valid > diff.old_last + 1 {
+                diff.new_last + 1..diff.shift(self.valid)
+            } else {
+                stale_below
+            };
+            self.valid = diff.first;
+        } else if self.stale.end > diff.old_last + 1 {
+            self.stale = stale_below;
+        } else {
+            self.stale.end = self.stale.end.min(diff.first);
+        }
+        if self.stale.is_empty() {
+            self.stale = 0..0;
+        }
+    }
+
+    /// Highlight from the first outdated line until `target`, or until the state converges
+    /// with what we had before the edit. Returns false if `cancelled` stopped us early.
+    fn highlight(
+        &mut self,
+        highlighter: &Highlighter,
+        theme: &syntect::highlighting::Theme,
+        syntax: &SyntaxReference,
+        target: usize,
+        max_line_len: usize,
+        cancelled: impl Fn() -> bool,
+    ) -> bool {
+        let target = target.min(self.lines.line_count());
+        let syntect_highlighter = syntect::highlighting::Highlighter::new(theme);
+        let (mut line, mut state) = self.restart_point(syntax, &syntect_highlighter);
+
+        while self.valid < target {
+            if line >= self.valid {
+                if self.stale.contains(&line)
+                    && self.line_state[line].state.as_deref() == Some(&state)
+                {
+                    // Same state as before the edit, so everything below is still correct
+                    self.valid = self.stale.end;
+                    self.stale = 0..0;
+                    (line, state) = self.restart_point(syntax, &syntect_highlighter);
+                    continue;
+                }
+                if cancelled() {
+                    return false;
+                }
+            }
 
-        let syntax = self
-            .ps
-            .find_syntax_by_first_line(text)
-            // .find_syntax_by_name(language)
-            .or_else(|| self.ps.find_syntax_by_extension(language))?;
+            self.line_state[line].state =
+                (line % CHECKPOINT_INTERVAL == 0).then(|| Box::new(state.clone()));
 
-        let theme = theme.syntect_theme.syntect_key_name();
-        let mut h = HighlightLines::new(syntax, &self.ts.themes[theme]);
+            let text = self.lines.line(line);
+            if text.len() > max_line_len {
+                // Would take too long. The following lines go on as if it wasn't there.
+                if line >= self.valid {
+                    self.line_state[line].styles = None;
+                    self.valid = line + 1;
+                    self.stale.start = self.stale.start.max(self.valid);
+                }
+                line += 1;
+                continue;
+            }
+            // Syntaxes expect the line break, e.g. to end a line comment
+            let text = if line + 1 < self.lines.line_count() {
+                format!("{text}\n")
+            } else {
+                text.into()
+            };
+            let (parse_state, highlight_state) = &mut state;
+            let Ok(ops) = parse_state.parse_line(&text, &highlighter.ps) else {
+                log::warn!("Highlighting failed in line {}", line + 1);
+                self.valid = self.lines.line_count();
+                break;
+            };
+            let styles =
+                RangedHighlightIterator::new(highlight_state, &ops, &text, &syntect_highlighter)
+                    .map(|(style, _, range)| (range, style));
+            if line >= self.valid {
+                self.line_state[line].styles = Some(Arc::new(styles.collect()));
+                self.valid = line + 1;
+                self.stale.start = self.stale.start.max(self.valid);
+            } else {
+                // Only replaying from the checkpoint up to the first outdated line
+                styles.for_each(drop);
+            }
+            line += 1;
+        }
+        true
+    }
+
+    /// The last checkpoint before the first outdated line, or the start of the document.
+    fn restart_point(
+        &self,
+        syntax: &SyntaxReference,
+        highlighter: &syntect::highlighting::Highlighter<'_>,
+    ) -> (usize, State) {
+        self.line_state[..self.valid]
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(i, l)| l.state.as_ref().map(|s| (i, (**s).clone())))
+            .unwrap_or_else(|| {
+                (
+                    0,
+                    (
+                        ParseState::new(syntax),
+                        HighlightState::new(highlighter, ScopeStack::new()),
+                    ),
+                )
+            })
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+struct Job {
+    generation: u64,
+    /// The whole text, if it was replaced since the last job
+    replaced: Option<LineBuffer>,
+    /// Changes since the last job, after the replacement
+    edits: Vec<Edit>,
+    theme: String,
+    query: SyntaxQuery,
+    /// Value of [`REVISION`] the job was made for
+    revision: u64,
+    /// Highlight at least this many lines
+    target: usize,
+    /// Lines longer than this many bytes stay plain
+    max_line_len: usize,
+    ctx: egui::Context,
+}
+
+struct Done {
+    generation: u64,
+    /// Name of the syntax used, `None` for plain text
+    syntax: Option<String>,
+    /// Number of lines that are highlighted
+    valid: usize,
+    styles: Vec<Option<LineStyles>>,
+}
+
+/// Thread highlighting one document. Stops when its [`HighlightCache`] is dropped.
+struct Worker {
+    jobs: Sender<Job>,
+    done: Receiver<Done>,
+    /// Generation of the newest job. Older jobs stop as soon as they notice.
+    latest: Arc<AtomicU64>,
+}
+
+impl Worker {
+    fn spawn() -> Self {
+        let (jobs, job_rx) = channel::<Job>();
+        let (done_tx, done) = channel();
+        let latest = Arc::new(AtomicU64::new(0));
+        let worker_latest = latest.clone();
+
+        let spawned = std::thread::Builder::new()
+            .name("highlighter".into())
+            .spawn(move || {
+                let latest = worker_latest;
+                let mut engine = Engine::default();
+                while let Ok(mut job) = job_rx.recv() {
+                    engine.update_text(job.replaced.take(), &job.edits);
+                    // Skip everything but the newest job, but not the changes of the text
+                    while let Ok(mut newer) = job_rx.try_recv() {
+                        engine.update_text(newer.replaced.take(), &newer.edits);
+                        job = newer;
+                    }
+                    let cancelled = || latest.load(Ordering::Relaxed) != job.generation;
+                    if cancelled() {
+                        continue;
+                    }
+
+                    let highlighter = Highlighter::get();
+                    let done = match highlighter.find_syntax(engine.lines.line(0), &job.query) {
+                        Some(syntax) => {
+                            engine.set_key((
+                                job.theme.clone(),
+                                syntax.name.clone(),
+                                job.revision,
+                                job.max_line_len,
+                            ));
+                            let preview = PREVIEW
+                                .read()
+                                .ok()
+                                .and_then(|p| p.clone())
+                                .filter(|(name, _)| *name == job.theme);
+                            // The theme may be gone after a reload
+                            let Some(theme) = preview
+                                .as_ref()
+                                .map(|(_, theme)| &**theme)
+                                .or_else(|| highlighter.ts.themes.get(&job.theme))
+                                .or_else(|| {
+                                    highlighter.ts.themes.get(&CodeTheme::default().theme_name)
+                                })
+                            else {
+                                continue;
+                            };
+                            if !engine.highlight(
+                                &highlighter,
+                                theme,
+                                syntax,
+                                job.target,
+                                job.max_line_len,
+                                cancelled,
+                            ) {
+                                continue;
+                            }
+                            Done {
+                                generation: job.generation,
+                                syntax: Some(syntax.name.clone()),
+                                valid: engine.valid,
+                                styles: engine
+                                    .line_state
+                                    .iter()
+                                    .map(|l| l.styles.clone())
+                                    .collect(),
+                            }
+                        }
+                        // Plain text
+                        None => Done {
+                            generation: job.generation,
+                            syntax: None,
+                            valid: engine.lines.line_count(),
+                            styles: vec![None; engine.lines.line_count()],
+                        },
+                    };
+                    if done_tx.send(done).is_err() {
+                        return;
+                    }
+                    job.ctx.request_repaint();
+                }
+            });
+        if let Err(e) = spawned {
+            log::warn!("Can't start highlighter thread: {e}");
+        }
+
+        Self { jobs, done, latest }
+    }
+}
 
-        use egui::text::{LayoutSection, TextFormat};
+/// How the columns of delimited text like CSV are shown.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, serde::Deserialize, serde::Serialize)]
+pub enum ColumnMode {
+    /// Highlighted like any other text
+    #[default]
+    Off,
+    /// Every column in its own color
+    Rainbow,
+    /// In their own colors and padded so they line up, without changing the text
+    Aligned,
+}
+
+impl ColumnMode {
+    /// Radio buttons for the modes.
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.radio_value(self, Self::Off, "Off");
+        ui.radio_value(self, Self::Rainbow, "Rainbow");
+        ui.radio_value(self, Self::Aligned, "Rainbow, aligned");
+    }
+}
+
+/// Delimited text colored by column, see [`ColumnMode`].
+#[derive(Clone, Copy, PartialEq)]
+pub struct Columns {
+    pub delimiter: char,
+    /// Pad the columns so they line up
+    pub align: bool,
+}
+
+/// Colors of the columns in dark themes, starting over after the last one
+const RAINBOW_DARK: [egui::Color32; 6] = [
+    egui::Color32::from_rgb(230, 180, 80),
+    egui::Color32::from_rgb(120, 190, 240),
+    egui::Color32::from_rgb(150, 210, 120),
+    egui::Color32::from_rgb(230, 130, 150),
+    egui::Color32::from_rgb(190, 150, 230),
+    egui::Color32::from_rgb(100, 200, 190),
+];
+
+/// Colors of the columns in light themes
+const RAINBOW_LIGHT: [egui::Color32; 6] = [
+    egui::Color32::from_rgb(170, 100, 0),
+    egui::Color32::from_rgb(20, 100, 180),
+    egui::Color32::from_rgb(40, 130, 40),
+    egui::Color32::from_rgb(180, 40, 80),
+    egui::Color32::from_rgb(120, 60, 180),
+    egui::Color32::from_rgb(0, 130, 120),
+];
+
+/// Aligned columns are padded to at most this many chars, so one long field doesn't push the
+/// others off screen
+const MAX_ALIGNED_WIDTH: usize = 40;
+
+/// Chars in the widest field of every column of `text`, up to [`MAX_ALIGNED_WIDTH`]. Empty if
+/// the columns aren't aligned.
+fn column_widths(columns: Option<Columns>, lines: &LineBuffer) -> Vec<usize> {
+    let Some(Columns {
+        delimiter,
+        align: true,
+    }) = columns
+    else {
+        return vec![];
+    };
+    let mut widths = vec![];
+    for line in lines.lines() {
+        for (column, field) in delimited::fields(line, delimiter).into_iter().enumerate() {
+            let width = line[field].chars().count().min(MAX_ALIGNED_WIDTH);
+            if column >= widths.len() {
+                widths.push(0);
+            }
+            widths[column] = widths[column].max(width);
+        }
+    }
+    widths
+}
+
+/// The text shown in an editor, for [`HighlightCache::update`].
+pub struct EditedText<'a> {
+    pub lines: &'a LineBuffer,
+    /// Changes whenever the text does
+    pub version: u64,
+    /// The version `edits` were made to. They lead from it to `version`.
+    pub edited_from: u64,
+    pub edits: &'a [Edit],
+}
+
+/// Highlighting of one document as shown in the UI.
+///
+/// The actual work happens in a background thread. Until it is done, changed lines are shown
+/// as plain text and unchanged ones keep their previous colors.
+#[derive(Default)]
+pub struct HighlightCache {
+    line_count: usize,
+    styles: Vec<Option<LineStyles>>,
+    /// Bumped whenever the text, theme or language change
+    generation: u64,
+    /// Theme, syntax query, [`REVISION`] and longest highlighted line of the current generation
+    key: (String, SyntaxQuery, u64, usize),
+    /// Syntax the worker picked, once it reported back
+    syntax: Option<Option<String>>,
+    /// Generation and target of the last job sent
+    requested: Option<(u64, usize)>,
+    /// Lines highlighted for the current generation
+    highlighted: usize,
+    /// Lines shown on screen, as of the last frame
+    visible: Range<usize>,
+    /// Version of the text the styles are for
+    version: Option<u64>,
+    /// Whole text the worker has yet to be sent
+    replaced: Option<LineBuffer>,
+    /// Edits the worker has yet to be sent, after `replaced`
+    edits: Vec<Edit>,
+    worker: Option<Worker>,
+    /// Colors by column instead of by syntax, if set
+    columns: Option<Columns>,
+    /// Chars every column is padded to, if they are aligned
+    column_widths: Vec<usize>,
+}
+
+impl HighlightCache {
+    /// Tell the cache which lines are on screen, so it highlights those first.
+    pub fn set_visible_lines(&mut self, visible: Range<usize>) {
+        self.visible = visible;
+    }
+
+    /// Color delimited text by column from now on, or by syntax again for `None`.
+    pub fn set_columns(&mut self, columns: Option<Columns>, lines: &LineBuffer) {
+        if self.columns != columns {
+            self.columns = columns;
+            self.column_widths = column_widths(columns, lines);
+        }
+    }
+
+    /// Chars in the widest line once the columns are aligned, 0 if they aren't.
+    pub fn aligned_width(&self) -> usize {
+        let widths = &self.column_widths;
+        widths.iter().sum::<usize>() + widths.len().saturating_sub(1)
+    }
+
+    /// Name of the syntax in use: `None` while detecting, `Some(None)` for plain text.
+    pub fn syntax_name(&self) -> Option<Option<&str>> {
+        self.syntax.as_ref().map(|s| s.as_deref())
+    }
+
+    /// Catch up with the worker and send it a new job if the text, the theme or the language
+    /// changed. Lines longer than `max_line_len` bytes are not highlighted.
+    pub fn update(
+        &mut self,
+        ctx: &egui::Context,
+        theme: &CodeTheme,
+        query: &SyntaxQuery,
+        max_line_len: usize,
+        text: EditedText<'_>,
+    ) {
+        let worker = self.worker.get_or_insert_with(Worker::spawn);
+        for done in worker.done.try_iter() {
+            if done.generation == self.generation {
+                self.syntax = Some(done.syntax);
+                self.styles = done.styles;
+                self.highlighted = done.valid;
+            }
+        }
+
+        let mut changed = false;
+        if self.version != Some(text.version) {
+            if self.version == Some(text.edited_from) {
+                for edit in text.edits {
+                    let diff = LineDiff::new(edit);
+                    self.line_count = self.line_count + diff.new_last - diff.old_last;
+                    diff.splice(&mut self.styles, self.line_count);
+                }
+                self.edits.extend_from_slice(text.edits);
+            } else {
+                // Another text, or we missed some edits
+                self.styles.clear();
+                self.replaced = Some(text.lines.clone());
+                self.edits.clear();
+            }
+            self.line_count = text.lines.line_count();
+            self.styles.resize_with(self.line_count, Default::default);
+            self.version = Some(text.version);
+            self.column_widths = column_widths(self.columns, text.lines);
+            changed = true;
+        }
+        let revision = REVISION.load(Ordering::Relaxed);
+        let key = (
+            theme.theme_name.clone(),
+            query.clone(),
+            revision,
+            max_line_len,
+        );
+        if self.key != key {
+            self.key = key;
+            changed = true;
+        }
+        if changed {
+            self.generation += 1;
+            self.highlighted = 0;
+            worker.latest.store(self.generation, Ordering::Relaxed);
+        }
+
+        let target = (self.visible.end + LOOKAHEAD).min(self.line_count);
+        let outdated = self.replaced.is_some() || !self.edits.is_empty();
+        if outdated
+            || self.highlighted < target && self.requested != Some((self.generation, target))
+        {
+            let job = Job {
+                generation: self.generation,
+                replaced: self.replaced.take(),
+                edits: std::mem::take(&mut self.edits),
+                theme: self.key.0.clone(),
+                query: self.key.1.clone(),
+                revision: self.key.2,
+                target,
+                max_line_len,
+                ctx: ctx.clone(),
+            };
+            if worker.jobs.send(job).is_ok() {
+                self.requested = Some((self.generation, target));
+            }
+        }
+    }
 
+    /// Layout of the `bytes` of line `line`, whose text without line break is `text`.
+    /// Plain until it has been highlighted. Aligned columns are padded by multiples of
+    /// `char_width`.
+    pub fn line_job(
+        &self,
+        line: usize,
+        text: &str,
+        bytes: Range<usize>,
+        theme: &CodeTheme,
+        font_id: &FontId,
+        char_width: f32,
+    ) -> LayoutJob {
         let mut job = LayoutJob {
-            text: text.into(),
+            text: text[bytes.clone()].into(),
             ..Default::default()
         };
-
-        for line in LinesWithEndings::from(text) {
-            for (style, range) in h.highlight_line(line, &self.ps).ok()? {
-                let fg = style.foreground;
-                let text_color = egui::Color32::from_rgb(fg.r, fg.g, fg.b);
-                let italics = style.font_style.contains(FontStyle::ITALIC);
-                let underline = style.font_style.contains(FontStyle::ITALIC);
-                let underline = if underline {
-                    egui::Stroke::new(1.0, text_color)
-                } else {
-                    egui::Stroke::NONE
-                };
+        if let Some(columns) = self.columns {
+            job.sections = self.column_sections(columns, text, bytes, theme, font_id, char_width);
+        } else if let Some(Some(styles)) = self.styles.get(line) {
+            for (r, style) in styles.iter() {
+                let range =
+                    r.start.clamp(bytes.start, bytes.end)..r.end.clamp(bytes.start, bytes.end);
+                if range.is_empty()
+                    || !text.is_char_boundary(range.start)
+                    || !text.is_char_boundary(range.end)
+                {
+                    continue;
+                }
                 job.sections.push(LayoutSection {
                     leading_space: 0.0,
-                    byte_range: as_byte_range(text, range),
-                    format: TextFormat {
-                        font_id: egui::FontId::monospace(12.0),
-                        color: text_color,
-                        italics,
-                        underline,
-                        ..Default::default()
-                    },
+                    byte_range: range.start - bytes.start..range.end - bytes.start,
+                    format: text_format(style, font_id),
                 });
             }
         }
+        // Styles of an outdated line may not cover it
+        if job.sections.last().map_or(0, |s| s.byte_range.end) < job.text.len() {
+            job.sections.clear();
+            job.sections.push(LayoutSection {
+                leading_space: 0.0,
+                byte_range: 0..job.text.len(),
+                format: TextFormat::simple(font_id.clone(), plain_color(theme)),
+            });
+        }
+        job
+    }
 
-        Some(job)
+    /// Sections coloring every field of `text` by its column, padding the delimiters after
+    /// short fields if the columns are aligned.
+    fn column_sections(
+        &self,
+        columns: Columns,
+        text: &str,
+        bytes: Range<usize>,
+        theme: &CodeTheme,
+        font_id: &FontId,
+        char_width: f32,
+    ) -> Vec<LayoutSection> {
+        let rainbow = if theme.dark_mode {
+            &RAINBOW_DARK
+        } else {
+            &RAINBOW_LIGHT
+        };
+        let plain = plain_color(theme);
+        let mut sections = vec![];
+        let mut push = |range: Range<usize>, color, leading_space| {
+            let range =
+                range.start.clamp(bytes.start, bytes.end)..range.end.clamp(bytes.start, bytes.end);
+            if !range.is_empty() {
+                sections.push(LayoutSection {
+                    leading_space,
+                    byte_range: range.start - bytes.start..range.end - bytes.start,
+                    format: TextFormat::simple(font_id.clone(), color),
+                });
+            }
+        };
+        let mut padding = 0.0;
+        for (column, field) in delimited::fields(text, columns.delimiter)
+            .into_iter()
+            .enumerate()
+        {
+            if column > 0 {
+                let delimiter = field.start - columns.delimiter.len_utf8()..field.start;
+                push(delimiter, plain, padding);
+            }
+            let chars = text[field.clone()].chars().count();
+            padding = self
+                .column_widths
+                .get(column)
+                .map_or(0.0, |w| w.saturating_sub(chars) as f32 * char_width);
+            push(field, rainbow[column % rainbow.len()], 0.0);
+        }
+        sections
     }
 }
 
-fn as_byte_range(whole: &str, range: &str) -> std::ops::Range<usize> {
-    let whole_start = whole.as_ptr() as usize;
-    let range_start = range.as_ptr() as usize;
-    assert!(whole_start <= range_start);
-    assert!(range_start + range.len() <= whole_start + whole.len());
-    let offset = range_start - whole_start;
-    offset..(offset + range.len())
+fn plain_color(theme: &CodeTheme) -> egui::Color32 {
+    with_theme(&theme.theme_name, |t| t.settings.foreground)
+        .flatten()
+        .map(to_color32)
+        .unwrap_or(if theme.dark_mode {
+            egui::Color32::LIGHT_GRAY
+        } else {
+            egui::Color32::DARK_GRAY
+        })
+}
+
+fn count_newlines(bytes: &[u8]) -> usize {
+    bytes.iter().filter(|b| **b == b'\n').count()
 }
 
-// ----------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::line_buffer::Position;
+    use std::str::FromStr;
+    use syntect::highlighting::{
+        Color, FontStyle, ScopeSelectors, StyleModifier, ThemeItem, ThemeSettings,
+    };
+
+    const SNIPPET: &str = "// note\nfn main() {\n    return \"text\" + 42;\n}\n";
+
+    /// A theme with one font style per scope
+    fn styled_theme() -> Theme {
+        let item = |scope: &str, font_style| ThemeItem {
+            scope: ScopeSelectors::from_str(scope).unwrap(),
+            style: StyleModifier {
+                foreground: Some(Color {
+                    r: 200,
+                    g: 100,
+                    b: 0,
+                    a: 255,
+                }),
+                background: None,
+                font_style: Some(font_style),
+            },
+        };
+        Theme {
+            settings: ThemeSettings {
+                foreground: Some(Color::WHITE),
+                background: Some(Color::BLACK),
+                ..Default::default()
+            },
+            scopes: vec![
+                item("comment", FontStyle::ITALIC),
+                item("keyword", FontStyle::BOLD),
+                item("string", FontStyle::UNDERLINE),
+                item(
+                    "constant.numeric",
+                    FontStyle::BOLD | FontStyle::ITALIC | FontStyle::UNDERLINE,
+                ),
+            ],
+            ..Default::default()
+        }
+    }
+
+    /// The editor font, with all faces installed like a user font with siblings would have them
+    fn editor_font() -> FontId {
+        set_font_faces(&FACE_FAMILIES);
+        FontId::new(14.0, egui::FontFamily::Name(EDITOR_FONT.into()))
+    }
+
+    /// Format of the section containing all of `needle`
+    fn format_at<'a>(job: &'a LayoutJob, needle: &str) -> &'a TextFormat {
+        let start = job.text.find(needle).unwrap();
+        let section = job
+            .sections
+            .iter()
+            .find(|s| s.byte_range.contains(&start))
+            .unwrap();
+        assert!(
+            section.byte_range.end >= start + needle.len(),
+            "{needle:?} is split at {:?}",
+            &job.text[section.byte_range.clone()]
+        );
+        &section.format
+    }
+
+    fn family(format: &TextFormat) -> String {
+        match &format.font_id.family {
+            egui::FontFamily::Name(name) => name.to_string(),
+            family => family.to_string(),
+        }
+    }
+
+    #[test]
+    fn font_styles() {
+        Highlighter::get();
+        let job = highlight_snippet(&styled_theme(), "rs", SNIPPET, &editor_font());
+        assert_eq!(job.text, SNIPPET);
+
+        let comment = format_at(&job, "note");
+        assert_eq!(family(comment), EDITOR_FONT_ITALIC);
+        assert!(!comment.italics, "the italic face is already slanted");
+        assert_eq!(comment.underline, egui::Stroke::NONE);
+
+        let keyword = format_at(&job, "return");
+        assert_eq!(family(keyword), EDITOR_FONT_BOLD);
+        assert_eq!(keyword.underline, egui::Stroke::NONE);
+
+        let string = format_at(&job, "text");
+        assert_eq!(family(string), EDITOR_FONT);
+        assert_eq!(string.underline.color, string.color);
+        assert!(string.underline.width > 0.0);
+
+        let number = format_at(&job, "42");
+        assert_eq!(family(number), EDITOR_FONT_BOLD_ITALIC);
+        assert!(number.underline.width > 0.0);
+
+        let plain = format_at(&job, "main");
+        assert_eq!(family(plain), EDITOR_FONT);
+        assert!(!plain.italics);
+        assert_eq!(plain.underline, egui::Stroke::NONE);
+        assert_eq!(plain.color, egui::Color32::WHITE);
+        assert_eq!(plain.font_id.size, 14.0);
+    }
+
+    #[test]
+    fn aligned_columns() {
+        let text = "id,name\n1,\"Smith, J\"\n";
+        let lines = LineBuffer::new(text);
+        assert_eq!(column_widths(None, &lines), Vec::<usize>::new());
+
+        let mut cache = HighlightCache::default();
+        let columns = Columns {
+            delimiter: ',',
+            align: true,
+        };
+        cache.set_columns(Some(columns), &lines);
+        assert_eq!(cache.column_widths, [2, 10]);
+        assert_eq!(cache.aligned_width(), 13);
+
+        let line = "1,\"Smith, J\"";
+        let job = cache.line_job(
+            1,
+            line,
+            0..line.len(),
+            &CodeTheme::dark(),
+            &editor_font(),
+            8.0,
+        );
+        assert_eq!(job.text, line, "the text is not changed");
+        let sections = job
+            .sections
+            .iter()
+            .map(|s| (&line[s.byte_range.clone()], s.leading_space, s.format.color))
+            .collect::<Vec<_>>();
+        assert_eq!(sections[0], ("1", 0.0, RAINBOW_DARK[0]));
+        assert_eq!(sections[1].0, ",");
+        assert_eq!(sections[1].1, 8.0, "padded to the width of \"id\"");
+        assert_eq!(sections[2], ("\"Smith, J\"", 0.0, RAINBOW_DARK[1]));
+    }
+
+    #[test]
+    fn font_styles_without_faces() {
+        Highlighter::get();
+        let font_id = FontId::monospace(14.0);
+        let job = highlight_snippet(&styled_theme(), "rs", SNIPPET, &font_id);
+
+        let comment = format_at(&job, "note");
+        assert_eq!(comment.font_id, font_id);
+        assert!(comment.italics);
+
+        let keyword = format_at(&job, "return");
+        assert_eq!(keyword.font_id, font_id);
+        assert!(!keyword.italics);
+
+        // The bundled editor font has only a regular face
+        let editor = FontId::new(14.0, egui::FontFamily::Name(EDITOR_FONT.into()));
+        assert_eq!(faced_font(&editor, true, false, 0), (editor.clone(), false));
+        assert_eq!(faced_font(&editor, true, true, 0), (editor.clone(), true));
+        let (bold_italic, slanted) = faced_font(&editor, true, true, 0b100);
+        assert_eq!(
+            bold_italic.family,
+            egui::FontFamily::Name(EDITOR_FONT_BOLD_ITALIC.into())
+        );
+        assert!(!slanted);
+    }
+
+    /// An engine with `text` fully highlighted as Rust with [`styled_theme`].
+    struct EngineTest {
+        engine: Engine,
+        /// The text as the editor has it
+        buffer: LineBuffer,
+        highlighter: Arc<Highlighter>,
+        theme: Theme,
+    }
+
+    impl EngineTest {
+        fn new(text: &str) -> Self {
+            let mut test = Self {
+                engine: Engine::default(),
+                buffer: LineBuffer::new(text),
+                highlighter: Highlighter::get(),
+                theme: styled_theme(),
+            };
+            test.engine.update_text(Some(test.buffer.clone()), &[]);
+            test.engine
+                .set_key(("styled".into(), "Rust".into(), 0, 1000));
+            test.highlight();
+            test
+        }
+
+        /// Highlight everything that is outdated. Returns how many lines that took.
+        fn highlight(&mut self) -> usize {
+            let syntax = self.highlighter.ps.find_syntax_by_extension("rs").unwrap();
+            let highlighted = std::cell::Cell::new(0);
+            let target = self.engine.lines.line_count();
+            let done =
+                self.engine
+                    .highlight(&self.highlighter, &self.theme, syntax, target, 1000, || {
+                        highlighted.set(highlighted.get() + 1);
+                        false
+                    });
+            assert!(done);
+            assert_eq!(self.engine.valid, target);
+            highlighted.get()
+        }
+
+        /// Replace `range` like the editor would.
+        fn edit(&mut self, range: Range<Position>, text: &str) {
+            let edit = self.buffer.replace(range, text);
+            self.engine.update_text(None, &[edit]);
+            assert_eq!(
+                self.engine.lines.text("\n"),
+                self.buffer.text("\n"),
+                "the engine has the editor's text"
+            );
+        }
+
+        fn styles(&self) -> Vec<Option<LineStyles>> {
+            let lines = self.engine.line_state.iter();
+            lines.map(|l| l.styles.clone()).collect()
+        }
+    }
+
+    fn numbered_lines(count: usize) -> Vec<String> {
+        (0..count).map(|i| format!("let x{i} = {i};")).collect()
+    }
+
+    #[test]
+    fn edits_converge() {
+        let lines = numbered_lines(300);
+        let mut test = EngineTest::new(&lines.join("\n"));
+        let before = test.styles();
+
+        let line = Position::new(150, 0)..Position::new(150, lines[150].len());
+        test.edit(line, "let changed = \"text\";");
+        assert_eq!(test.engine.valid, 150);
+        let highlighted = test.highlight();
+        // Stops at the first checkpoint after the edit where the state is unchanged
+        assert!(
+            highlighted <= CHECKPOINT_INTERVAL,
+            "highlighted {highlighted} lines"
+        );
+        let after = test.styles();
+        assert_ne!(after[150], before[150]);
+        assert_eq!(after[151..], before[151..]);
+
+        // Lines inserted above shift the stale lines
+        let start = Position::new(10, 0);
+        test.edit(start..start, "let inserted = 2;\nlet inserted = 1;\n");
+        let highlighted = test.highlight();
+        assert!(
+            highlighted <= CHECKPOINT_INTERVAL,
+            "highlighted {highlighted} lines"
+        );
+        assert_eq!(test.styles()[153..], after[151..]);
+    }
+
+    #[test]
+    fn comment_opener_invalidates_following_lines() {
+        let mut test = EngineTest::new(&numbered_lines(300).join("\n"));
+        let before = test.styles();
+
+        let start = Position::new(100, 0);
+        test.edit(start..start, "/* ");
+        assert_eq!(test.highlight(), 200);
+        let commented = test.styles();
+        for (line, styles) in commented.iter().enumerate().skip(101) {
+            let styles = styles.as_ref().unwrap();
+            assert!(
+                styles
+                    .iter()
+                    .all(|(_, s)| s.font_style == FontStyle::ITALIC),
+                "line {line} is not a comment"
+            );
+        }
+
+        test.edit(start..Position::new(100, 3), "");
+        assert_eq!(test.highlight(), 200);
+        assert_eq!(test.styles(), before);
+    }
+
+    #[test]
+    fn file_name_rules_resolve() {
+        let highlighter = Highlighter::get();
+        for (file_name, syntax) in FILE_NAME_RULES {
+            assert!(
+                highlighter.ps.find_syntax_by_name(syntax).is_some(),
+                "{file_name} maps to {syntax}, which isn't loaded"
+            );
+        }
+
+        let query = SyntaxQuery::for_path(Path::new("docker/Dockerfile"));
+        let text =
+            "FROM rust AS build\nRUN apt-get update \\\n    && echo \"$HOME\"\nCOPY . /src\n";
+        let syntax = highlighter.find_syntax(text, &query).unwrap();
+        assert_eq!(syntax.name, "Dockerfile");
+        let job = highlight_snippet(&styled_theme(), "Dockerfile", text, &editor_font());
+        // Keywords after a shell command that spans lines are Dockerfile's again
+        assert_eq!(family(format_at(&job, "COPY")), EDITOR_FONT_BOLD);
+
+        // Text in each language, with a comment
+        let samples = [
+            (
+                "cmake",
+                "# build\nif(NOT WIN32)\n  set(FLAGS \"${FLAGS} -O2\")\nendif()\n",
+            ),
+            (
+                "gitconfig",
+                "[remote \"origin\"]\n\turl = git@host:repo ; build\n",
+            ),
+            ("gitignore", "# build output\n/target/**\n!keep.rs\n"),
+        ];
+        for (extension, text) in samples {
+            let job = highlight_snippet(&styled_theme(), extension, text, &editor_font());
+            assert!(job.sections.len() > 1, "{extension} isn't highlighted");
+            assert_eq!(family(format_at(&job, "build")), EDITOR_FONT_ITALIC);
+        }
+    }
+}
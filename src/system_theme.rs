@@ -0,0 +1,243 @@
+//! The desktop's preferred color scheme, watched in the background.
+
+use log::{info, warn};
+use std::{
+    env, fs,
+    path::Path,
+    sync::atomic::{AtomicBool, AtomicU8, Ordering},
+    thread,
+    time::Duration,
+};
+
+/// Names a file containing `dark` or `light`, read instead of asking the desktop.
+/// Meant for tests and for desktops we can't query. It is polled while [`set_active`].
+pub const SCHEME_FILE_VAR: &str = "MICRON_COLOR_SCHEME_FILE";
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+const UNKNOWN: u8 = 0;
+const DARK: u8 = 1;
+const LIGHT: u8 = 2;
+
+static SCHEME: AtomicU8 = AtomicU8::new(UNKNOWN);
+/// Whether the app follows the system theme, so polling is worth it
+static ACTIVE: AtomicBool = AtomicBool::new(true);
+
+/// Whether the desktop prefers dark mode, as last seen by [`watch`]. `None` if it doesn't say.
+pub fn prefers_dark() -> Option<bool> {
+    match SCHEME.load(Ordering::Relaxed) {
+        DARK => Some(true),
+        LIGHT => Some(false),
+        _ => None,
+    }
+}
+
+/// Whether the app currently follows the system theme. The stand-in file is only polled then.
+pub fn set_active(active: bool) {
+    ACTIVE.store(active, Ordering::Relaxed);
+}
+
+/// Read the preference in the background and keep following it, repainting when it changes.
+pub fn watch(ctx: &egui::Context) {
+    let ctx = ctx.clone();
+    let spawned = thread::Builder::new()
+        .name("color scheme watcher".into())
+        .spawn(move || {
+            let changed = |dark| {
+                if store(dark) {
+                    info!("System color scheme changed, dark: {:?}", prefers_dark());
+                    ctx.request_repaint();
+                }
+            };
+            changed(detect());
+            match env::var_os(SCHEME_FILE_VAR) {
+                Some(path) => loop {
+                    thread::sleep(POLL_INTERVAL);
+                    if ACTIVE.load(Ordering::Relaxed) {
+                        changed(from_file(Path::new(&path)));
+                    }
+                },
+                // `GTK_THEME` can't change, so only the portal is worth following
+                None => monitor_portal(changed),
+            }
+        });
+    if let Err(e) = spawned {
+        warn!("Can't start color scheme watcher thread: {e}");
+    }
+}
+
+/// Returns whether the value changed.
+fn store(dark: Option<bool>) -> bool {
+    let value = match dark {
+        Some(true) => DARK,
+        Some(false) => LIGHT,
+        None => UNKNOWN,
+    };
+    SCHEME.swap(value, Ordering::Relaxed) != value
+}
+
+/// The stand-in file if there is one, then the settings portal, then `GTK_THEME`.
+pub fn detect() -> Option<bool> {
+    if let Some(path) = env::var_os(SCHEME_FILE_VAR) {
+        return from_file(Path::new(&path));
+    }
+    portal().or_else(gtk_theme)
+}
+
+fn from_file(path: &Path) -> Option<bool> {
+    match fs::read_to_string(path).ok()?.trim() {
+        "dark" => Some(true),
+        "light" => Some(false),
+        _ => None,
+    }
+}
+
+/// Ask the freedesktop settings portal for `org.freedesktop.appearance color-scheme`.
+#[cfg(unix)]
+fn portal() -> Option<bool> {
+    let output = std::process::Command::new("gdbus")
+        .args([
+            "call",
+            "--session",
+            "--dest",
+            "org.freedesktop.portal.Desktop",
+            "--object-path",
+            "/org/freedesktop/portal/desktop",
+            "--method",
+            "org.freedesktop.portal.Settings.Read",
+            "org.freedesktop.appearance",
+            "color-scheme",
+        ])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_portal_reply(&String::from_utf8_lossy(&output.stdout))
+}
+
+#[cfg(not(unix))]
+fn portal() -> Option<bool> {
+    None
+}
+
+/// Pass on every change of the color scheme the settings portal signals, until the monitor exits.
+#[cfg(unix)]
+fn monitor_portal(changed: impl Fn(Option<bool>)) {
+    use std::io::{BufRead, BufReader};
+
+    let child = std::process::Command::new("gdbus")
+        .args([
+            "monitor",
+            "--session",
+            "--dest",
+            "org.freedesktop.portal.Desktop",
+            "--object-path",
+            "/org/freedesktop/portal/desktop",
+        ])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            info!("Not following the system color scheme, can't run gdbus: {e}");
+            return;
+        }
+    };
+    if let Some(stdout) = child.stdout.take() {
+        for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+            if let Some(dark) = parse_setting_changed(&line) {
+                changed(dark);
+            }
+        }
+    }
+    child.wait().ok();
+}
+
+#[cfg(not(unix))]
+fn monitor_portal(_changed: impl Fn(Option<bool>)) {}
+
+/// A monitored signal looks like
+/// `/org/freedesktop/portal/desktop: org.freedesktop.portal.Settings.SettingChanged
+/// ('org.freedesktop.appearance', 'color-scheme', <uint32 1>)`.
+/// `None` for other signals and settings.
+fn parse_setting_changed(line: &str) -> Option<Option<bool>> {
+    let (_, args) = line.split_once(".SettingChanged (")?;
+    args.starts_with("'org.freedesktop.appearance', 'color-scheme',")
+        .then(|| parse_portal_reply(args))
+}
+
+/// The reply looks like `(<<uint32 1>>,)`, where 1 means dark, 2 light and 0 no preference.
+fn parse_portal_reply(reply: &str) -> Option<bool> {
+    let (_, rest) = reply.split_once("uint32 ")?;
+    let digits = rest
+        .chars()
+        .take_while(char::is_ascii_digit)
+        .collect::<String>();
+    match digits.parse::<u32>().ok()? {
+        1 => Some(true),
+        2 => Some(false),
+        _ => None,
+    }
+}
+
+/// GTK themes have dark variants named like `Adwaita:dark` or `Arc-Dark`.
+fn gtk_theme() -> Option<bool> {
+    let theme = env::var("GTK_THEME").ok()?;
+    Some(theme.to_lowercase().contains("dark"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn portal_reply() {
+        assert_eq!(parse_portal_reply("(<<uint32 1>>,)\n"), Some(true));
+        assert_eq!(parse_portal_reply("(<uint32 2>,)\n"), Some(false));
+        assert_eq!(parse_portal_reply("(<<uint32 0>>,)\n"), None);
+        assert_eq!(parse_portal_reply(""), None);
+    }
+
+    #[test]
+    fn setting_changed_signal() {
+        let signal =
+            "/org/freedesktop/portal/desktop: org.freedesktop.portal.Settings.SettingChanged";
+        assert_eq!(
+            parse_setting_changed(&format!(
+                "{signal} ('org.freedesktop.appearance', 'color-scheme', <uint32 1>)"
+            )),
+            Some(Some(true))
+        );
+        assert_eq!(
+            parse_setting_changed(&format!(
+                "{signal} ('org.freedesktop.appearance', 'color-scheme', <uint32 0>)"
+            )),
+            Some(None)
+        );
+        assert_eq!(
+            parse_setting_changed(&format!(
+                "{signal} ('org.freedesktop.appearance', 'accent-color', <(0.2, 0.4, 0.8)>)"
+            )),
+            None
+        );
+        assert_eq!(
+            parse_setting_changed("Monitoring signals from all objects"),
+            None
+        );
+    }
+
+    #[test]
+    fn scheme_file() {
+        let path = env::temp_dir().join(format!("micron-color-scheme-{}", std::process::id()));
+        fs::write(&path, "dark\n").unwrap();
+        assert_eq!(from_file(&path), Some(true));
+        fs::write(&path, "light").unwrap();
+        assert_eq!(from_file(&path), Some(false));
+        fs::write(&path, "").unwrap();
+        assert_eq!(from_file(&path), None);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(from_file(&path), None);
+    }
+}
@@ -0,0 +1,515 @@
+//! CSV and TSV files as a table. Records are found by a background thread and only the ones on
+//! screen are parsed, so large files stay fast.
+
+use crate::{delimited, encoding::Encoding};
+use egui::{Align2, CursorIcon, Rect, Sense, Vec2};
+use log::warn;
+use positioned_io::{RandomAccessFile, ReadAt};
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    fs::File,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering as AtomicOrdering},
+        mpsc::{self, Receiver},
+        Arc, Mutex,
+    },
+};
+
+/// Bytes of a file looked at to detect the delimiter
+const SAMPLE_BYTES: usize = 64 * 1024;
+
+/// Records looked at to size the columns
+const SIZING_RECORDS: usize = 100;
+
+/// Parsed records kept around before the cache is cleared
+const CACHE_RECORDS: usize = 10_000;
+
+/// Chars of a field that are drawn at most
+const MAX_FIELD_CHARS: usize = 200;
+
+const MIN_COLUMN_WIDTH: f32 = 24.0;
+const MAX_COLUMN_WIDTH: f32 = 300.0;
+
+/// Width of the handle to resize a column with
+const RESIZE_HANDLE: f32 = 6.0;
+
+/// Where the records come from.
+#[derive(Clone)]
+pub enum Source {
+    /// A file that is loaded completely
+    Text(Arc<str>),
+    /// A large file that is read as needed
+    File { path: PathBuf, encoding: Encoding },
+}
+
+/// The bytes of a [`Source::Text`], to read them without a copy.
+struct TextBytes(Arc<str>);
+
+impl AsRef<[u8]> for TextBytes {
+    fn as_ref(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+}
+
+impl Source {
+    fn read(&self) -> std::io::Result<Box<dyn std::io::Read + Send>> {
+        Ok(match self {
+            Self::Text(text) => Box::new(std::io::Cursor::new(TextBytes(text.clone()))),
+            Self::File { path, .. } => Box::new(File::open(path)?),
+        })
+    }
+
+    fn decode(&self, bytes: &[u8]) -> String {
+        match self {
+            Self::Text(_) => String::from_utf8_lossy(bytes).into_owned(),
+            Self::File { encoding, .. } => encoding.decode(bytes),
+        }
+    }
+
+    /// The text of byte `range`, or as much of it as there is.
+    fn text(&self, range: std::ops::Range<u64>) -> String {
+        match self {
+            Self::Text(text) => {
+                let bytes = text.as_bytes();
+                let end = (range.end as usize).min(bytes.len());
+                let start = (range.start as usize).min(end);
+                String::from_utf8_lossy(&bytes[start..end]).into_owned()
+            }
+            Self::File { path, encoding } => {
+                let mut buf = vec![0; (range.end - range.start) as usize];
+                match RandomAccessFile::open(path).and_then(|f| f.read_at(range.start, &mut buf)) {
+                    Ok(read) => encoding.decode(&buf[..read]),
+                    Err(e) => {
+                        warn!("Can't read {}: {e}", path.display());
+                        String::new()
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct RecordIndex {
+    /// Byte offset of every record found so far, and of the end of the last one
+    starts: Vec<u64>,
+    /// Whether the whole source was scanned
+    done: bool,
+}
+
+/// How a column sorts: numbers before text.
+enum SortKey {
+    Number(f64),
+    Text(String),
+}
+
+impl SortKey {
+    fn new(field: &str) -> Self {
+        match field.trim().parse::<f64>() {
+            Ok(n) if !n.is_nan() => Self::Number(n),
+            _ => Self::Text(field.to_lowercase()),
+        }
+    }
+
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Self::Number(a), Self::Number(b)) => a.total_cmp(b),
+            (Self::Number(_), Self::Text(_)) => Ordering::Less,
+            (Self::Text(_), Self::Number(_)) => Ordering::Greater,
+            (Self::Text(a), Self::Text(b)) => a.cmp(b),
+        }
+    }
+}
+
+/// Sorting and filtering done in the background.
+struct Arrangement {
+    receiver: Receiver<Vec<usize>>,
+    cancel: Arc<AtomicBool>,
+}
+
+impl Drop for Arrangement {
+    fn drop(&mut self) {
+        self.cancel.store(true, AtomicOrdering::Relaxed);
+    }
+}
+
+/// A delimited file as a table with a header row, resizable columns, sorting and a filter.
+pub struct TableView {
+    source: Source,
+    /// Text version the records are from
+    version: u64,
+    delimiter: char,
+    header: bool,
+    index: Arc<Mutex<RecordIndex>>,
+    widths: Vec<f32>,
+    /// Column and whether it is sorted ascending
+    sort: Option<(usize, bool)>,
+    filter: String,
+    /// Records shown, in order, when sorted or filtered
+    order: Option<Vec<usize>>,
+    arrangement: Option<Arrangement>,
+    cache: HashMap<usize, Vec<String>>,
+}
+
+impl TableView {
+    /// Fields are split by the delimiter found in the first lines, or by `delimiter` if none is.
+    pub fn new(source: Source, version: u64, delimiter: char) -> Self {
+        let sample = source.text(0..SAMPLE_BYTES as u64);
+        let delimiter = delimited::detect(&sample).unwrap_or(delimiter);
+        let mut view = Self {
+            source: source.clone(),
+            version,
+            delimiter,
+            header: true,
+            index: Default::default(),
+            widths: vec![],
+            sort: None,
+            filter: String::new(),
+            order: None,
+            arrangement: None,
+            cache: HashMap::new(),
+        };
+        view.set_source(source, version);
+        view
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Show records from `source` from now on, keeping the columns, sorting and filter.
+    pub fn set_source(&mut self, source: Source, version: u64) {
+        self.source = source;
+        self.version = version;
+        self.cache.clear();
+        self.index = Default::default();
+        // Stops once the view or source is gone
+        let index = Arc::downgrade(&self.index);
+        let source = self.source.clone();
+        std::thread::spawn(move || {
+            let scanned = source.read().and_then(|reader| {
+                delimited::scan(reader, |_, start, bytes| {
+                    let Some(index) = index.upgrade() else {
+                        return false;
+                    };
+                    let Ok(mut index) = index.lock() else {
+                        return false;
+                    };
+                    if index.starts.is_empty() {
+                        index.starts.push(start);
+                    }
+                    index.starts.push(start + bytes.len() as u64);
+                    true
+                })
+            });
+            if let Err(e) = scanned {
+                warn!("Can't read records: {e}");
+            }
+            if let Some(Ok(mut index)) = index.upgrade().as_deref().map(Mutex::lock) {
+                index.done = true;
+            }
+        });
+        self.arrange();
+    }
+
+    /// Number of records found so far, and whether that is all of them.
+    fn record_count(&self) -> (usize, bool) {
+        self.index.lock().map_or((0, true), |index| {
+            (index.starts.len().saturating_sub(1), index.done)
+        })
+    }
+
+    fn record(&mut self, record: usize) -> &[String] {
+        if self.cache.len() > CACHE_RECORDS {
+            self.cache.clear();
+        }
+        let range = self
+            .index
+            .lock()
+            .ok()
+            .and_then(|index| Some(*index.starts.get(record)?..*index.starts.get(record + 1)?));
+        let (source, delimiter) = (&self.source, self.delimiter);
+        self.cache.entry(record).or_insert_with(|| match range {
+            Some(range) => delimited::record(&source.text(range), delimiter),
+            None => vec![],
+        })
+    }
+
+    /// Start sorting and filtering again, or show all records in file order if there is nothing
+    /// to do.
+    fn arrange(&mut self) {
+        self.order = None;
+        self.arrangement = None;
+        if self.sort.is_none() && self.filter.is_empty() {
+            return;
+        }
+        let (sender, receiver) = mpsc::channel();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let cancelled = cancel.clone();
+        let (source, delimiter, header, sort) =
+            (self.source.clone(), self.delimiter, self.header, self.sort);
+        let filter = self.filter.to_lowercase();
+        std::thread::spawn(move || {
+            let mut rows = vec![];
+            let scanned = source.read().and_then(|reader| {
+                delimited::scan(reader, |i, _, bytes| {
+                    if header && i == 0 {
+                        return true;
+                    }
+                    let fields = delimited::record(&source.decode(bytes), delimiter);
+                    if filter.is_empty()
+                        || fields.iter().any(|f| f.to_lowercase().contains(&filter))
+                    {
+                        let key = sort.map(|(column, _)| {
+                            SortKey::new(fields.get(column).map_or("", String::as_str))
+                        });
+                        rows.push((i, key));
+                    }
+                    !cancelled.load(AtomicOrdering::Relaxed)
+                })
+            });
+            if let Err(e) = scanned {
+                warn!("Can't sort records: {e}");
+                return;
+            }
+            if let Some((_, ascending)) = sort {
+                rows.sort_by(|(_, a), (_, b)| {
+                    let (Some(a), Some(b)) = (a, b) else {
+                        return Ordering::Equal;
+                    };
+                    if ascending {
+                        a.cmp(b)
+                    } else {
+                        b.cmp(a)
+                    }
+                });
+            }
+            sender.send(rows.into_iter().map(|(i, _)| i).collect()).ok();
+        });
+        self.arrangement = Some(Arrangement { receiver, cancel });
+    }
+
+    /// Column widths from the header and the first records.
+    fn size_columns(&mut self, ui: &egui::Ui) {
+        let (count, _) = self.record_count();
+        let records = count.min(SIZING_RECORDS);
+        if !self.widths.is_empty() || records == 0 {
+            return;
+        }
+        let char_width =
+            ui.fonts(|f| f.glyph_width(&egui::TextStyle::Body.resolve(ui.style()), '0'));
+        let mut widths = vec![];
+        for record in 0..records {
+            for (column, field) in self.record(record).iter().enumerate() {
+                let width = (field.chars().count() as f32 + 2.0) * char_width;
+                if column >= widths.len() {
+                    widths.push(MIN_COLUMN_WIDTH);
+                }
+                widths[column] = width.clamp(widths[column], MAX_COLUMN_WIDTH);
+            }
+        }
+        self.widths = widths;
+    }
+
+    /// Show the toolbar and the records on screen.
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        if let Some(arrangement) = &self.arrangement {
+            match arrangement.receiver.try_recv() {
+                Ok(order) => {
+                    self.order = Some(order);
+                    self.arrangement = None;
+                }
+                Err(_) => ui
+                    .ctx()
+                    .request_repaint_after(std::time::Duration::from_millis(100)),
+            }
+        }
+        let (count, done) = self.record_count();
+        if !done {
+            ui.ctx()
+                .request_repaint_after(std::time::Duration::from_millis(100));
+        }
+
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_label("Delimiter")
+                .selected_text(delimited::name(self.delimiter))
+                .show_ui(ui, |ui| {
+                    for delimiter in delimited::DELIMITERS {
+                        changed |= ui
+                            .selectable_value(
+                                &mut self.delimiter,
+                                delimiter,
+                                delimited::name(delimiter),
+                            )
+                            .changed();
+                    }
+                });
+            changed |= ui.checkbox(&mut self.header, "Header row").changed();
+            let filter = ui.add(
+                egui::TextEdit::singleline(&mut self.filter)
+                    .hint_text("Filter")
+                    .desired_width(160.0),
+            );
+            if filter.changed() {
+                self.arrange();
+            }
+            let records = count.saturating_sub(usize::from(self.header));
+            let status = match (&self.order, &self.arrangement) {
+                (_, Some(_)) => "sorting…".to_string(),
+                (Some(order), None) if !self.filter.is_empty() => {
+                    format!("{} of {records} rows", order.len())
+                }
+                _ if !done => format!("{records} rows so far…"),
+                _ => format!("{records} rows"),
+            };
+            ui.weak(status);
+        });
+        if changed {
+            self.cache.clear();
+            self.widths.clear();
+            self.arrange();
+        }
+        ui.separator();
+        self.size_columns(ui);
+
+        let font_id = egui::TextStyle::Body.resolve(ui.style());
+        let row_height = ui.fonts(|f| f.row_height(&font_id)) + 4.0;
+        let number_width =
+            (count.max(1).ilog10() + 2) as f32 * ui.fonts(|f| f.glyph_width(&font_id, '0')) + 8.0;
+        let header = self.header.then(|| self.record(0).to_vec());
+        let first = usize::from(self.header);
+        let rows = match &self.order {
+            Some(order) => order.len(),
+            None => count.saturating_sub(first),
+        };
+        let visuals = ui.visuals().clone();
+
+        egui::ScrollArea::horizontal()
+            .auto_shrink([false; 2])
+            .show(ui, |ui| {
+                let total_width = number_width + self.widths.iter().sum::<f32>();
+                let (rect, _) =
+                    ui.allocate_exact_size(Vec2::new(total_width, row_height), Sense::hover());
+                ui.painter()
+                    .rect_filled(rect, 0.0, visuals.widgets.noninteractive.bg_fill);
+                let mut x = rect.left() + number_width;
+                let mut sort = None;
+                for column in 0..self.widths.len() {
+                    let cell = Rect::from_min_size(
+                        egui::pos2(x, rect.top()),
+                        Vec2::new(self.widths[column], row_height),
+                    );
+                    let name = header
+                        .as_ref()
+                        .and_then(|h| h.get(column).cloned())
+                        .unwrap_or_else(|| format!("{}", column + 1));
+                    let arrow = match self.sort {
+                        Some((c, true)) if c == column => " ⏶",
+                        Some((c, false)) if c == column => " ⏷",
+                        _ => "",
+                    };
+                    let response = ui
+                        .interact(cell, ui.id().with(("sort", column)), Sense::click())
+                        .on_hover_text("Sort");
+                    if response.clicked() {
+                        sort = Some(column);
+                    }
+                    ui.painter().with_clip_rect(cell.shrink(2.0)).text(
+                        cell.left_center() + Vec2::new(4.0, 0.0),
+                        Align2::LEFT_CENTER,
+                        format!("{name}{arrow}"),
+                        font_id.clone(),
+                        visuals.strong_text_color(),
+                    );
+
+                    let handle = Rect::from_center_size(
+                        cell.right_center(),
+                        Vec2::new(RESIZE_HANDLE, row_height),
+                    );
+                    let resize =
+                        ui.interact(handle, ui.id().with(("resize", column)), Sense::drag());
+                    if resize.hovered() || resize.dragged() {
+                        ui.ctx().set_cursor_icon(CursorIcon::ResizeHorizontal);
+                    }
+                    if resize.dragged() {
+                        self.widths[column] =
+                            (self.widths[column] + resize.drag_delta().x).max(MIN_COLUMN_WIDTH);
+                    }
+                    ui.painter().vline(
+                        cell.right(),
+                        cell.y_range(),
+                        visuals.widgets.noninteractive.bg_stroke,
+                    );
+                    x += self.widths[column];
+                }
+                if let Some(column) = sort {
+                    self.sort = match self.sort {
+                        Some((c, true)) if c == column => Some((column, false)),
+                        Some((c, false)) if c == column => None,
+                        _ => Some((column, true)),
+                    };
+                    self.arrange();
+                }
+
+                egui::ScrollArea::vertical()
+                    .auto_shrink([false; 2])
+                    .show_rows(ui, row_height, rows, |ui, visible| {
+                        ui.spacing_mut().item_spacing.y = 0.0;
+                        for row in visible {
+                            let record = match &self.order {
+                                Some(order) => order[row],
+                                None => row + first,
+                            };
+                            let (rect, _) = ui.allocate_exact_size(
+                                Vec2::new(total_width, row_height),
+                                Sense::hover(),
+                            );
+                            if row % 2 == 1 {
+                                ui.painter().rect_filled(rect, 0.0, visuals.faint_bg_color);
+                            }
+                            ui.painter().text(
+                                rect.left_center() + Vec2::new(4.0, 0.0),
+                                Align2::LEFT_CENTER,
+                                (record + 1 - first).to_string(),
+                                font_id.clone(),
+                                visuals.weak_text_color(),
+                            );
+                            let widths = self.widths.clone();
+                            let mut x = rect.left() + number_width;
+                            for (field, width) in self.record(record).iter().zip(widths) {
+                                let cell = Rect::from_min_size(
+                                    egui::pos2(x, rect.top()),
+                                    Vec2::new(width, row_height),
+                                );
+                                let text = field.chars().take(MAX_FIELD_CHARS).collect::<String>();
+                                ui.painter().with_clip_rect(cell.shrink(2.0)).text(
+                                    cell.left_center() + Vec2::new(4.0, 0.0),
+                                    Align2::LEFT_CENTER,
+                                    text,
+                                    font_id.clone(),
+                                    visuals.text_color(),
+                                );
+                                x += width;
+                            }
+                        }
+                    });
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_sort_before_text() {
+        let mut keys = ["b", "10", "a", "9", "-1.5"].map(SortKey::new);
+        keys.sort_by(SortKey::cmp);
+        assert!(matches!(keys[0], SortKey::Number(n) if n == -1.5));
+        assert!(matches!(keys[2], SortKey::Number(n) if n == 10.0));
+        assert!(matches!(&keys[3], SortKey::Text(t) if t == "a"));
+    }
+}
@@ -0,0 +1,468 @@
+use crate::{
+    settings::EDITOR_FONT,
+    syntax_highlighting::{self, to_color32},
+};
+use egui::Color32;
+use log::{info, warn};
+use std::{fmt::Write, fs, str::FromStr};
+use syntect::{
+    highlighting::{Color, FontStyle, ScopeSelectors, StyleModifier, Theme, ThemeItem},
+    parsing::Scope,
+};
+
+/// Scope groups that can be recolored, with the selector used for them
+const SCOPE_GROUPS: &[(&str, &str)] = &[
+    ("Comments", "comment"),
+    ("Strings", "string"),
+    ("Keywords", "keyword, storage.modifier"),
+    ("Numbers and constants", "constant"),
+    ("Functions", "entity.name.function, support.function"),
+    (
+        "Types",
+        "entity.name.type, entity.name.class, support.type, storage.type",
+    ),
+    ("Variables", "variable"),
+    ("Tags and keys", "entity.name.tag"),
+    ("Punctuation", "punctuation"),
+];
+
+const SAMPLE: &str = r#"// Count the words in a file
+fn count(path: &str) -> usize {
+    let text = std::fs::read_to_string(path).unwrap_or_default();
+    text.split_whitespace().count() + 0
+}
+"#;
+
+/// A theme being edited: a base theme plus the colors the user changed.
+pub struct ThemeEditor {
+    base: String,
+    name: String,
+    scopes: Vec<Option<Color32>>,
+    background: Option<Color32>,
+    selection: Option<Color32>,
+    line_highlight: Option<Color32>,
+    /// The preview needs to be updated
+    dirty: bool,
+    /// The theme the preview is shown instead of
+    previewed: String,
+    error: Option<String>,
+}
+
+impl ThemeEditor {
+    pub fn new(base: &str) -> Self {
+        Self {
+            base: base.into(),
+            name: format!("{base} custom"),
+            scopes: vec![None; SCOPE_GROUPS.len()],
+            background: None,
+            selection: None,
+            line_highlight: None,
+            dirty: true,
+            previewed: String::new(),
+            error: None,
+        }
+    }
+
+    /// Show the editor window, previewing the theme instead of `theme_name`, which is set to the
+    /// new theme once it was saved. Returns false once it was closed.
+    pub fn ui(&mut self, ctx: &egui::Context, theme_name: &mut String) -> bool {
+        if syntax_highlighting::revision().is_none() {
+            let mut open = true;
+            egui::Window::new("Theme editor")
+                .open(&mut open)
+                .show(ctx, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.spinner();
+                        ui.label("Loading themes…");
+                    });
+                });
+            return open;
+        }
+        let Some(base) = syntax_highlighting::theme(&self.base) else {
+            warn!("Theme {} is gone", self.base);
+            syntax_highlighting::preview_theme(None);
+            return false;
+        };
+
+        let mut open = true;
+        let mut saved = false;
+        let mut cancelled = false;
+        egui::Window::new("Theme editor")
+            .open(&mut open)
+            .show(ctx, |ui| {
+                egui::Grid::new("theme_editor_name").show(ui, |ui| {
+                    ui.label("Based on");
+                    egui::ComboBox::from_id_source("theme_editor_base")
+                        .selected_text(&self.base)
+                        .show_ui(ui, |ui| {
+                            for (key, label, _) in syntax_highlighting::theme_choices() {
+                                if key != self.name {
+                                    self.dirty |=
+                                        ui.selectable_value(&mut self.base, key, label).changed();
+                                }
+                            }
+                        });
+                    ui.end_row();
+                    ui.label("Name");
+                    self.dirty |= ui.text_edit_singleline(&mut self.name).changed();
+                    ui.end_row();
+                });
+                ui.separator();
+
+                let highlighter = syntect::highlighting::Highlighter::new(&base);
+                egui::Grid::new("theme_editor_colors").show(ui, |ui| {
+                    for ((label, selector), color) in SCOPE_GROUPS.iter().zip(&mut self.scopes) {
+                        let first = selector.split(',').next().unwrap_or_default().trim();
+                        let current = Scope::new(first)
+                            .map(|s| to_color32(highlighter.style_for_stack(&[s]).foreground))
+                            .unwrap_or(Color32::GRAY);
+                        self.dirty |= color_row(ui, label, color, current);
+                    }
+                    let settings = &base.settings;
+                    let current = settings.background.map_or(Color32::BLACK, to_color32);
+                    self.dirty |= color_row(ui, "Background", &mut self.background, current);
+                    let current = settings.selection.map_or(Color32::GRAY, to_color32);
+                    self.dirty |= color_row(ui, "Selection", &mut self.selection, current);
+                    let current = settings
+                        .line_highlight
+                        .map_or(Color32::DARK_GRAY, to_color32);
+                    self.dirty |= color_row(ui, "Current line", &mut self.line_highlight, current);
+                });
+                ui.separator();
+
+                let theme = self.build(&base);
+                preview(ui, &theme);
+
+                if let Some(error) = &self.error {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                }
+                ui.horizontal(|ui| {
+                    if ui
+                        .button("Save")
+                        .on_hover_text("Add it to the theme list")
+                        .clicked()
+                    {
+                        match self.save(&theme) {
+                            Ok(()) => {
+                                syntax_highlighting::reload(ctx);
+                                *theme_name = self.name.clone();
+                                saved = true;
+                            }
+                            Err(e) => self.error = Some(e.to_string()),
+                        }
+                    }
+                    if ui.button("Export…").clicked() {
+                        self.export(&theme);
+                    }
+                    cancelled = ui.button("Cancel").clicked();
+                });
+
+                if self.dirty || self.previewed != *theme_name {
+                    self.dirty = false;
+                    self.previewed = theme_name.clone();
+                    syntax_highlighting::preview_theme(Some((theme_name.clone(), theme)));
+                }
+            });
+
+        if saved || cancelled || !open {
+            syntax_highlighting::preview_theme(None);
+            return false;
+        }
+        true
+    }
+
+    /// The base theme with our colors
+    fn build(&self, base: &Theme) -> Theme {
+        let mut theme = base.clone();
+        theme.name = Some(self.name.clone());
+        if let Some(c) = self.background {
+            theme.settings.background = Some(to_color(c));
+        }
+        if let Some(c) = self.selection {
+            theme.settings.selection = Some(to_color(c));
+        }
+        if let Some(c) = self.line_highlight {
+            theme.settings.line_highlight = Some(to_color(c));
+        }
+
+        for ((_, selector), color) in SCOPE_GROUPS.iter().zip(&self.scopes) {
+            let (Some(color), Ok(scope)) = (color, ScopeSelectors::from_str(selector)) else {
+                continue;
+            };
+            let prefixes = scope
+                .selectors
+                .iter()
+                .filter_map(|s| s.path.as_slice().last().copied())
+                .collect::<Vec<_>>();
+            // Rules for more specific scopes would win against ours, so drop them
+            for item in &mut theme.scopes {
+                item.scope.selectors.retain(|s| {
+                    !s.path
+                        .as_slice()
+                        .last()
+                        .is_some_and(|last| prefixes.iter().any(|p| p.is_prefix_of(*last)))
+                });
+            }
+            theme.scopes.retain(|item| !item.scope.selectors.is_empty());
+            // Of equally specific rules the first one wins
+            theme.scopes.insert(
+                0,
+                ThemeItem {
+                    scope,
+                    style: StyleModifier {
+                        foreground: Some(to_color(*color)),
+                        ..Default::default()
+                    },
+                },
+            );
+        }
+        theme
+    }
+
+    /// Write the theme to the themes folder in the config dir, where it is loaded from.
+    fn save(&self, theme: &Theme) -> anyhow::Result<()> {
+        let name = self.name.trim();
+        if name.is_empty() {
+            anyhow::bail!("The theme needs a name");
+        }
+        if name.contains(['/', '\\']) {
+            anyhow::bail!("The name can't contain / or \\");
+        }
+        if syntax_highlighting::theme_choices()
+            .iter()
+            .any(|(key, label, _)| key == name && label != name)
+        {
+            anyhow::bail!("Built-in themes can't be replaced");
+        }
+        let Some(dir) = crate::settings::config_dir().map(|d| d.join("themes")) else {
+            anyhow::bail!("No config dir");
+        };
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(format!("{name}.tmTheme"));
+        fs::write(&path, to_tm_theme(theme))?;
+        info!("Saved theme to {}", path.display());
+        Ok(())
+    }
+
+    fn export(&mut self, theme: &Theme) {
+        let Some(path) = rfd::FileDialog::new()
+            .add_filter("TextMate theme", &["tmTheme"])
+            .set_file_name(&format!("{}.tmTheme", self.name))
+            .save_file()
+        else {
+            return;
+        };
+        match fs::write(&path, to_tm_theme(theme)) {
+            Ok(()) => info!("Exported theme to {}", path.display()),
+            Err(e) => self.error = Some(e.to_string()),
+        }
+    }
+}
+
+/// A color button showing `current` until the user picks an own color. Returns true on changes.
+fn color_row(
+    ui: &mut egui::Ui,
+    label: &str,
+    color: &mut Option<Color32>,
+    current: Color32,
+) -> bool {
+    let mut changed = false;
+    ui.label(label);
+    let mut value = color.unwrap_or(current);
+    if ui.color_edit_button_srgba(&mut value).changed() {
+        *color = Some(value);
+        changed = true;
+    }
+    if ui
+        .add_enabled(color.is_some(), egui::Button::new("↺"))
+        .on_hover_text("Use the color of the base theme")
+        .clicked()
+    {
+        *color = None;
+        changed = true;
+    }
+    ui.end_row();
+    changed
+}
+
+/// Some code in `theme`, with a selection and the current line marked.
+fn preview(ui: &mut egui::Ui, theme: &Theme) {
+    let font_id = egui::FontId::new(12.0, egui::FontFamily::Name(EDITOR_FONT.into()));
+    let mut job = syntax_highlighting::highlight_snippet(theme, "rs", SAMPLE, &font_id);
+    if let Some(selection) = theme.settings.selection {
+        // Select "text" in the third line
+        let start = SAMPLE.find("let text").unwrap_or_default() + "let ".len();
+        job = with_background(job, start..start + "text".len(), to_color32(selection));
+    }
+    let galley = ui.fonts(|f| f.layout_job(job));
+
+    let background = theme.settings.background.map_or(Color32::BLACK, to_color32);
+    egui::Frame::none()
+        .fill(background)
+        .inner_margin(4.0)
+        .show(ui, |ui| {
+            let (rect, _) = ui.allocate_exact_size(
+                egui::vec2(ui.available_width().max(galley.size().x), galley.size().y),
+                egui::Sense::hover(),
+            );
+            if let (Some(color), Some(row)) = (theme.settings.line_highlight, galley.rows.get(1)) {
+                let row_rect = egui::Rect::from_x_y_ranges(
+                    rect.x_range(),
+                    row.rect.translate(rect.min.to_vec2()).y_range(),
+                );
+                ui.painter().rect_filled(row_rect, 0.0, to_color32(color));
+            }
+            ui.painter().galley(rect.min, galley);
+        });
+}
+
+/// Give the text in `range` a background color, splitting sections as needed.
+fn with_background(
+    job: egui::text::LayoutJob,
+    range: std::ops::Range<usize>,
+    color: Color32,
+) -> egui::text::LayoutJob {
+    let mut out = egui::text::LayoutJob {
+        sections: vec![],
+        ..job.clone()
+    };
+    for section in job.sections {
+        let r = section.byte_range.clone();
+        let cuts = [
+            r.start,
+            range.start.clamp(r.start, r.end),
+            range.end.clamp(r.start, r.end),
+            r.end,
+        ];
+        for pair in cuts.windows(2).filter(|p| p[0] < p[1]) {
+            let mut part = section.clone();
+            part.byte_range = pair[0]..pair[1];
+            if range.contains(&pair[0]) {
+                part.format.background = color;
+            }
+            out.sections.push(part);
+        }
+    }
+    out
+}
+
+fn to_color(c: Color32) -> Color {
+    let [r, g, b, a] = c.to_srgba_unmultiplied();
+    Color { r, g, b, a }
+}
+
+/// Serialize `theme` as a TextMate theme, i.e. an XML property list.
+fn to_tm_theme(theme: &Theme) -> String {
+    let mut out = String::from(concat!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+        "\n",
+        r#"<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">"#,
+        "\n<plist version=\"1.0\">\n<dict>\n",
+    ));
+    if let Some(name) = &theme.name {
+        key_string(&mut out, 1, "name", name);
+    }
+    if let Some(author) = &theme.author {
+        key_string(&mut out, 1, "author", author);
+    }
+    out += "\t<key>settings</key>\n\t<array>\n";
+
+    let s = &theme.settings;
+    let globals = [
+        ("foreground", s.foreground),
+        ("background", s.background),
+        ("caret", s.caret),
+        ("lineHighlight", s.line_highlight),
+        ("selection", s.selection),
+        ("selectionForeground", s.selection_foreground),
+        ("selectionBorder", s.selection_border),
+        ("inactiveSelection", s.inactive_selection),
+        (
+            "inactiveSelectionForeground",
+            s.inactive_selection_foreground,
+        ),
+        ("gutter", s.gutter),
+        ("gutterForeground", s.gutter_foreground),
+        ("highlight", s.highlight),
+        ("findHighlight", s.find_highlight),
+        ("findHighlightForeground", s.find_highlight_foreground),
+        ("guide", s.guide),
+        ("activeGuide", s.active_guide),
+        ("stackGuide", s.stack_guide),
+        ("misspelling", s.misspelling),
+        ("accent", s.accent),
+        ("minimapBorder", s.minimap_border),
+        ("bracketsForeground", s.brackets_foreground),
+        ("bracketsBackground", s.brackets_background),
+        ("bracketContentsForeground", s.bracket_contents_foreground),
+        ("tagsForeground", s.tags_foreground),
+        ("shadow", s.shadow),
+    ];
+    out += "\t\t<dict>\n\t\t\t<key>settings</key>\n\t\t\t<dict>\n";
+    for (key, color) in globals {
+        if let Some(color) = color {
+            key_string(&mut out, 4, key, &hex(color));
+        }
+    }
+    out += "\t\t\t</dict>\n\t\t</dict>\n";
+
+    for item in &theme.scopes {
+        out += "\t\t<dict>\n";
+        key_string(&mut out, 3, "scope", &selectors_string(&item.scope));
+        out += "\t\t\t<key>settings</key>\n\t\t\t<dict>\n";
+        if let Some(color) = item.style.foreground {
+            key_string(&mut out, 4, "foreground", &hex(color));
+        }
+        if let Some(color) = item.style.background {
+            key_string(&mut out, 4, "background", &hex(color));
+        }
+        if let Some(font_style) = item.style.font_style {
+            let names = [
+                (FontStyle::BOLD, "bold"),
+                (FontStyle::ITALIC, "italic"),
+                (FontStyle::UNDERLINE, "underline"),
+            ]
+            .into_iter()
+            .filter(|(flag, _)| font_style.contains(*flag))
+            .map(|(_, name)| name)
+            .collect::<Vec<_>>();
+            key_string(&mut out, 4, "fontStyle", &names.join(" "));
+        }
+        out += "\t\t\t</dict>\n\t\t</dict>\n";
+    }
+    out += "\t</array>\n</dict>\n</plist>\n";
+    out
+}
+
+fn key_string(out: &mut String, indent: usize, key: &str, value: &str) {
+    let tabs = "\t".repeat(indent);
+    let _ = writeln!(out, "{tabs}<key>{key}</key>");
+    let _ = writeln!(out, "{tabs}<string>{}</string>", escape(value));
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn hex(c: Color) -> String {
+    format!("#{:02X}{:02X}{:02X}{:02X}", c.r, c.g, c.b, c.a)
+}
+
+/// Back to the text form, e.g. `source.rust string - comment, constant`.
+fn selectors_string(selectors: &ScopeSelectors) -> String {
+    selectors
+        .selectors
+        .iter()
+        .map(|s| {
+            let mut out = s.path.to_string().trim_end().to_string();
+            for exclude in &s.excludes {
+                out += " - ";
+                out += exclude.to_string().trim_end();
+            }
+            out
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
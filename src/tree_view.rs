@@ -0,0 +1,759 @@
+//! JSON, YAML and TOML documents as a collapsible tree.
+
+use crate::{
+    format::{self, Format, ParseError},
+    line_buffer::Position,
+};
+use egui::RichText;
+use serde::de::{Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use std::{collections::HashSet, fmt, ops::Range};
+use yaml_rust::{
+    parser::{Event, MarkedEventReceiver, Parser},
+    scanner::{Marker, TScalarStyle},
+};
+
+/// Chars of a value shown next to its key
+const PREVIEW_CHARS: usize = 60;
+
+/// Where a node is in its parent.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Key {
+    Root,
+    Name(String),
+    Index(usize),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Kind {
+    Object,
+    Array,
+    Scalar,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Node {
+    pub key: Key,
+    pub kind: Kind,
+    pub parent: Option<usize>,
+    pub depth: usize,
+    /// Start of the key, or of the value if it has none
+    pub start: Position,
+    /// The value as written, for scalars
+    pub value: String,
+    /// Number of children
+    pub len: usize,
+    /// Index after the last descendant
+    pub end: usize,
+}
+
+/// The nodes of a document, each followed by its descendants.
+#[derive(Default, Debug)]
+pub struct Tree {
+    pub nodes: Vec<Node>,
+}
+
+impl Tree {
+    /// Only JSON, YAML and TOML can be shown as a tree.
+    pub fn supports(format: Format) -> bool {
+        format != Format::Xml
+    }
+
+    pub fn parse(format: Format, text: &str) -> Result<Self, ParseError> {
+        match format {
+            Format::Json => json(text),
+            Format::Yaml => yaml(text),
+            Format::Toml => toml(text),
+            Format::Xml => Err(ParseError {
+                message: "XML can't be shown as a tree".into(),
+                pos: Position::default(),
+            }),
+        }
+    }
+
+    fn ancestors(&self, node: usize) -> impl Iterator<Item = usize> + '_ {
+        std::iter::successors(self.nodes[node].parent, |i| self.nodes[*i].parent)
+    }
+
+    /// The innermost node that starts at or before `pos`.
+    pub fn node_at(&self, pos: Position) -> Option<usize> {
+        // Not always in document order, as TOML tables can be split up
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, n)| n.start <= pos)
+            .max_by_key(|(i, n)| (n.start, *i))
+            .map(|(i, _)| i)
+    }
+
+    /// Like `$.store.books[0]['first name']`.
+    pub fn json_path(&self, node: usize) -> String {
+        let mut nodes = self.ancestors(node).collect::<Vec<_>>();
+        nodes.reverse();
+        nodes.push(node);
+        let mut path = String::new();
+        for key in nodes.into_iter().map(|n| &self.nodes[n].key) {
+            match key {
+                Key::Root => path.push('$'),
+                Key::Index(i) => path.push_str(&format!("[{i}]")),
+                Key::Name(name)
+                    if name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+                        && name.chars().all(|c| c.is_alphanumeric() || c == '_') =>
+                {
+                    path.push('.');
+                    path.push_str(name);
+                }
+                Key::Name(name) => {
+                    let escaped = name.replace('\\', "\\\\").replace('\'', "\\'");
+                    path.push_str(&format!("['{escaped}']"));
+                }
+            }
+        }
+        path
+    }
+
+    /// Key and value as shown in a row.
+    fn label(&self, node: usize) -> (String, String) {
+        let node = &self.nodes[node];
+        let key = match &node.key {
+            Key::Root => "$".to_string(),
+            Key::Name(name) => name.clone(),
+            Key::Index(i) => format!("[{i}]"),
+        };
+        let value = match node.kind {
+            Kind::Object => format!("{{{}}}", node.len),
+            Kind::Array => format!("[{}]", node.len),
+            Kind::Scalar if node.value.chars().count() > PREVIEW_CHARS => {
+                let mut preview = node.value.chars().take(PREVIEW_CHARS).collect::<String>();
+                preview.push('…');
+                preview
+            }
+            Kind::Scalar => node.value.clone(),
+        };
+        (key, value)
+    }
+}
+
+/// Adds nodes in document order.
+#[derive(Default)]
+struct Builder {
+    tree: Tree,
+    /// Objects and arrays we are in
+    open: Vec<usize>,
+}
+
+impl Builder {
+    /// Adds a node as the next child of the innermost open one. Objects and arrays stay open
+    /// until [`Builder::close`].
+    fn add(&mut self, name: Option<String>, kind: Kind, start: Position, value: String) {
+        let id = self.tree.nodes.len();
+        let parent = self.open.last().copied();
+        let key = match (name, parent) {
+            (Some(name), _) => Key::Name(name),
+            (None, Some(parent)) => Key::Index(self.tree.nodes[parent].len),
+            (None, None) => Key::Root,
+        };
+        if let Some(parent) = parent {
+            self.tree.nodes[parent].len += 1;
+        }
+        self.tree.nodes.push(Node {
+            key,
+            kind,
+            parent,
+            depth: self.open.len(),
+            start,
+            value,
+            len: 0,
+            end: id + 1,
+        });
+        if kind != Kind::Scalar {
+            self.open.push(id);
+        }
+    }
+
+    fn close(&mut self) {
+        if let Some(id) = self.open.pop() {
+            self.tree.nodes[id].end = self.tree.nodes.len();
+        }
+    }
+}
+
+/// Turns byte offsets into positions.
+struct Lines<'a> {
+    text: &'a str,
+    starts: Vec<usize>,
+}
+
+impl<'a> Lines<'a> {
+    fn new(text: &'a str) -> Self {
+        let starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Self { text, starts }
+    }
+
+    fn position(&self, offset: usize) -> Position {
+        let line = self.starts.partition_point(|s| *s <= offset) - 1;
+        let column = self.text[self.starts[line]..offset].chars().count();
+        Position::new(line, column)
+    }
+}
+
+fn json(text: &str) -> Result<Tree, ParseError> {
+    format::check_json(text)?;
+    let lines = Lines::new(text);
+    let bytes = text.as_bytes();
+    let mut builder = Builder::default();
+    let mut i = 0;
+    // Start and name of the member whose value comes next
+    let mut key: Option<(usize, String)> = None;
+    let string_end = |start: usize| {
+        let mut end = start + 1;
+        while bytes[end] != b'"' {
+            end += if bytes[end] == b'\\' { 2 } else { 1 };
+        }
+        end + 1
+    };
+    while i < bytes.len() {
+        let in_object = builder
+            .open
+            .last()
+            .is_some_and(|n| builder.tree.nodes[*n].kind == Kind::Object);
+        match bytes[i] {
+            b'"' if in_object && key.is_none() => {
+                let end = string_end(i);
+                let name = serde_json::from_str(&text[i..end]).unwrap_or_default();
+                key = Some((i, name));
+                i = end;
+                continue;
+            }
+            open @ (b'{' | b'[') => {
+                let (start, name) = key.take().map_or((i, None), |(s, n)| (s, Some(n)));
+                let kind = if open == b'{' {
+                    Kind::Object
+                } else {
+                    Kind::Array
+                };
+                builder.add(name, kind, lines.position(start), String::new());
+            }
+            b'}' | b']' => builder.close(),
+            b',' | b':' => {}
+            b if b.is_ascii_whitespace() => {}
+            _ => {
+                let end = if bytes[i] == b'"' {
+                    string_end(i)
+                } else {
+                    bytes[i..]
+                        .iter()
+                        .position(|b| matches!(b, b',' | b'}' | b']') || b.is_ascii_whitespace())
+                        .map_or(bytes.len(), |n| i + n)
+                };
+                let (start, name) = key.take().map_or((i, None), |(s, n)| (s, Some(n)));
+                builder.add(
+                    name,
+                    Kind::Scalar,
+                    lines.position(start),
+                    text[i..end].to_string(),
+                );
+                i = end;
+                continue;
+            }
+        }
+        i += 1;
+    }
+    Ok(builder.tree)
+}
+
+/// Builds the tree from parser events, which unlike the loaded documents know where they are.
+struct YamlBuilder {
+    builder: Builder,
+    /// Per open node whether it is a mapping, and the key and where it starts once it was read
+    frames: Vec<(bool, Option<(String, Position)>)>,
+    /// How deep we are in a mapping or sequence used as a key, which we don't show
+    in_key: usize,
+    /// Whether there is more than one document, so they need a common root
+    multi: bool,
+}
+
+impl YamlBuilder {
+    fn add(&mut self, kind: Kind, mark: Marker, value: String) {
+        let pos = Position::new(mark.line().saturating_sub(1), mark.col());
+        if self.in_key > 0 {
+            self.in_key += usize::from(kind != Kind::Scalar);
+            return;
+        }
+        let (name, start) = match self.frames.last_mut() {
+            Some((true, key @ None)) if kind == Kind::Scalar => {
+                *key = Some((value, pos));
+                return;
+            }
+            Some((true, None)) => {
+                self.in_key = 1;
+                return;
+            }
+            Some((true, key)) => key.take().map_or((None, pos), |(n, s)| (Some(n), s)),
+            _ => (None, pos),
+        };
+        if kind != Kind::Scalar {
+            self.frames.push((kind == Kind::Object, None));
+        }
+        self.builder.add(name, kind, start, value);
+    }
+
+    fn close(&mut self, mark: Marker) {
+        match self.in_key {
+            0 => {
+                self.frames.pop();
+                self.builder.close();
+            }
+            1 => {
+                self.in_key = 0;
+                if let Some((_, key)) = self.frames.last_mut() {
+                    let pos = Position::new(mark.line().saturating_sub(1), mark.col());
+                    *key = Some(("?".into(), pos));
+                }
+            }
+            _ => self.in_key -= 1,
+        }
+    }
+}
+
+impl MarkedEventReceiver for YamlBuilder {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        match event {
+            Event::StreamStart if self.multi => self.add(Kind::Array, mark, String::new()),
+            Event::StreamEnd if self.multi => self.close(mark),
+            Event::MappingStart(_) => self.add(Kind::Object, mark, String::new()),
+            Event::SequenceStart(_) => self.add(Kind::Array, mark, String::new()),
+            Event::MappingEnd | Event::SequenceEnd => self.close(mark),
+            Event::Scalar(value, style, _, _) => {
+                let value = match style {
+                    TScalarStyle::SingleQuoted => format!("'{value}'"),
+                    TScalarStyle::DoubleQuoted => format!("{value:?}"),
+                    _ => value,
+                };
+                self.add(Kind::Scalar, mark, value);
+            }
+            Event::Alias(_) => self.add(Kind::Scalar, mark, "*alias".into()),
+            _ => {}
+        }
+    }
+}
+
+fn yaml(text: &str) -> Result<Tree, ParseError> {
+    let documents = format::load_yaml(text)?;
+    let mut receiver = YamlBuilder {
+        builder: Builder::default(),
+        frames: vec![],
+        in_key: 0,
+        multi: documents.len() > 1,
+    };
+    Parser::new(text.chars())
+        .load(&mut receiver, true)
+        .map_err(|e| ParseError {
+            message: e.to_string(),
+            pos: Position::default(),
+        })?;
+    Ok(receiver.builder.tree)
+}
+
+/// A TOML value and the bytes of its children, as the deserializer reports them.
+enum TomlValue {
+    Table(Vec<(String, toml::Spanned<TomlValue>)>),
+    Array(Vec<toml::Spanned<TomlValue>>),
+    Scalar(String),
+}
+
+impl<'de> Deserialize<'de> for TomlValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(TomlVisitor)
+    }
+}
+
+struct TomlVisitor;
+
+impl<'de> Visitor<'de> for TomlVisitor {
+    type Value = TomlValue;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a TOML value")
+    }
+
+    fn visit_bool<E>(self, v: bool) -> Result<TomlValue, E> {
+        Ok(TomlValue::Scalar(v.to_string()))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> Result<TomlValue, E> {
+        Ok(TomlValue::Scalar(v.to_string()))
+    }
+
+    fn visit_u64<E>(self, v: u64) -> Result<TomlValue, E> {
+        Ok(TomlValue::Scalar(v.to_string()))
+    }
+
+    fn visit_f64<E>(self, v: f64) -> Result<TomlValue, E> {
+        Ok(TomlValue::Scalar(v.to_string()))
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<TomlValue, E> {
+        Ok(TomlValue::Scalar(format!("{v:?}")))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<TomlValue, A::Error> {
+        let mut items = vec![];
+        while let Some(item) = seq.next_element()? {
+            items.push(item);
+        }
+        Ok(TomlValue::Array(items))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<TomlValue, A::Error> {
+        let mut entries = vec![];
+        while let Some(key) = map.next_key::<String>()? {
+            // Dates come as a map with a single made up key
+            if key == "$__toml_private_datetime" {
+                return Ok(TomlValue::Scalar(map.next_value()?));
+            }
+            entries.push((key, map.next_value()?));
+        }
+        Ok(TomlValue::Table(entries))
+    }
+}
+
+fn toml(text: &str) -> Result<Tree, ParseError> {
+    let root = toml::from_str::<TomlValue>(text).map_err(|e| ParseError {
+        message: e.message().trim().to_string(),
+        pos: format::position_of(text, e.span().map_or(0, |s| s.start)),
+    })?;
+    let lines = Lines::new(text);
+    let mut builder = Builder::default();
+
+    // Where the key of the value at `span` starts
+    let key_start = |span: Range<usize>| {
+        let before = text[..span.start].trim_end();
+        let Some(before) = before.strip_suffix('=') else {
+            return span.start;
+        };
+        let before = before.trim_end();
+        let start = before.rfind(['{', ',', '\n']).map_or(0, |i| i + 1);
+        start + before[start..].len() - before[start..].trim_start().len()
+    };
+    fn add(
+        builder: &mut Builder,
+        name: Option<String>,
+        value: &TomlValue,
+        start: Position,
+        child_start: &dyn Fn(Range<usize>) -> Position,
+    ) {
+        match value {
+            TomlValue::Scalar(value) => builder.add(name, Kind::Scalar, start, value.clone()),
+            TomlValue::Table(entries) => {
+                builder.add(name, Kind::Object, start, String::new());
+                for (key, value) in entries {
+                    let start = child_start(value.span());
+                    add(
+                        builder,
+                        Some(key.clone()),
+                        value.get_ref(),
+                        start,
+                        child_start,
+                    );
+                }
+                builder.close();
+            }
+            TomlValue::Array(items) => {
+                builder.add(name, Kind::Array, start, String::new());
+                for item in items {
+                    let start = child_start(item.span());
+                    add(builder, None, item.get_ref(), start, child_start);
+                }
+                builder.close();
+            }
+        }
+    }
+    let child_start = |span: Range<usize>| lines.position(key_start(span));
+    add(&mut builder, None, &root, Position::default(), &child_start);
+    Ok(builder.tree)
+}
+
+/// What the user did in the tree.
+pub enum TreeAction {
+    /// Selected the node starting here
+    Select(Position),
+    /// Wants to see the node starting here in the text
+    Open(Position),
+}
+
+/// A parsed document and which of its nodes are expanded and selected.
+pub struct TreeView {
+    /// Text version the tree was built from
+    version: u64,
+    tree: Result<Tree, ParseError>,
+    expanded: HashSet<usize>,
+    selected: Option<usize>,
+    /// Nodes shown, as of the last change
+    rows: Option<Vec<usize>>,
+    /// Scroll the selected node into view on the next frame
+    scroll_to_selected: bool,
+}
+
+impl TreeView {
+    pub fn new(format: Format, text: &str, version: u64) -> Self {
+        let tree = Tree::parse(format, text);
+        let expanded = match &tree {
+            Ok(tree) if !tree.nodes.is_empty() => HashSet::from([0]),
+            _ => HashSet::new(),
+        };
+        Self {
+            version,
+            tree,
+            expanded,
+            selected: None,
+            rows: None,
+            scroll_to_selected: false,
+        }
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Build the tree from `text` again, keeping the same paths expanded.
+    pub fn rebuild(&mut self, format: Format, text: &str, version: u64) {
+        let mut new = Self::new(format, text, version);
+        if let (Ok(old), Ok(tree)) = (&self.tree, &new.tree) {
+            let paths = self
+                .expanded
+                .iter()
+                .map(|n| old.json_path(*n))
+                .collect::<HashSet<_>>();
+            new.expanded = (0..tree.nodes.len())
+                .filter(|n| tree.nodes[*n].kind != Kind::Scalar)
+                .filter(|n| paths.contains(&tree.json_path(*n)))
+                .collect();
+        }
+        *self = new;
+    }
+
+    /// Lines the nodes, or the parse error, are in.
+    pub fn lines(&self) -> Vec<usize> {
+        match &self.tree {
+            Ok(tree) => tree.nodes.iter().map(|n| n.start.line).collect(),
+            Err(error) => vec![error.pos.line],
+        }
+    }
+
+    /// Select the node at `pos` and show it.
+    pub fn select_at(&mut self, pos: Position) {
+        let Ok(tree) = &self.tree else {
+            return;
+        };
+        self.selected = tree.node_at(pos);
+        if let Some(node) = self.selected {
+            self.expanded.extend(tree.ancestors(node));
+            self.rows = None;
+            self.scroll_to_selected = true;
+        }
+    }
+
+    fn set_expanded(&mut self, node: usize, expanded: bool) {
+        if expanded {
+            self.expanded.insert(node);
+        } else {
+            self.expanded.remove(&node);
+        }
+        self.rows = None;
+    }
+
+    fn rows(&mut self) -> &[usize] {
+        let (Ok(tree), expanded) = (&self.tree, &self.expanded) else {
+            return &[];
+        };
+        self.rows.get_or_insert_with(|| {
+            let mut rows = vec![];
+            let mut node = 0;
+            while node < tree.nodes.len() {
+                rows.push(node);
+                node = if expanded.contains(&node) {
+                    node + 1
+                } else {
+                    tree.nodes[node].end
+                };
+            }
+            rows
+        })
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) -> Option<TreeAction> {
+        if let Err(error) = &self.tree {
+            ui.colored_label(ui.visuals().error_fg_color, error.to_string());
+            return ui
+                .button("Go to error")
+                .clicked()
+                .then_some(TreeAction::Open(error.pos));
+        }
+
+        let mut action = None;
+        ui.horizontal(|ui| {
+            if ui.button("Expand all").clicked() {
+                if let Ok(tree) = &self.tree {
+                    self.expanded = (0..tree.nodes.len())
+                        .filter(|n| tree.nodes[*n].kind != Kind::Scalar)
+                        .collect();
+                    self.rows = None;
+                }
+            }
+            if ui.button("Collapse all").clicked() {
+                self.expanded = HashSet::from([0]);
+                self.rows = None;
+            }
+            if let (Ok(tree), Some(node)) = (&self.tree, self.selected) {
+                let path = tree.json_path(node);
+                if ui.button("Copy JSONPath").clicked() {
+                    ui.output_mut(|o| o.copied_text = path.clone());
+                }
+                ui.monospace(path);
+            }
+        });
+        ui.separator();
+
+        let row_height = ui.spacing().interact_size.y;
+        let selected_row = self
+            .selected
+            .and_then(|s| self.rows().iter().position(|r| *r == s));
+        let mut scroll_area = egui::ScrollArea::both().auto_shrink([false; 2]);
+        if let (true, Some(row)) = (std::mem::take(&mut self.scroll_to_selected), selected_row) {
+            let offset = row as f32 * (row_height + ui.spacing().item_spacing.y)
+                - ui.available_height() / 2.0;
+            scroll_area = scroll_area.vertical_scroll_offset(offset.max(0.0));
+        }
+        let rows = self.rows().to_vec();
+        let mut toggled = None;
+        scroll_area.show_rows(ui, row_height, rows.len(), |ui, visible| {
+            let Ok(tree) = &self.tree else {
+                return;
+            };
+            for &node in &rows[visible] {
+                let (key, value) = tree.label(node);
+                let n = &tree.nodes[node];
+                ui.horizontal(|ui| {
+                    ui.add_space(n.depth as f32 * ui.spacing().indent);
+                    let expanded = self.expanded.contains(&node);
+                    let toggle = if n.kind == Kind::Scalar {
+                        " "
+                    } else if expanded {
+                        "⏷"
+                    } else {
+                        "⏵"
+                    };
+                    if ui
+                        .add_enabled(
+                            n.kind != Kind::Scalar,
+                            egui::Button::new(RichText::new(toggle).monospace()).frame(false),
+                        )
+                        .clicked()
+                    {
+                        toggled = Some((node, !expanded));
+                    }
+                    let response = ui
+                        .selectable_label(
+                            self.selected == Some(node),
+                            RichText::new(key).monospace(),
+                        )
+                        .on_hover_text("Double-click to show in the text");
+                    ui.weak(RichText::new(value).monospace());
+                    if response.clicked() {
+                        self.selected = Some(node);
+                        action = Some(TreeAction::Select(n.start));
+                    }
+                    if response.double_clicked() {
+                        action = Some(TreeAction::Open(n.start));
+                    }
+                    response.context_menu(|ui| {
+                        if ui.button("Copy JSONPath").clicked() {
+                            ui.output_mut(|o| o.copied_text = tree.json_path(node));
+                            ui.close_menu();
+                        }
+                    });
+                });
+            }
+        });
+        if let Some((node, expanded)) = toggled {
+            self.set_expanded(node, expanded);
+        }
+        action
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(tree: &Tree) -> Vec<(usize, String, String)> {
+        (0..tree.nodes.len())
+            .map(|n| {
+                let (key, value) = tree.label(n);
+                (tree.nodes[n].depth, key, value)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn json_nodes() {
+        let text = "{\n  \"a\": [1, {\"b c\": null}],\n  \"d\": \"x\"\n}";
+        let tree = Tree::parse(Format::Json, text).unwrap();
+        assert_eq!(
+            keys(&tree),
+            [
+                (0, "$".into(), "{2}".into()),
+                (1, "a".into(), "[2]".into()),
+                (2, "[0]".into(), "1".into()),
+                (2, "[1]".into(), "{1}".into()),
+                (3, "b c".into(), "null".into()),
+                (1, "d".into(), "\"x\"".into()),
+            ]
+        );
+        assert_eq!(tree.json_path(4), "$.a[1]['b c']");
+        assert_eq!(tree.nodes[1].start, Position::new(1, 2));
+        assert_eq!(tree.node_at(Position::new(1, 11)), Some(3));
+        assert_eq!(tree.node_at(Position::new(2, 3)), Some(5));
+        assert_eq!(tree.nodes[1].end, 5);
+    }
+
+    #[test]
+    fn yaml_nodes() {
+        let text = "a: 1\nb:\n  - x\n  - 'y'\n";
+        let tree = Tree::parse(Format::Yaml, text).unwrap();
+        assert_eq!(
+            keys(&tree),
+            [
+                (0, "$".into(), "{2}".into()),
+                (1, "a".into(), "1".into()),
+                (1, "b".into(), "[2]".into()),
+                (2, "[0]".into(), "x".into()),
+                (2, "[1]".into(), "'y'".into()),
+            ]
+        );
+        assert_eq!(tree.nodes[2].start, Position::new(1, 0));
+        assert_eq!(tree.nodes[4].start, Position::new(3, 4));
+        assert!(Tree::parse(Format::Yaml, "a: [\n").is_err());
+    }
+
+    #[test]
+    fn toml_nodes() {
+        let text = "title = \"t\"\n\n[owner]\nname = \"n\"\nborn = 1979-05-27\n";
+        let tree = Tree::parse(Format::Toml, text).unwrap();
+        assert_eq!(
+            keys(&tree),
+            [
+                (0, "$".into(), "{2}".into()),
+                (1, "title".into(), "\"t\"".into()),
+                (1, "owner".into(), "{2}".into()),
+                (2, "name".into(), "\"n\"".into()),
+                (2, "born".into(), "1979-05-27".into()),
+            ]
+        );
+        assert_eq!(tree.nodes[1].start, Position::new(0, 0));
+        assert_eq!(tree.nodes[3].start, Position::new(3, 0));
+        assert_eq!(tree.node_at(Position::new(4, 3)), Some(4));
+    }
+}
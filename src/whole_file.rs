@@ -0,0 +1,229 @@
+//! Reading all of a large file in the background, for the commands that need its whole text.
+
+use crate::encoding::Encoding;
+use std::{
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{self, Receiver},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// Larger files are not read whole
+pub const MAX_SIZE: u64 = 256 << 20;
+
+/// Size of the blocks the file is read in, so progress can be shown
+const BLOCK: usize = 1 << 20;
+
+/// The whole text of a file being read and worked on by a background thread.
+/// Stops reading once dropped.
+pub struct WholeFile<T> {
+    result: Receiver<Result<T, String>>,
+    /// Bytes read so far
+    read: Arc<AtomicU64>,
+    len: u64,
+    cancel: Arc<AtomicBool>,
+    /// What is done with the text once it was read, like "Parsing"
+    work: &'static str,
+    error: Option<String>,
+}
+
+impl<T> Drop for WholeFile<T> {
+    fn drop(&mut self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+}
+
+impl<T: Send + 'static> WholeFile<T> {
+    /// Read the file at `path`, which is `len` bytes long, and pass its text to `f`. Fails
+    /// right away if the file is larger than [`MAX_SIZE`].
+    pub fn spawn(
+        path: &Path,
+        len: u64,
+        encoding: Encoding,
+        work: &'static str,
+        f: impl FnOnce(String) -> T + Send + 'static,
+    ) -> Self {
+        let (sender, result) = mpsc::channel();
+        let task = Self {
+            result,
+            read: Default::default(),
+            len,
+            cancel: Default::default(),
+            work,
+            error: None,
+        };
+        if len > MAX_SIZE {
+            sender
+                .send(Err(format!(
+                    "{} is larger than {} MB, too large to read whole",
+                    path.display(),
+                    MAX_SIZE >> 20
+                )))
+                .ok();
+            return task;
+        }
+        let reader = Reader {
+            path: path.into(),
+            read: task.read.clone(),
+            cancel: task.cancel.clone(),
+        };
+        std::thread::spawn(move || {
+            let result = reader
+                .read()
+                .map(|bytes| f(encoding.decode(&bytes)))
+                .map_err(|e| format!("Can't read {}: {e}", reader.path.display()));
+            sender.send(result).ok();
+        });
+        task
+    }
+
+    /// The result, once it is there. `None` after a failure, which [`WholeFile::ui`] shows.
+    pub fn poll(&mut self) -> Option<T> {
+        match self.result.try_recv().ok()? {
+            Ok(result) => Some(result),
+            Err(e) => {
+                self.error = Some(e);
+                None
+            }
+        }
+    }
+
+    /// How far reading got, or why it failed.
+    pub fn ui(&self, ui: &mut egui::Ui) {
+        if let Some(error) = &self.error {
+            ui.colored_label(ui.visuals().error_fg_color, error);
+            return;
+        }
+        let read = self.read.load(Ordering::Relaxed);
+        if read < self.len {
+            let progress = read as f32 / self.len as f32;
+            ui.add(egui::ProgressBar::new(progress).text(format!(
+                "Reading {} of {} MB",
+                read >> 20,
+                self.len >> 20
+            )));
+        } else {
+            ui.horizontal(|ui| {
+                ui.spinner();
+                ui.label(format!("{}…", self.work));
+            });
+        }
+        ui.ctx().request_repaint_after(Duration::from_millis(100));
+    }
+}
+
+/// The background half of a [`WholeFile`].
+struct Reader {
+    path: PathBuf,
+    read: Arc<AtomicU64>,
+    cancel: Arc<AtomicBool>,
+}
+
+impl Reader {
+    fn read(&self) -> std::io::Result<Vec<u8>> {
+        let mut file = File::open(&self.path)?;
+        let mut bytes = vec![];
+        let mut block = vec![0; BLOCK];
+        loop {
+            if self.cancel.load(Ordering::Relaxed) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Interrupted,
+                    "cancelled",
+                ));
+            }
+            let n = file.read(&mut block)?;
+            if n == 0 {
+                return Ok(bytes);
+            }
+            bytes.extend_from_slice(&block[..n]);
+            self.read.store(bytes.len() as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Byte offsets in the file of some lines of its whole text, to load a large file from there.
+#[derive(Default)]
+pub struct LineOffsets(Vec<(usize, u64)>);
+
+impl LineOffsets {
+    /// Offsets of `lines` of `text`, the file decoded from `encoding`.
+    pub fn new(text: &str, encoding: Encoding, lines: impl IntoIterator<Item = usize>) -> Self {
+        let mut lines = lines.into_iter().collect::<Vec<_>>();
+        lines.sort_unstable();
+        lines.dedup();
+        let mut offsets = Vec::with_capacity(lines.len());
+        let mut offset = encoding.encode("").len() as u64;
+        let mut wanted = lines.into_iter().peekable();
+        for (line, text) in text.split_inclusive('\n').enumerate() {
+            let Some(&next) = wanted.peek() else {
+                break;
+            };
+            if next == line {
+                offsets.push((line, offset));
+                wanted.next();
+            }
+            offset += encoding.encoded_len(text) as u64;
+        }
+        // The empty line after a final line break
+        if let Some(line) = wanted.next() {
+            offsets.push((line, offset));
+        }
+        Self(offsets)
+    }
+
+    pub fn get(&self, line: usize) -> Option<u64> {
+        let i = self.0.binary_search_by_key(&line, |(l, _)| *l).ok()?;
+        Some(self.0[i].1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, thread};
+
+    #[test]
+    fn read_in_background() {
+        let text = "first\nsecond\n".repeat(100_000);
+        let path = env::temp_dir().join(format!("micron-whole-file-{}", std::process::id()));
+        fs::write(&path, &text).unwrap();
+        let mut task =
+            WholeFile::spawn(&path, text.len() as u64, Encoding::Utf8, "Counting", |t| {
+                t.lines().count()
+            });
+        let mut lines = None;
+        for _ in 0..500 {
+            lines = task.poll();
+            if lines.is_some() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(2));
+        }
+        assert_eq!(lines, Some(200_000));
+        fs::remove_file(&path).ok();
+
+        let mut too_large = WholeFile::spawn(&path, MAX_SIZE + 1, Encoding::Utf8, "", |_| ());
+        assert_eq!(too_large.poll(), None);
+        assert!(too_large.error.as_deref().unwrap().contains("too large"));
+    }
+
+    #[test]
+    fn line_offsets() {
+        let text = "a\nbé\n\nc\n";
+        let offsets = LineOffsets::new(text, Encoding::Utf8, [4, 1, 3, 1]);
+        assert_eq!(offsets.get(0), None);
+        assert_eq!(offsets.get(1), Some(2));
+        assert_eq!(offsets.get(3), Some(7));
+        assert_eq!(offsets.get(4), Some(9));
+
+        // After the byte order mark, two bytes per char
+        let offsets = LineOffsets::new(text, Encoding::Utf16Le, [0, 2]);
+        assert_eq!(offsets.get(0), Some(2));
+        assert_eq!(offsets.get(2), Some(2 + 2 * 5));
+    }
+}
@@ -0,0 +1,277 @@
+//! Splitting lines into the rows they take up on screen.
+
+use crate::line_buffer::{LineBuffer, Position};
+use std::{collections::HashMap, ops::Range};
+
+/// Column wrapped lines are broken at by default
+pub const DEFAULT_WRAP_COLUMN: usize = 80;
+
+/// Where long lines are broken into more rows.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, serde::Deserialize, serde::Serialize)]
+pub enum WrapMode {
+    /// Scroll sideways instead
+    #[default]
+    Off,
+    /// At the right edge of the editor
+    Window,
+    /// After this many chars
+    Column(usize),
+}
+
+impl WrapMode {
+    /// Radio buttons for the modes, with the column of [`WrapMode::Column`].
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.radio_value(self, Self::Off, "Off");
+        ui.radio_value(self, Self::Window, "At window edge");
+        ui.horizontal(|ui| {
+            let mut column = match *self {
+                Self::Column(column) => column,
+                _ => DEFAULT_WRAP_COLUMN,
+            };
+            ui.radio_value(self, Self::Column(column), "At column");
+            if ui
+                .add(egui::DragValue::new(&mut column).clamp_range(1..=1000))
+                .changed()
+            {
+                *self = Self::Column(column);
+            }
+        });
+    }
+}
+
+/// The rows of every line of a buffer, for a given row width.
+#[derive(Default)]
+pub struct Rows {
+    /// Text version, row width in chars and split column the rows were counted for
+    key: Option<(u64, Option<usize>, usize)>,
+    /// Index of the first row of every line, and the number of rows at the end
+    starts: Vec<usize>,
+    /// Lines that take more than one row
+    wrapped: HashMap<usize, Wrapped>,
+    /// Number of lines longer than the split column
+    long_lines: usize,
+}
+
+struct Wrapped {
+    /// Column and byte the second and following rows start at
+    breaks: Vec<(usize, usize)>,
+    /// Length in chars and bytes
+    len: (usize, usize),
+    /// Split because it is too long, rather than wrapped
+    split: bool,
+}
+
+impl Rows {
+    /// Split the lines of `buffer` into rows of at most `columns` chars, or not at all with `None`.
+    /// Lines longer than `split` chars are split into rows of that length in any case.
+    /// Does nothing if neither the text nor the widths changed since the last call.
+    pub fn update(
+        &mut self,
+        buffer: &LineBuffer,
+        version: u64,
+        columns: Option<usize>,
+        split: usize,
+    ) {
+        let split = split.max(1);
+        if self.key == Some((version, columns, split)) {
+            return;
+        }
+        self.key = Some((version, columns, split));
+        self.starts.clear();
+        self.wrapped.clear();
+        self.long_lines = 0;
+        let mut row = 0;
+        for line in 0..buffer.line_count() {
+            self.starts.push(row);
+            let text = buffer.line(line);
+            let width = columns.map_or(split, |c| c.min(split));
+            // No line has more chars than bytes
+            let wrapped = (text.len() > width)
+                .then(|| {
+                    let len = (text.chars().count(), text.len());
+                    self.long_lines += usize::from(len.0 > split);
+                    let split = width == split;
+                    let breaks = if split {
+                        split_line(text, width)
+                    } else {
+                        wrap_line(text, width)
+                    };
+                    Wrapped { breaks, len, split }
+                })
+                .filter(|w| !w.breaks.is_empty());
+            row += 1;
+            if let Some(wrapped) = wrapped {
+                row += wrapped.breaks.len();
+                self.wrapped.insert(line, wrapped);
+            }
+        }
+        self.starts.push(row);
+    }
+
+    pub fn count(&self) -> usize {
+        self.starts.last().copied().unwrap_or(0)
+    }
+
+    /// How many lines are longer than the split column.
+    pub fn long_lines(&self) -> usize {
+        self.long_lines
+    }
+
+    /// The line a row belongs to, and which of its rows it is.
+    pub fn line_of(&self, row: usize) -> (usize, usize) {
+        let lines = self.starts.len().saturating_sub(1);
+        let line = self
+            .starts
+            .partition_point(|start| *start <= row)
+            .saturating_sub(1)
+            .min(lines.saturating_sub(1));
+        (line, row.saturating_sub(self.starts[line]))
+    }
+
+    /// The row the caret at `pos` is shown in. A caret right at a break starts the next row.
+    pub fn row_of(&self, pos: Position) -> usize {
+        let index = self.wrapped.get(&pos.line).map_or(0, |w| {
+            w.breaks
+                .partition_point(|(column, _)| *column <= pos.column)
+        });
+        self.starts.get(pos.line).copied().unwrap_or(0) + index
+    }
+
+    /// Columns of row `index` of `line`.
+    pub fn columns(&self, buffer: &LineBuffer, line: usize, index: usize) -> Range<usize> {
+        match self.wrapped.get(&line) {
+            Some(w) => w.row(index, |(column, _)| column, w.len.0),
+            None => 0..buffer.line_len(line),
+        }
+    }
+
+    /// The bytes of `line` shown in row `index`.
+    pub fn bytes(&self, buffer: &LineBuffer, line: usize, index: usize) -> Range<usize> {
+        match self.wrapped.get(&line) {
+            Some(w) => w.row(index, |(_, byte)| byte, w.len.1),
+            None => 0..buffer.line(line).len(),
+        }
+    }
+
+    /// Whether row `index` of `line` is its last.
+    pub fn is_last(&self, line: usize, index: usize) -> bool {
+        self.wrapped.get(&line).map_or(0, |w| w.breaks.len()) == index
+    }
+
+    /// Whether `line` is split because it is longer than the split column, rather than wrapped.
+    pub fn is_split(&self, line: usize) -> bool {
+        self.wrapped.get(&line).is_some_and(|w| w.split)
+    }
+}
+
+impl Wrapped {
+    /// Start and end of row `index`, in whatever `offset` picks from the breaks.
+    fn row(
+        &self,
+        index: usize,
+        offset: impl Fn((usize, usize)) -> usize,
+        len: usize,
+    ) -> Range<usize> {
+        let start = index
+            .checked_sub(1)
+            .and_then(|i| self.breaks.get(i))
+            .map_or(0, |b| offset(*b));
+        let end = self.breaks.get(index).map_or(len, |b| offset(*b));
+        start..end
+    }
+}
+
+/// Column and byte where the rows after the first start when `line` is wrapped at `columns`
+/// chars. Breaks after whitespace if there is some in the row, anywhere otherwise.
+pub fn wrap_line(line: &str, columns: usize) -> Vec<(usize, usize)> {
+    let columns = columns.max(1);
+    let mut breaks = vec![];
+    let mut row_start = 0;
+    // Column and byte after the last whitespace in the current row
+    let mut after_space = None;
+    for (column, (byte, c)) in line.char_indices().enumerate() {
+        if column - row_start == columns {
+            let at = after_space
+                .filter(|(a, _)| *a > row_start)
+                .unwrap_or((column, byte));
+            breaks.push(at);
+            row_start = at.0;
+            after_space = None;
+        }
+        if c.is_whitespace() {
+            after_space = Some((column + 1, byte + c.len_utf8()));
+        }
+    }
+    breaks
+}
+
+/// Column and byte of every `columns`th char after the first, where a too long line is split.
+pub fn split_line(line: &str, columns: usize) -> Vec<(usize, usize)> {
+    let columns = columns.max(1);
+    if line.is_ascii() {
+        return (columns..line.len())
+            .step_by(columns)
+            .map(|i| (i, i))
+            .collect();
+    }
+    line.char_indices()
+        .enumerate()
+        .skip(columns)
+        .step_by(columns)
+        .map(|(column, (byte, _))| (column, byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn columns(breaks: Vec<(usize, usize)>) -> Vec<usize> {
+        breaks.into_iter().map(|(column, _)| column).collect()
+    }
+
+    #[test]
+    fn wraps_after_spaces() {
+        assert_eq!(columns(wrap_line("aaa bbb ccc", 5)), [4, 8]);
+        assert_eq!(columns(wrap_line("aaaaaaaaaa", 4)), [4, 8]);
+        assert_eq!(columns(wrap_line("aaaa", 4)), [] as [usize; 0]);
+        assert_eq!(columns(wrap_line("ab cdefghij", 4)), [3, 7]);
+        assert_eq!(wrap_line("ä öü", 2), [(2, 3)]);
+    }
+
+    #[test]
+    fn splits_long_lines() {
+        assert_eq!(split_line("aaaaaaa", 3), [(3, 3), (6, 6)]);
+        assert_eq!(split_line("äää", 3), []);
+        assert_eq!(split_line("äaäaä", 2), [(2, 3), (4, 6)]);
+    }
+
+    #[test]
+    fn rows_of_lines() {
+        let buffer = LineBuffer::new("short\naaa bbb ccc\n\nxy");
+        let mut rows = Rows::default();
+        rows.update(&buffer, 1, Some(5), 100);
+        assert_eq!(rows.count(), 6);
+        assert_eq!(rows.line_of(0), (0, 0));
+        assert_eq!(rows.line_of(3), (1, 2));
+        assert_eq!(rows.line_of(5), (3, 0));
+        assert_eq!(rows.columns(&buffer, 1, 1), 4..8);
+        assert_eq!(rows.columns(&buffer, 1, 2), 8..11);
+        assert!(rows.is_last(1, 2));
+        assert_eq!(rows.row_of(Position::new(1, 3)), 1);
+        assert_eq!(rows.row_of(Position::new(1, 4)), 2);
+        assert_eq!(rows.row_of(Position::new(3, 1)), 5);
+        assert_eq!(rows.long_lines(), 0);
+
+        rows.update(&buffer, 1, None, 100);
+        assert_eq!(rows.count(), 4);
+        assert_eq!(rows.columns(&buffer, 1, 0), 0..11);
+
+        // Split at the limit even though wrapping is off
+        rows.update(&buffer, 1, None, 4);
+        assert_eq!(rows.count(), 7);
+        assert!(rows.is_split(1) && !rows.is_split(3));
+        assert_eq!(rows.long_lines(), 2);
+        assert_eq!(rows.bytes(&buffer, 1, 2), 8..11);
+    }
+}